@@ -0,0 +1,98 @@
+//! Terminal client for `tetris --spectate-port`; the frame format is documented on
+//! `spectate::snapshot`.
+
+use std::env;
+use std::io::{self, Read};
+use std::net::TcpStream;
+
+
+const GLYPHS: [char; 9] = ['.', 'R', 'O', 'Y', 'G', 'B', 'D', 'P', 'X'];
+
+
+fn read_frame(stream: &mut TcpStream) -> io::Result<Vec<u8>> {
+    let mut length = [0; 4];
+    stream.read_exact(&mut length)?;
+    let mut payload = vec![0; u32::from_be_bytes(length) as usize];
+    stream.read_exact(&mut payload)?;
+    Ok(payload)
+}
+
+
+fn u16_at(payload: &[u8], offset: usize) -> usize {
+    u16::from_be_bytes([payload[offset], payload[offset + 1]]) as usize
+}
+
+
+/// One decoded frame: the falling figure is its kind index and column and row, and every cell
+/// is 0 when empty or a color index plus one.
+#[derive(Debug)]
+pub struct Frame {
+    pub columns: usize,
+    pub lines: usize,
+    pub lines_cleared: u32,
+    pub figure: Option<(u8, usize, usize)>,
+    pub cells: Vec<u8>,
+}
+
+
+pub fn decode(payload: &[u8]) -> Option<Frame> {
+    if payload.len() < 13 {
+        return None;
+    }
+    let (columns, lines) = (u16_at(payload, 0), u16_at(payload, 2));
+    let lines_cleared = u32::from_be_bytes([payload[4], payload[5], payload[6], payload[7]]);
+    let figure = match payload[8] {
+        0xff => None,
+        kind => Some((kind, u16_at(payload, 9), u16_at(payload, 11))),
+    };
+
+    let mut cells = Vec::with_capacity(columns * lines);
+    for pair in payload[13 ..].chunks(2) {
+        if pair.len() < 2 || pair[1] as usize >= GLYPHS.len() {
+            return None;
+        }
+        cells.extend(std::iter::repeat_n(pair[1], pair[0] as usize));
+    }
+    if cells.len() != columns * lines {
+        return None;
+    }
+    Some(Frame { columns, lines, lines_cleared, figure, cells })
+}
+
+
+fn render(frame: &Frame) -> String {
+    let figure = match frame.figure {
+        Some((kind, x, y)) => format!("#{} at {},{}", kind, x, y),
+        None => "none".to_string(),
+    };
+    let mut screen = format!("\x1b[H\x1b[2Jlines: {}  figure: {}\n", frame.lines_cleared, figure);
+    for row in frame.cells.chunks(frame.columns) {
+        screen.extend(row.iter().map(|&cell| GLYPHS[cell as usize]));
+        screen.push('\n');
+    }
+    screen
+}
+
+
+fn main() {
+    let address = env::args().nth(1).unwrap_or_else(|| "127.0.0.1:7777".to_string());
+    let mut stream = match TcpStream::connect(&address) {
+        Ok(stream) => stream,
+        Err(e) => {
+            println!("Can not connect to {}: {}", address, e);
+            std::process::exit(1);
+        },
+    };
+    loop {
+        match read_frame(&mut stream) {
+            Ok(payload) => match decode(&payload) {
+                Some(frame) => print!("{}", render(&frame)),
+                None => println!("Malformed frame of {} bytes", payload.len()),
+            },
+            Err(e) => {
+                println!("Disconnected: {}", e);
+                return;
+            },
+        }
+    }
+}
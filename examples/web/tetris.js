@@ -0,0 +1,77 @@
+// Glue between the page and tetris.wasm: held keys become the input bits of `tick`, and the cells
+// of the board are painted onto the canvas every animation frame.
+
+const CELL_SIZE = 24;
+
+// The bits of `INPUT_EVENTS` in src/web.rs.
+const KEY_BITS = {
+  ArrowLeft: 1 << 0,
+  ArrowRight: 1 << 1,
+  ArrowUp: 1 << 2,
+  KeyA: 1 << 3,
+  ArrowDown: 1 << 4,
+  Space: 1 << 4,
+  KeyC: 1 << 5,
+};
+
+const heldKeys = new Set();
+
+function inputBits() {
+  let bits = 0;
+  for (const code of heldKeys) {
+    bits |= KEY_BITS[code];
+  }
+  return bits;
+}
+
+document.addEventListener('keydown', event => {
+  if (event.code in KEY_BITS) {
+    heldKeys.add(event.code);
+    event.preventDefault();
+  }
+});
+
+document.addEventListener('keyup', event => heldKeys.delete(event.code));
+
+WebAssembly.instantiateStreaming(fetch('tetris.wasm')).then(({ instance }) => {
+  const wasm = instance.exports;
+  wasm.start(Math.floor(Math.random() * 0x100000000));
+  const columns = wasm.columns();
+  const lines = wasm.lines();
+
+  const canvas = document.getElementById('board');
+  canvas.width = columns * CELL_SIZE;
+  canvas.height = lines * CELL_SIZE;
+  const context = canvas.getContext('2d');
+  const status = document.getElementById('status');
+
+  let lastFrameMs = performance.now();
+
+  function frame(nowMs) {
+    const dtMs = Math.max(0, Math.round(nowMs - lastFrameMs));
+    lastFrameMs += dtMs;
+    const running = wasm.tick(inputBits(), dtMs);
+
+    // The views are made anew every frame, since memory growth detaches the old buffer.
+    const cells = new Uint8Array(wasm.memory.buffer, wasm.cells(), columns * lines);
+    const palette = new Uint8Array(wasm.memory.buffer, wasm.palette(), 8 * 3);
+    context.clearRect(0, 0, canvas.width, canvas.height);
+    cells.forEach((cell, index) => {
+      if (cell === 0) {
+        return;
+      }
+      const [r, g, b] = palette.subarray((cell - 1) * 3, cell * 3);
+      context.fillStyle = `rgb(${r}, ${g}, ${b})`;
+      context.fillRect((index % columns) * CELL_SIZE + 1, Math.floor(index / columns) * CELL_SIZE + 1,
+                       CELL_SIZE - 2, CELL_SIZE - 2);
+    });
+
+    if (running) {
+      requestAnimationFrame(frame);
+    } else {
+      status.textContent = `Game over, ${wasm.lines_cleared()} lines`;
+    }
+  }
+
+  requestAnimationFrame(frame);
+});
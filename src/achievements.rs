@@ -0,0 +1,203 @@
+use std::fs::File;
+use std::io::{self, BufRead, BufReader, BufWriter, Write};
+use std::path::{Path, PathBuf};
+
+use strings::Strings;
+use super::{GameEvent, GameMode};
+
+
+const ACHIEVEMENTS_HEADER: &str = "tetris-achievements 1";
+const TOTAL_LINES_GOAL: usize = 100;
+const LEVEL_GOAL: usize = 10;
+const ENDLESS_SURVIVAL_MS: u64 = 5 * 60 * 1000;
+
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Achievement {
+    FirstTetris,
+    HundredLines,
+    LevelTen,
+    PerfectClear,
+    EndlessSurvivor,
+}
+
+
+const ACHIEVEMENTS: [Achievement; 5] = [
+    Achievement::FirstTetris,
+    Achievement::HundredLines,
+    Achievement::LevelTen,
+    Achievement::PerfectClear,
+    Achievement::EndlessSurvivor,
+    ];
+
+
+impl Achievement {
+    fn id(&self) -> &'static str {
+        match *self {
+            Achievement::FirstTetris => "first-tetris",
+            Achievement::HundredLines => "hundred-lines",
+            Achievement::LevelTen => "level-ten",
+            Achievement::PerfectClear => "perfect-clear",
+            Achievement::EndlessSurvivor => "endless-survivor",
+        }
+    }
+
+    pub fn title(&self, strings: &Strings) -> &'static str {
+        strings.get(match *self {
+            Achievement::FirstTetris => "achievement_first_tetris",
+            Achievement::HundredLines => "achievement_hundred_lines",
+            Achievement::LevelTen => "achievement_level_ten",
+            Achievement::PerfectClear => "achievement_perfect_clear",
+            Achievement::EndlessSurvivor => "achievement_endless_survivor",
+        })
+    }
+
+    fn from_id(id: &str) -> Option<Achievement> {
+        ACHIEVEMENTS.iter().cloned().find(|achievement| achievement.id() == id)
+    }
+
+    fn is_earned_by(&self, event: GameEvent, total_lines: usize) -> bool {
+        match (self, event) {
+            (&Achievement::FirstTetris, GameEvent::LinesCleared(count)) => count >= 4,
+            (&Achievement::HundredLines, GameEvent::LinesCleared(_)) =>
+                total_lines >= TOTAL_LINES_GOAL,
+            (&Achievement::LevelTen, GameEvent::LevelUp(level)) => level >= LEVEL_GOAL,
+            (&Achievement::PerfectClear, GameEvent::PerfectClear) => true,
+            (&Achievement::EndlessSurvivor, GameEvent::TimePlayed(GameMode::Endless, time_ms)) =>
+                time_ms >= ENDLESS_SURVIVAL_MS,
+            _ => false,
+        }
+    }
+}
+
+
+fn invalid_data(message: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
+
+
+pub struct Achievements {
+    path: PathBuf,
+    total_lines: usize,
+    unlocked: Vec<Achievement>,
+}
+
+
+impl Achievements {
+    pub fn load(path: PathBuf) -> io::Result<Self> {
+        let mut achievements = Achievements {
+            path,
+            total_lines: 0,
+            unlocked: Vec::new(),
+        };
+        let file = match File::open(&achievements.path) {
+            Ok(file) => file,
+            Err(ref e) if e.kind() == io::ErrorKind::NotFound => return Ok(achievements),
+            Err(e) => return Err(e),
+        };
+        let mut lines = BufReader::new(file).lines();
+
+        match lines.next() {
+            Some(Ok(ref header)) if header == ACHIEVEMENTS_HEADER => {},
+            Some(Err(e)) => return Err(e),
+            _ => return Err(invalid_data(format!(
+                "{} is not an achievements file", achievements.path.display()))),
+        }
+
+        achievements.total_lines = match lines.next() {
+            Some(line) => line?.trim().parse()
+                .map_err(|_| invalid_data("malformed total line count".to_string()))?,
+            None => return Err(invalid_data("achievements have no total line count".to_string())),
+        };
+
+        for line in lines {
+            let line = line?;
+            match Achievement::from_id(line.trim()) {
+                Some(achievement) => achievements.unlocked.push(achievement),
+                None => return Err(invalid_data(format!("unknown achievement {:?}", line))),
+            }
+        }
+        Ok(achievements)
+    }
+
+    pub fn save(&self) -> io::Result<()> {
+        let mut out = BufWriter::new(File::create(&self.path)?);
+        writeln!(out, "{}", ACHIEVEMENTS_HEADER)?;
+        writeln!(out, "{}", self.total_lines)?;
+        for achievement in self.unlocked.iter() {
+            writeln!(out, "{}", achievement.id())?;
+        }
+        out.flush()
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    pub fn handle(&mut self, event: GameEvent) -> Vec<Achievement> {
+        if let GameEvent::LinesCleared(count) = event {
+            self.total_lines += count;
+        }
+        let earned = ACHIEVEMENTS.iter().cloned()
+            .filter(|achievement| ! self.unlocked.contains(achievement))
+            .filter(|achievement| achievement.is_earned_by(event, self.total_lines))
+            .collect::<Vec<_>>();
+        self.unlocked.extend(earned.iter().cloned());
+        earned
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use std::env;
+    use std::fs;
+
+    use super::*;
+
+    fn temp_path(name: &str) -> PathBuf {
+        env::temp_dir().join(format!("tetris-achievements-{}-{}", name, std::process::id()))
+    }
+
+    #[test]
+    fn achievements_unlock_exactly_once() {
+        let mut achievements = Achievements::load(temp_path("missing")).unwrap();
+        let events = [
+            (GameEvent::LinesCleared(2), vec![]),
+            (GameEvent::LinesCleared(4), vec![Achievement::FirstTetris]),
+            (GameEvent::LinesCleared(4), vec![]),
+            (GameEvent::LevelUp(9), vec![]),
+            (GameEvent::LevelUp(10), vec![Achievement::LevelTen]),
+            (GameEvent::PerfectClear, vec![Achievement::PerfectClear]),
+            (GameEvent::PerfectClear, vec![]),
+            (GameEvent::TimePlayed(GameMode::Sprint, ENDLESS_SURVIVAL_MS), vec![]),
+            (GameEvent::TimePlayed(GameMode::Endless, ENDLESS_SURVIVAL_MS - 1), vec![]),
+            (GameEvent::TimePlayed(GameMode::Endless, ENDLESS_SURVIVAL_MS),
+             vec![Achievement::EndlessSurvivor]),
+        ];
+        for &(event, ref expected) in events.iter() {
+            assert_eq!(&achievements.handle(event), expected, "{:?}", event);
+        }
+
+        for _ in 0 .. 29 {
+            assert_eq!(achievements.handle(GameEvent::LinesCleared(3)), vec![]);
+        }
+        assert_eq!(achievements.total_lines, 97);
+        assert_eq!(achievements.handle(GameEvent::LinesCleared(3)), vec![Achievement::HundredLines]);
+        assert_eq!(achievements.handle(GameEvent::LinesCleared(4)), vec![]);
+    }
+
+    #[test]
+    fn achievements_survive_a_save_and_load() {
+        let path = temp_path("saved");
+        let mut achievements = Achievements::load(path.clone()).unwrap();
+        achievements.handle(GameEvent::LinesCleared(4));
+        achievements.save().unwrap();
+
+        let mut loaded = Achievements::load(path.clone()).unwrap();
+        let _ = fs::remove_file(&path);
+        assert_eq!(loaded.total_lines, 4);
+        assert_eq!(loaded.unlocked, vec![Achievement::FirstTetris]);
+        assert_eq!(loaded.handle(GameEvent::LinesCleared(4)), vec![]);
+    }
+}
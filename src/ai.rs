@@ -0,0 +1,192 @@
+use rand::{Rng, XorShiftRng};
+
+use super::{CellScreen, Point, TetrisCellColor, TetrisCellScreen};
+
+
+const DANGER_HEIGHT_RATIO: f64 = 0.8;
+
+pub const POLICY_NAMES: [&str; 2] = ["heuristic", "random"];
+
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct EvalWeights {
+    pub height: f64,
+    pub lines: f64,
+    pub holes: f64,
+    pub bumpiness: f64,
+    pub row_transitions: f64,
+    pub column_transitions: f64,
+    pub danger: f64,
+}
+
+
+impl Default for EvalWeights {
+    fn default() -> Self {
+        EvalWeights {
+            height: -0.51,
+            lines: 0.76,
+            holes: -0.36,
+            bumpiness: -0.18,
+            row_transitions: 0.0,
+            column_transitions: 0.0,
+            danger: -10.0,
+        }
+    }
+}
+
+
+impl EvalWeights {
+    pub fn map<F: FnMut(f64) -> f64>(&self, mut f: F) -> Self {
+        EvalWeights {
+            height: f(self.height),
+            lines: f(self.lines),
+            holes: f(self.holes),
+            bumpiness: f(self.bumpiness),
+            row_transitions: f(self.row_transitions),
+            column_transitions: f(self.column_transitions),
+            danger: f(self.danger),
+        }
+    }
+
+    pub fn to_json(self) -> String {
+        format!("{{\"height\": {}, \"lines\": {}, \"holes\": {}, \"bumpiness\": {}, \
+                 \"row_transitions\": {}, \"column_transitions\": {}, \"danger\": {}}}",
+                self.height, self.lines, self.holes, self.bumpiness, self.row_transitions,
+                self.column_transitions, self.danger)
+    }
+}
+
+
+fn evaluate(screen: &TetrisCellScreen, lines_cleared: usize, weights: &EvalWeights) -> f64 {
+    let heights = screen.column_heights();
+    let aggregate_height: u32 = heights.iter().sum();
+    let bumpiness: u32 = heights.windows(2)
+        .map(|pair| (pair[0] as i32 - pair[1] as i32).unsigned_abs())
+        .sum();
+    let danger_height = screen.dimensions().1 as f64 * DANGER_HEIGHT_RATIO;
+    let max_height = heights.iter().max().cloned().unwrap_or(0) as f64;
+    weights.height * aggregate_height as f64
+        + weights.lines * lines_cleared as f64
+        + weights.holes * screen.hole_count() as f64
+        + weights.bumpiness * bumpiness as f64
+        + weights.row_transitions * screen.aggregate_transitions() as f64
+        + weights.column_transitions * screen.vertical_transitions() as f64
+        + weights.danger * (max_height - danger_height).max(0.0)
+}
+
+
+pub struct Player {
+    scratch: TetrisCellScreen,
+    weights: EvalWeights,
+}
+
+
+impl Player {
+    pub fn new(screen: &TetrisCellScreen) -> Self {
+        Player::with_weights(screen, EvalWeights::default())
+    }
+
+    pub fn with_weights(screen: &TetrisCellScreen, weights: EvalWeights) -> Self {
+        let scratch = TetrisCellScreen::from_bitboard(
+            &screen.cells_as_bitboard(), screen.dimensions(), TetrisCellColor::Gray)
+            .expect("the bitboard of a board fits its dimensions");
+        Player { scratch, weights }
+    }
+
+    pub fn best_placement(&mut self, screen: &TetrisCellScreen) -> Option<(usize, u8)> {
+        let (Point(_, y), color, figure) = screen.get_figure()?;
+        let columns = screen.dimensions().0;
+        let mut best = None;
+        // Symmetric figures land the same way in several rotations, so a column scores each board
+        // once.
+        let mut scored_boards: Vec<Vec<Vec<u64>>> = vec![Vec::new(); columns];
+        let mut rotated = figure;
+        for rotation in 0 .. 4 {
+            for (col, scored_boards) in scored_boards.iter_mut().enumerate() {
+                if screen.figure_overlaps(Point(col, y), &rotated) {
+                    continue;
+                }
+                let landing_y = (y ..)
+                    .take_while(|&y| ! screen.figure_overlaps(Point(col, y), &rotated))
+                    .last().unwrap();
+
+                screen.copy_cells_into(&mut self.scratch.cells);
+                for cell in rotated.cells_iter(Point(col, landing_y)) {
+                    self.scratch.set_cell(cell, Some(color));
+                }
+                if scored_boards.iter().any(|board| self.scratch.bitboard_eq(board)) {
+                    continue;
+                }
+                scored_boards.push(self.scratch.cells_as_bitboard());
+                let filled_lines = self.scratch.filled_lines();
+                let score = if filled_lines.is_empty() {
+                    evaluate(&self.scratch, 0, &self.weights)
+                } else {
+                    evaluate(&self.scratch.compaction_preview(&filled_lines), filled_lines.len(),
+                             &self.weights)
+                };
+
+                match best {
+                    Some((best_score, _)) if best_score >= score => {},
+                    _ => best = Some((score, (col, rotation))),
+                }
+            }
+            rotated = rotated.rotate_clockwise().1;
+        }
+        best.map(|(_, placement)| placement)
+    }
+}
+
+
+pub trait Policy {
+    fn best_placement(&mut self, screen: &TetrisCellScreen) -> Option<(usize, u8)>;
+}
+
+
+pub fn policy(name: &str, screen: &TetrisCellScreen, rng: XorShiftRng) -> Option<Box<dyn Policy>> {
+    match name {
+        "heuristic" => Some(Box::new(Player::new(screen))),
+        "random" => Some(Box::new(RandomPlayer { rng })),
+        _ => None,
+    }
+}
+
+
+fn placements(screen: &TetrisCellScreen) -> Vec<(usize, u8)> {
+    let (Point(_, y), _, figure) = match screen.get_figure() {
+        Some(figure) => figure,
+        None => return Vec::new(),
+    };
+    let columns = screen.dimensions().0;
+    let mut result = Vec::new();
+    let mut rotated = figure;
+    for rotation in 0 .. 4 {
+        for col in 0 .. columns {
+            if ! screen.figure_overlaps(Point(col, y), &rotated) {
+                result.push((col, rotation));
+            }
+        }
+        rotated = rotated.rotate_clockwise().1;
+    }
+    result
+}
+
+
+impl Policy for Player {
+    fn best_placement(&mut self, screen: &TetrisCellScreen) -> Option<(usize, u8)> {
+        Player::best_placement(self, screen)
+    }
+}
+
+
+pub struct RandomPlayer {
+    rng: XorShiftRng,
+}
+
+
+impl Policy for RandomPlayer {
+    fn best_placement(&mut self, screen: &TetrisCellScreen) -> Option<(usize, u8)> {
+        let placements = placements(screen);
+        self.rng.choose(&placements).cloned()
+    }
+}
@@ -0,0 +1,61 @@
+use time;
+
+use ai;
+use super::{FIGURE_KINDS, GameConfig, TetrisGame, seeded_rng};
+
+
+const MAX_FIGURES_PER_GAME: usize = 10_000;
+const SIMULATIONS: usize = 100_000;
+
+
+pub fn run(seed: u32, games: usize, config: &GameConfig) {
+    let mut figures = 0;
+    let mut lines = 0;
+    let started_ns = time::precise_time_ns();
+
+    for game_no in 0 .. games {
+        let mut game = TetrisGame::new(seeded_rng(seed.wrapping_add(game_no as u32)), config.clone());
+        let mut player = ai::Player::new(&game.cell_screen);
+        for _ in 0 .. MAX_FIGURES_PER_GAME {
+            let running = match player.best_placement(&game.cell_screen) {
+                Some((col, rotation)) => game.apply_placement(col, rotation),
+                None => false,
+            };
+            figures += 1;
+            if ! running {
+                break;
+            }
+        }
+        lines += game.lines_cleared;
+    }
+
+    let seconds = (time::precise_time_ns() - started_ns) as f64 / 1e9;
+    println!("{} games, {} figures, {} lines in {:.2} s: {:.0} figures/s, {:.0} lines/s",
+             games, figures, lines, seconds, figures as f64 / seconds, lines as f64 / seconds);
+
+    simulate_placements(seed, config);
+}
+
+
+fn simulate_placements(seed: u32, config: &GameConfig) {
+    let config = GameConfig { columns: 10, lines: 20, .. config.clone() };
+    let game = TetrisGame::new(seeded_rng(seed), config);
+    let mut simulations = 0;
+    let mut valid = 0;
+    let started_ns = time::precise_time_ns();
+
+    while simulations < SIMULATIONS {
+        for figure in FIGURE_KINDS.iter().flat_map(|kind| kind.rotations()) {
+            for col in 0 .. 10 {
+                if game.simulate_placement(figure.clone(), col, 0).is_some() {
+                    valid += 1;
+                }
+                simulations += 1;
+            }
+        }
+    }
+
+    let seconds = (time::precise_time_ns() - started_ns) as f64 / 1e9;
+    println!("{} placement simulations on a 10x20 board ({} valid) in {:.2} s: {:.0} simulations/s",
+             simulations, valid, seconds, simulations as f64 / seconds);
+}
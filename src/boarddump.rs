@@ -0,0 +1,97 @@
+use super::{BoardState, CellScreen, Dimensions, FIGURE_KINDS, Figure, Point, TetrisCellColor,
+            TetrisCellScreen};
+
+
+pub struct BoardDump {
+    pub board: BoardState,
+    pub figure: Option<(Figure, Point)>,
+}
+
+
+pub fn short_hash(screen: &TetrisCellScreen) -> String {
+    let hash = screen.board_hash();
+    format!("{:08x}", (hash ^ hash >> 32) as u32)
+}
+
+
+fn figure_line(screen: &TetrisCellScreen) -> String {
+    match screen.get_figure() {
+        Some((Point(x, y), _, figure)) =>
+            format!("{} {} {} {}", figure.kind.name(), figure.rotation, x, y),
+        None => "none".to_string(),
+    }
+}
+
+
+pub fn summary(screen: &TetrisCellScreen) -> String {
+    format!("{} {}", short_hash(screen), figure_line(screen))
+}
+
+
+pub fn dump(screen: &TetrisCellScreen) -> String {
+    let Dimensions(columns, lines) = screen.dimensions();
+    format!("board {} {} {}\nfigure {}\n{}",
+            short_hash(screen), columns, lines, figure_line(screen), screen.to_compact_string())
+}
+
+
+fn color_from_symbol(symbol: char) -> Option<TetrisCellColor> {
+    (0 ..).map_while(TetrisCellColor::from_index).find(|color| color.symbol() == symbol)
+}
+
+
+fn parse_figure(words: &[&str]) -> Result<Option<(Figure, Point)>, String> {
+    if words == ["none"] {
+        return Ok(None);
+    }
+    let numbers = words.get(1 ..).unwrap_or(&[]).iter()
+        .map(|word| word.parse::<usize>())
+        .collect::<Result<Vec<_>, _>>();
+    let kind = FIGURE_KINDS.iter().find(|kind| Some(&kind.name()) == words.first());
+    match (kind, numbers) {
+        (Some(&kind), Ok(ref n)) if n.len() == 3 && n[0] < kind.rotation_count() as usize =>
+            Ok(Some((Figure::new(kind, n[0] as u8), Point(n[1], n[2])))),
+        _ => Err(format!("malformed figure {:?}", words.join(" "))),
+    }
+}
+
+
+pub fn parse(text: &str) -> Result<BoardDump, String> {
+    let mut lines = text.lines();
+    let header: Vec<&str> = lines.next().unwrap_or("").split_whitespace().collect();
+    let dim = match (header.first(), header.get(2).and_then(|w| w.parse().ok()),
+                     header.get(3).and_then(|w| w.parse().ok())) {
+        (Some(&"board"), Some(columns), Some(lines)) if header.len() == 4 => Dimensions(columns, lines),
+        _ => return Err("expected \"board <hash> <columns> <lines>\"".to_string()),
+    };
+    let figure = match lines.next().map(|line| line.split_whitespace().collect::<Vec<_>>()) {
+        Some(ref words) if words.first() == Some(&"figure") => parse_figure(&words[1 ..])?,
+        _ => return Err("expected \"figure <shape> <rotation> <x> <y>\" or \"figure none\"".to_string()),
+    };
+
+    let mut cells = Vec::with_capacity(dim.0 * dim.1);
+    for (line_no, line) in lines.by_ref().take(dim.1).enumerate() {
+        if line.chars().count() != dim.0 {
+            return Err(format!("board line {} has {} cells instead of {}",
+                               line_no + 1, line.chars().count(), dim.0));
+        }
+        for symbol in line.chars() {
+            cells.push(match symbol {
+                '.' => None,
+                symbol => match color_from_symbol(symbol) {
+                    Some(color) => Some(color),
+                    None => return Err(format!("unknown cell {:?} on board line {}",
+                                               symbol, line_no + 1)),
+                },
+            });
+        }
+    }
+    if cells.len() != dim.0 * dim.1 {
+        return Err(format!("expected {} board lines", dim.1));
+    }
+
+    Ok(BoardDump {
+        board: BoardState { cells, dim },
+        figure,
+    })
+}
@@ -0,0 +1,56 @@
+//! Draw calls that return SDL errors. The `Renderer` methods of this sdl2 version panic on them
+//! instead, and a lost GPU context then looks the same as a bug in the game.
+
+use std::os::raw::c_int;
+
+use sdl2;
+use sdl2::pixels::Color;
+use sdl2::rect::Rect;
+use sdl2::render::{BlendMode, Renderer};
+use sdl2_sys::render as ll;
+
+
+fn check(status: c_int, action: &str) -> Result<(), String> {
+    if status == 0 {
+        Ok(())
+    } else {
+        Err(format!("can not {}: {}", action, sdl2::get_error().0))
+    }
+}
+
+
+pub trait TryDraw {
+    fn try_set_draw_color(&mut self, color: Color) -> Result<(), String>;
+    fn try_set_blend_mode(&mut self, blend: BlendMode) -> Result<(), String>;
+    fn try_clear(&mut self) -> Result<(), String>;
+    fn try_fill_rect(&mut self, rect: Rect) -> Result<(), String>;
+    fn try_draw_rect(&mut self, rect: Rect) -> Result<(), String>;
+}
+
+
+impl<'a> TryDraw for Renderer<'a> {
+    fn try_set_draw_color(&mut self, color: Color) -> Result<(), String> {
+        let (r, g, b, a) = match color {
+            Color::RGB(r, g, b) => (r, g, b, 255),
+            Color::RGBA(r, g, b, a) => (r, g, b, a),
+        };
+        check(unsafe { ll::SDL_SetRenderDrawColor(self.raw(), r, g, b, a) }, "set the draw color")
+    }
+
+    fn try_set_blend_mode(&mut self, blend: BlendMode) -> Result<(), String> {
+        check(unsafe { ll::SDL_SetRenderDrawBlendMode(self.raw(), blend as ll::SDL_BlendMode) },
+              "set the blend mode")
+    }
+
+    fn try_clear(&mut self) -> Result<(), String> {
+        check(unsafe { ll::SDL_RenderClear(self.raw()) }, "clear the window")
+    }
+
+    fn try_fill_rect(&mut self, rect: Rect) -> Result<(), String> {
+        check(unsafe { ll::SDL_RenderFillRect(self.raw(), rect.raw()) }, "fill a rectangle")
+    }
+
+    fn try_draw_rect(&mut self, rect: Rect) -> Result<(), String> {
+        check(unsafe { ll::SDL_RenderDrawRect(self.raw(), rect.raw()) }, "draw a rectangle")
+    }
+}
@@ -0,0 +1,93 @@
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::path::Path;
+
+use replay;
+use super::{GameError, GameInputEvent};
+
+
+pub struct LogEntry {
+    pub frame: u64,
+    pub event: GameInputEvent,
+    pub lines_cleared_after: u32,
+    pub soft_drop_factor: f64,
+}
+
+
+pub struct GameEventLog {
+    pub events: Vec<LogEntry>,
+}
+
+
+impl GameEventLog {
+    pub fn new() -> Self {
+        GameEventLog { events: Vec::new() }
+    }
+
+    pub fn record(&mut self, event: GameInputEvent, lines_cleared: usize, soft_drop_factor: f64) {
+        let frame = self.events.len() as u64;
+        self.events.push(LogEntry {
+            frame,
+            event,
+            lines_cleared_after: lines_cleared as u32,
+            soft_drop_factor,
+        });
+    }
+
+    pub fn export_csv(&self, path: &Path) -> Result<(), GameError> {
+        let write = || {
+            let mut out = BufWriter::new(File::create(path)?);
+            writeln!(out, "frame,event,lines_cleared,soft_drop_factor")?;
+            for entry in &self.events {
+                writeln!(out, "{},{},{},{}",
+                         entry.frame, replay::event_name(entry.event), entry.lines_cleared_after,
+                         entry.soft_drop_factor)?;
+            }
+            out.flush()
+        };
+        write().map_err(|e| GameError::EventLogExport(e.kind()))
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use std::env;
+    use std::fs;
+
+    use super::*;
+
+    const EVENTS: [GameInputEvent; 4] = [
+        GameInputEvent::MoveLeft,
+        GameInputEvent::RotateClockwise,
+        GameInputEvent::SoftDrop,
+        GameInputEvent::Timer,
+    ];
+
+    #[test]
+    fn exported_log_reads_back_frame_by_frame() {
+        let mut log = GameEventLog::new();
+        for frame in 0 .. 50 {
+            log.record(EVENTS[frame % EVENTS.len()], frame / 10, 1.0 + frame as f64 / 8.0);
+        }
+        assert_eq!(log.events.len(), 50);
+
+        let path = env::temp_dir().join(format!("tetris-event-log-{}.csv", std::process::id()));
+        log.export_csv(&path).unwrap();
+        let written = fs::read_to_string(&path).unwrap();
+        let _ = fs::remove_file(&path);
+
+        let mut lines = written.lines();
+        assert_eq!(lines.next(), Some("frame,event,lines_cleared,soft_drop_factor"));
+        let rows = lines.map(|line| line.split(',').collect::<Vec<_>>()).collect::<Vec<_>>();
+        assert_eq!(rows.len(), log.events.len());
+        for (row, entry) in rows.iter().zip(&log.events) {
+            assert_eq!(row[0].parse::<u64>().unwrap(), entry.frame);
+            assert_eq!(replay::event_from_name(row[1]), Some(entry.event));
+            assert_eq!(row[2].parse::<u32>().unwrap(), entry.lines_cleared_after);
+            assert_eq!(row[3].parse::<f64>().unwrap(), entry.soft_drop_factor);
+        }
+        assert_eq!(log.events[49].frame, 49);
+        assert_eq!(log.events[49].lines_cleared_after, 4);
+    }
+}
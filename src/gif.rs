@@ -0,0 +1,172 @@
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::path::PathBuf;
+
+use raster::{Frame, PALETTE_SIZE};
+
+
+const MIN_CODE_SIZE: u8 = 4;
+const MAX_CODE: u16 = 4096;
+const MAX_RECORDING_BYTES: usize = 64 * 1024 * 1024;
+const LAST_FRAME_DELAY_CS: u64 = 200;
+
+
+struct BitWriter {
+    bytes: Vec<u8>,
+    acc: u32,
+    bits: u8,
+}
+
+
+impl BitWriter {
+    fn write(&mut self, code: u16, code_size: u8) {
+        self.acc |= (code as u32) << self.bits;
+        self.bits += code_size;
+        while self.bits >= 8 {
+            self.bytes.push(self.acc as u8);
+            self.acc >>= 8;
+            self.bits -= 8;
+        }
+    }
+
+    fn finish(mut self) -> Vec<u8> {
+        if self.bits > 0 {
+            self.bytes.push(self.acc as u8);
+        }
+        self.bytes
+    }
+}
+
+
+fn lzw_encode(pixels: &[u8]) -> Vec<u8> {
+    let clear_code = 1u16 << MIN_CODE_SIZE;
+    let end_code = clear_code + 1;
+
+    let mut out = BitWriter { bytes: Vec::new(), acc: 0, bits: 0 };
+    let mut table = HashMap::new();
+    let mut next_code = end_code + 1;
+    let mut code_size = MIN_CODE_SIZE + 1;
+
+    out.write(clear_code, code_size);
+    let mut prefix = match pixels.first() {
+        Some(&pixel) => pixel as u16,
+        None => {
+            out.write(end_code, code_size);
+            return out.finish();
+        },
+    };
+
+    for &pixel in pixels[1 ..].iter() {
+        if let Some(&code) = table.get(&(prefix, pixel)) {
+            prefix = code;
+            continue;
+        }
+
+        out.write(prefix, code_size);
+        table.insert((prefix, pixel), next_code);
+        next_code += 1;
+        if next_code == MAX_CODE {
+            out.write(clear_code, code_size);
+            table.clear();
+            next_code = end_code + 1;
+            code_size = MIN_CODE_SIZE + 1;
+        } else if next_code > 1 << code_size {
+            code_size += 1;
+        }
+        prefix = pixel as u16;
+    }
+
+    out.write(prefix, code_size);
+    out.write(end_code, code_size);
+    out.finish()
+}
+
+
+fn write_u16<W: Write>(out: &mut W, value: u16) -> io::Result<()> {
+    out.write_all(&[value as u8, (value >> 8) as u8])
+}
+
+
+pub struct GifRecorder {
+    path: PathBuf,
+    palette: [(u8, u8, u8); PALETTE_SIZE],
+    frames: Vec<(u64, Frame)>,
+    recorded_bytes: usize,
+    full: bool,
+}
+
+
+impl GifRecorder {
+    pub fn new(path: PathBuf, palette: [(u8, u8, u8); PALETTE_SIZE]) -> Self {
+        GifRecorder {
+            path,
+            palette,
+            frames: Vec::new(),
+            recorded_bytes: 0,
+            full: false,
+        }
+    }
+
+    pub fn add_frame(&mut self, time_ms: u64, frame: Frame) {
+        if self.full {
+            return;
+        }
+        if let Some((_, last)) = self.frames.last() {
+            if last.pixels == frame.pixels {
+                return;
+            }
+        }
+        if self.recorded_bytes + frame.pixels.len() > MAX_RECORDING_BYTES {
+            println!("Recording to {} is too long, the rest of the game is not recorded",
+                     self.path.display());
+            self.full = true;
+            return;
+        }
+        self.recorded_bytes += frame.pixels.len();
+        self.frames.push((time_ms, frame));
+    }
+
+    pub fn save(&self) -> io::Result<()> {
+        let (width, height) = match self.frames.first() {
+            Some((_, frame)) => (frame.width as u16, frame.height as u16),
+            None => return Ok(()),
+        };
+
+        let mut out = BufWriter::new(File::create(&self.path)?);
+        out.write_all(b"GIF89a")?;
+        write_u16(&mut out, width)?;
+        write_u16(&mut out, height)?;
+        out.write_all(&[0x80 | (MIN_CODE_SIZE - 1) << 4 | (MIN_CODE_SIZE - 1), 0, 0])?;
+        for &(r, g, b) in self.palette.iter() {
+            out.write_all(&[r, g, b])?;
+        }
+
+        out.write_all(&[0x21, 0xff, 11])?;
+        out.write_all(b"NETSCAPE2.0")?;
+        out.write_all(&[3, 1, 0, 0, 0])?;
+
+        for (i, &(time_ms, ref frame)) in self.frames.iter().enumerate() {
+            let delay_cs = match self.frames.get(i + 1) {
+                Some(&(next_time_ms, _)) => (next_time_ms - time_ms) / 10,
+                None => LAST_FRAME_DELAY_CS,
+            };
+            out.write_all(&[0x21, 0xf9, 4, 1 << 2])?;
+            write_u16(&mut out, delay_cs.min(u16::MAX as u64) as u16)?;
+            out.write_all(&[0, 0])?;
+
+            out.write_all(&[0x2c, 0, 0, 0, 0])?;
+            write_u16(&mut out, frame.width as u16)?;
+            write_u16(&mut out, frame.height as u16)?;
+            out.write_all(&[0, MIN_CODE_SIZE])?;
+            for block in lzw_encode(&frame.pixels).chunks(255) {
+                out.write_all(&[block.len() as u8])?;
+                out.write_all(block)?;
+            }
+            out.write_all(&[0])?;
+        }
+
+        out.write_all(&[0x3b])?;
+        out.flush()
+    }
+}
@@ -0,0 +1,225 @@
+use std::collections::HashSet;
+
+use sdl2::event::Event;
+use sdl2::keyboard::Scancode;
+
+use super::{GameInputEvent, KeyBindings};
+
+
+pub struct KeyEdgeDetector {
+    previous: HashSet<Scancode>,
+    current: HashSet<Scancode>,
+}
+
+
+impl KeyEdgeDetector {
+    pub fn new() -> Self {
+        KeyEdgeDetector {
+            previous: HashSet::new(),
+            current: HashSet::new(),
+        }
+    }
+
+    pub fn update(&mut self, event: Option<&Event>) {
+        self.previous.clone_from(&self.current);
+        match event {
+            Some(&Event::KeyDown {scancode: Some(code), ..}) => {
+                self.current.insert(code);
+            },
+            Some(&Event::KeyUp {scancode: Some(code), ..}) => {
+                self.current.remove(&code);
+            },
+            _ => {},
+        }
+    }
+
+    pub fn just_pressed(&self, code: Scancode) -> bool {
+        self.current.contains(&code) && ! self.previous.contains(&code)
+    }
+
+    pub fn held(&self, code: Scancode) -> bool {
+        self.current.contains(&code)
+    }
+
+    pub fn just_released(&self, code: Scancode) -> bool {
+        self.previous.contains(&code) && ! self.current.contains(&code)
+    }
+}
+
+
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct KeyState {
+    pub pressed: bool,
+    pub held: bool,
+    pub released: bool,
+}
+
+
+impl KeyState {
+    fn capture(keys: &KeyEdgeDetector, codes: &[Scancode]) -> Self {
+        KeyState {
+            pressed: codes.iter().any(|&code| keys.just_pressed(code)),
+            held: codes.iter().any(|&code| keys.held(code)),
+            released: codes.iter().any(|&code| keys.just_released(code)),
+        }
+    }
+}
+
+
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct InputSnapshot {
+    pub time_ms: u64,
+    pub move_left: KeyState,
+    pub move_right: KeyState,
+    pub rotate: KeyState,
+    pub rotate_180: KeyState,
+    pub soft_drop: KeyState,
+    pub soft_drop_without_space: KeyState,
+    pub space: KeyState,
+    pub hold: KeyState,
+}
+
+
+impl InputSnapshot {
+    pub fn capture(keys: &KeyEdgeDetector, bindings: &KeyBindings, mirrored: bool,
+                   time_ms: u64) -> Self {
+        let state = |event| KeyState::capture(keys, bindings.scancodes(event));
+        let (move_left, move_right) = if mirrored {
+            (state(GameInputEvent::MoveRight), state(GameInputEvent::MoveLeft))
+        } else {
+            (state(GameInputEvent::MoveLeft), state(GameInputEvent::MoveRight))
+        };
+        let soft_drop_without_space: Vec<_> = bindings.soft_drop.iter().cloned()
+            .filter(|&code| code != Scancode::Space)
+            .collect();
+        InputSnapshot {
+            time_ms,
+            move_left,
+            move_right,
+            rotate: state(GameInputEvent::RotateClockwise),
+            rotate_180: state(GameInputEvent::Rotate180),
+            soft_drop: state(GameInputEvent::SoftDrop),
+            soft_drop_without_space: KeyState::capture(keys, &soft_drop_without_space),
+            space: KeyState::capture(keys, &[Scancode::Space]),
+            hold: state(GameInputEvent::Hold),
+        }
+    }
+}
+
+
+/// Resolves Left and Right held together: the most recently pressed direction wins.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct MovePriority {
+    right_has_priority: bool,
+}
+
+
+impl MovePriority {
+    /// Returns whether the direction changed, so the auto-shift delay starts over.
+    pub fn update(&mut self, input: &InputSnapshot) -> bool {
+        let mut restart = false;
+        if input.move_left.pressed {
+            self.right_has_priority = false;
+            restart = true;
+        }
+        if input.move_right.pressed {
+            self.right_has_priority = true;
+            restart = true;
+        }
+        if input.move_left.released && input.move_right.held && ! self.right_has_priority {
+            self.right_has_priority = true;
+            restart = true;
+        }
+        if input.move_right.released && input.move_left.held && self.right_has_priority {
+            self.right_has_priority = false;
+            restart = true;
+        }
+        restart
+    }
+
+    pub fn direction(&self, input: &InputSnapshot) -> Option<GameInputEvent> {
+        match (input.move_left.held, input.move_right.held) {
+            (false, false) => None,
+            (true, false) => Some(GameInputEvent::MoveLeft),
+            (false, true) => Some(GameInputEvent::MoveRight),
+            (true, true) if self.right_has_priority => Some(GameInputEvent::MoveRight),
+            (true, true) => Some(GameInputEvent::MoveLeft),
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use sdl2::keyboard::NOMOD;
+
+    use super::*;
+
+    fn key(pressed: bool, held: bool, released: bool) -> KeyState {
+        KeyState { pressed, held, released }
+    }
+
+    fn frame(left: KeyState, right: KeyState) -> InputSnapshot {
+        InputSnapshot { move_left: left, move_right: right, .. InputSnapshot::default() }
+    }
+
+    #[test]
+    fn most_recent_direction_wins() {
+        let (up, down) = (KeyState::default(), key(false, true, false));
+        let frames = [
+            // Hold Left, tap Right while Left is still down, release Right.
+            (frame(key(true, true, false), up), true, Some(GameInputEvent::MoveLeft)),
+            (frame(down, up), false, Some(GameInputEvent::MoveLeft)),
+            (frame(down, key(true, true, false)), true, Some(GameInputEvent::MoveRight)),
+            (frame(down, down), false, Some(GameInputEvent::MoveRight)),
+            (frame(down, key(false, false, true)), true, Some(GameInputEvent::MoveLeft)),
+            // Press Right, then Left on top of it, then release Left.
+            (frame(key(false, false, true), key(true, true, false)), true,
+             Some(GameInputEvent::MoveRight)),
+            (frame(key(true, true, false), down), true, Some(GameInputEvent::MoveLeft)),
+            (frame(key(false, false, true), down), true, Some(GameInputEvent::MoveRight)),
+            // Releasing the key without priority keeps the current direction and its charge.
+            (frame(key(true, true, false), down), true, Some(GameInputEvent::MoveLeft)),
+            (frame(down, key(false, false, true)), false, Some(GameInputEvent::MoveLeft)),
+            (frame(key(false, false, true), up), false, None),
+        ];
+        let mut priority = MovePriority::default();
+        for (i, &(ref input, restart, direction)) in frames.iter().enumerate() {
+            assert_eq!(priority.update(input), restart, "frame {}", i);
+            assert_eq!(priority.direction(input), direction, "frame {}", i);
+        }
+    }
+
+
+    fn key_event(code: Scancode, down: bool) -> Event {
+        if down {
+            Event::KeyDown { timestamp: 0, window_id: 0, keycode: None, scancode: Some(code),
+                             keymod: NOMOD, repeat: false }
+        } else {
+            Event::KeyUp { timestamp: 0, window_id: 0, keycode: None, scancode: Some(code),
+                           keymod: NOMOD, repeat: false }
+        }
+    }
+
+    #[test]
+    fn key_edges_follow_presses_and_releases() {
+        let mut keys = KeyEdgeDetector::new();
+        let state = |keys: &KeyEdgeDetector, code| {
+            (keys.just_pressed(code), keys.held(code), keys.just_released(code))
+        };
+        let steps = [
+            (Some(key_event(Scancode::Left, true)), (true, true, false), (false, false, false)),
+            (None, (false, true, false), (false, false, false)),
+            (Some(key_event(Scancode::Up, true)), (false, true, false), (true, true, false)),
+            (Some(key_event(Scancode::Left, false)), (false, false, true), (false, true, false)),
+            (None, (false, false, false), (false, true, false)),
+            (Some(key_event(Scancode::Up, false)), (false, false, false), (false, false, true)),
+            (Some(key_event(Scancode::Left, true)), (true, true, false), (false, false, false)),
+        ];
+        for (i, &(ref event, left, up)) in steps.iter().enumerate() {
+            keys.update(event.as_ref());
+            assert_eq!(state(&keys, Scancode::Left), left, "step {}", i);
+            assert_eq!(state(&keys, Scancode::Up), up, "step {}", i);
+        }
+    }
+}
@@ -1,41 +1,180 @@
+// Without SDL only the game logic that `web` drives is built, and the rest of it goes unused.
+#![cfg_attr(not(feature = "sdl"), allow(dead_code, unused_imports))]
+
 extern crate argparse;
 extern crate rand;
+#[cfg(feature = "sdl")]
 extern crate sdl2;
+#[cfg(feature = "sdl")]
 extern crate sdl2_sys;
 extern crate time;
 
+mod achievements;
+mod ai;
+mod bench;
+mod boarddump;
+#[cfg(feature = "sdl")]
+mod draw;
+mod eventlog;
+#[cfg(feature = "sdl")]
+mod font;
+mod gif;
+#[cfg(feature = "sdl")]
+mod input;
+mod optimize;
+mod placements;
+mod raster;
+mod replay;
+#[cfg(test)]
+mod snapshots;
+#[cfg(test)]
+mod soak;
+mod spectate;
+mod strings;
+mod timing;
+#[cfg(feature = "sdl")]
+mod title;
+mod tournament;
+#[cfg(feature = "web")]
+mod web;
+
 
 use std::borrow::Borrow;
-use std::cmp::{min, max};
+use std::cell::{Cell, RefCell};
+use std::collections::VecDeque;
+use std::fmt;
+use std::cmp::{min, max, Ordering};
+use std::ops::{Add, Index, Mul, Neg};
+use std::path::{Path, PathBuf};
 use std::vec::Vec;
 
-use sdl2::keyboard::Keycode;
-use sdl2::pixels::Color;
-use sdl2::render::Renderer;
+use rand::SeedableRng;
+
+#[cfg(feature = "sdl")]
+use sdl2::keyboard::{Keycode, LCTRLMOD, RCTRLMOD, Scancode};
+#[cfg(feature = "sdl")]
+use sdl2::mouse::Mouse;
+#[cfg(feature = "sdl")]
+use sdl2::pixels::{Color, PixelFormatEnum};
+#[cfg(feature = "sdl")]
+use sdl2::render::{BlendMode, Renderer, RendererParent};
+#[cfg(feature = "sdl")]
+use sdl2::surface::Surface;
+#[cfg(feature = "sdl")]
 use sdl2::event::Event;
 
+#[cfg(feature = "sdl")]
+use draw::TryDraw;
 
-#[derive(Clone, Copy, Debug, PartialEq)]
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
 enum TetrisCellColor {
-    Red, Orange, Yellow, Green, Blue, DeepBlue, Purple,
+    Red, Orange, Yellow, Green, Blue, DeepBlue, Purple, Gray,
 }
 
 
 impl TetrisCellColor {
-    fn get_sdl_color(&self) -> Color {
+    fn default_rgb(&self) -> (u8, u8, u8) {
+        match self {
+            &TetrisCellColor::Red => (200, 0, 0),
+            &TetrisCellColor::Orange => (180, 130, 0),
+            &TetrisCellColor::Yellow => (180, 180, 0),
+            &TetrisCellColor::Green => (0, 200, 0),
+            &TetrisCellColor::Blue => (0, 180, 180),
+            &TetrisCellColor::DeepBlue => (0, 0, 200),
+            &TetrisCellColor::Purple => (180, 0, 180),
+            &TetrisCellColor::Gray => (100, 100, 100),
+        }
+    }
+
+    fn to_rgb(self, scheme: &ColorScheme) -> (u8, u8, u8) {
+        scheme.rgb[self.to_index() as usize]
+    }
+
+    fn to_dim_rgb(self, alpha: u8, scheme: &ColorScheme) -> (u8, u8, u8) {
+        dim_rgb(self.to_rgb(scheme), alpha)
+    }
+
+    #[cfg(feature = "sdl")]
+    fn get_sdl_color(&self, scheme: &ColorScheme) -> Color {
+        let (r, g, b) = self.to_rgb(scheme);
+        Color::RGB(r, g, b)
+    }
+
+    fn name(&self) -> &'static str {
+        match *self {
+            TetrisCellColor::Red => "red",
+            TetrisCellColor::Orange => "orange",
+            TetrisCellColor::Yellow => "yellow",
+            TetrisCellColor::Green => "green",
+            TetrisCellColor::Blue => "blue",
+            TetrisCellColor::DeepBlue => "deep_blue",
+            TetrisCellColor::Purple => "purple",
+            TetrisCellColor::Gray => "gray",
+        }
+    }
+
+    fn glyph(&self) -> &'static [(usize, usize, usize, usize)] {
+        match *self {
+            TetrisCellColor::Red => &[],
+            TetrisCellColor::Orange => &[(2, 2, 4, 4)],
+            TetrisCellColor::Yellow => &[(3, 3, 2, 2)],
+            TetrisCellColor::Green => &[(1, 3, 6, 2)],
+            TetrisCellColor::Blue => &[(3, 1, 2, 6)],
+            TetrisCellColor::DeepBlue => &[(0, 0, 3, 3)],
+            TetrisCellColor::Purple => &[(1, 1, 3, 3), (4, 4, 3, 3)],
+            TetrisCellColor::Gray => &[(1, 3, 6, 2), (3, 1, 2, 6)],
+        }
+    }
+
+    fn symbol(&self) -> char {
+        match *self {
+            TetrisCellColor::Red => 'R',
+            TetrisCellColor::Orange => 'O',
+            TetrisCellColor::Yellow => 'Y',
+            TetrisCellColor::Green => 'G',
+            TetrisCellColor::Blue => 'B',
+            TetrisCellColor::DeepBlue => 'D',
+            TetrisCellColor::Purple => 'P',
+            TetrisCellColor::Gray => 'X',
+        }
+    }
+
+    fn from_index(index: u8) -> Option<TetrisCellColor> {
+        match index {
+            0 => Some(TetrisCellColor::Red),
+            1 => Some(TetrisCellColor::Orange),
+            2 => Some(TetrisCellColor::Yellow),
+            3 => Some(TetrisCellColor::Green),
+            4 => Some(TetrisCellColor::Blue),
+            5 => Some(TetrisCellColor::DeepBlue),
+            6 => Some(TetrisCellColor::Purple),
+            7 => Some(TetrisCellColor::Gray),
+            _ => None,
+        }
+    }
+
+    fn to_index(self) -> u8 {
         match self {
-            &TetrisCellColor::Red => Color::RGB(200, 0, 0),
-            &TetrisCellColor::Orange => Color::RGB(180, 130, 0),
-            &TetrisCellColor::Yellow => Color::RGB(180, 180, 0),
-            &TetrisCellColor::Green => Color::RGB(0, 200, 0),
-            &TetrisCellColor::Blue => Color::RGB(0, 180, 180),
-            &TetrisCellColor::DeepBlue => Color::RGB(0, 0, 200),
-            &TetrisCellColor::Purple => Color::RGB(180, 0, 180),
+            TetrisCellColor::Red => 0,
+            TetrisCellColor::Orange => 1,
+            TetrisCellColor::Yellow => 2,
+            TetrisCellColor::Green => 3,
+            TetrisCellColor::Blue => 4,
+            TetrisCellColor::DeepBlue => 5,
+            TetrisCellColor::Purple => 6,
+            TetrisCellColor::Gray => 7,
         }
     }
 }
 
 
+fn dim_rgb((r, g, b): (u8, u8, u8), alpha: u8) -> (u8, u8, u8) {
+    let dim = |channel: u8| (channel as u16 * alpha as u16 / 255) as u8;
+    (dim(r), dim(g), dim(b))
+}
+
+
 #[derive(PartialEq, Clone, Copy, Debug)]
 struct Point(usize, usize);
 
@@ -46,44 +185,118 @@ struct Dimensions(usize, usize);
 struct PointOffset(isize, isize);
 
 
+impl From<(usize, usize)> for Dimensions {
+    fn from((width, height): (usize, usize)) -> Dimensions {
+        Dimensions(width, height)
+    }
+}
+
+
+impl Add for PointOffset {
+    type Output = PointOffset;
+
+    fn add(self, other: PointOffset) -> PointOffset {
+        PointOffset(self.0 + other.0, self.1 + other.1)
+    }
+}
+
+
+impl Neg for PointOffset {
+    type Output = PointOffset;
+
+    fn neg(self) -> PointOffset {
+        PointOffset(-self.0, -self.1)
+    }
+}
+
+
+impl Mul<isize> for PointOffset {
+    type Output = PointOffset;
+
+    fn mul(self, factor: isize) -> PointOffset {
+        PointOffset(self.0 * factor, self.1 * factor)
+    }
+}
+
+
 
 trait CellScreen {
     fn reset(&mut self);
     fn set_cell(&mut self, Point, Option<TetrisCellColor>);
+    fn copy_cells_into(&self, dest: &mut Vec<Option<TetrisCellColor>>);
     fn dimensions(&self) -> Dimensions;
     fn layers<'a>(&'a self) -> Vec<(Point, Dimensions, &'a [Option<TetrisCellColor>])>;
     fn cell_size(&self) -> Dimensions;
     fn cell_spacing(&self) -> Dimensions;
     fn window_size(&self) -> Dimensions;
     fn global_offset(&self) -> Dimensions;
-}
+    fn lock_progress(&self) -> Option<(Point, Dimensions, f32)>;
+    fn ghost_layer(&self) -> Option<(Point, Dimensions, &[Option<TetrisCellColor>])>;
+    fn mirrored(&self) -> bool;
+    fn monochrome(&self) -> bool;
+    fn colors(&self) -> &ColorScheme;
+
+    fn row_color(&self, _y: usize) -> Option<(u8, u8, u8)> {
+        None
+    }
 
+    fn rgb(&self, color: TetrisCellColor) -> (u8, u8, u8) {
+        color.to_rgb(self.colors())
+    }
 
-trait CellScreenRenderer {
-    fn render_cell_screen(&self, renderer: &mut Renderer);
-}
+    #[cfg(feature = "sdl")]
+    fn sdl_color(&self, color: TetrisCellColor) -> Color {
+        let (r, g, b) = self.rgb(color);
+        Color::RGB(r, g, b)
+    }
 
+    /// The spacing to draw around every cell, shrunk to leave at least one pixel of the cell, or
+    /// `None` when cells are too small to draw at all.
+    fn drawn_cell_spacing(&self) -> Option<Dimensions> {
+        let (cell_size, spacing) = (self.cell_size(), self.cell_spacing());
+        if cell_size.0 == 0 || cell_size.1 == 0 {
+            return None;
+        }
+        Some(Dimensions(min(spacing.0, (cell_size.0 - 1) / 2), min(spacing.1, (cell_size.1 - 1) / 2)))
+    }
 
-impl <C: CellScreen> CellScreenRenderer for C {
-    fn render_cell_screen(&self, renderer: &mut Renderer) {
-        let Dimensions(x_glob_offset, y_glob_offset) = self.global_offset();
+    fn screen_column(&self, x: usize) -> usize {
+        if self.mirrored() {
+            self.dimensions().0 - 1 - x
+        } else {
+            x
+        }
+    }
 
-        let Dimensions(x_max, y_max) = self.dimensions();
-        let mut cells = std::iter::repeat(None).take(x_max * y_max).collect::<Vec<_>>();
+    fn ghost_cells(&self) -> Vec<Option<TetrisCellColor>> {
+        let Dimensions(columns, lines) = self.dimensions();
+        let mut result = vec![None; columns * lines];
+        if let Some((Point(ghost_x, ghost_y), Dimensions(ghost_width, _), ghost_cells)) =
+            self.ghost_layer() {
+            for (i, cell) in ghost_cells.iter().enumerate() {
+                let (x, y) = (self.screen_column(ghost_x + i % ghost_width), ghost_y + i / ghost_width);
+                if cell.is_some() && y < lines {
+                    result[y * columns + x] = *cell;
+                }
+            }
+        }
+        result
+    }
 
-        let cell_size = self.cell_size();
-        let cell_spacing = self.cell_spacing();
+    fn composed_cells(&self) -> Vec<Option<TetrisCellColor>> {
+        let mut cells = Vec::new();
+        self.compose_cells_into(&mut cells);
+        cells
+    }
 
-        renderer.set_draw_color(Color::RGB(0, 0, 0));
-        renderer.clear();
+    fn with_composed_cells(&self, f: &mut dyn FnMut(&[Option<TetrisCellColor>])) {
+        f(&self.composed_cells())
+    }
 
-        renderer.set_draw_color(Color::RGB(127, 127, 127));
-        renderer.draw_rect(sdl2::rect::Rect::new_unwrap(
-            x_glob_offset as i32,
-            y_glob_offset as i32,
-            (cell_size.0 * x_max) as u32,
-            (cell_size.1 * y_max) as u32,
-            ));
+    fn compose_cells_into(&self, cells: &mut Vec<Option<TetrisCellColor>>) {
+        let Dimensions(x_max, y_max) = self.dimensions();
+        cells.clear();
+        cells.resize(x_max * y_max, None);
 
         for layer_params in self.layers() {
             let (Point(layer_x0, layer_y0),
@@ -97,68 +310,520 @@ impl <C: CellScreen> CellScreenRenderer for C {
             let mut layer_cell_iter = layer_cells.iter();
             for y in layer_y0 .. layer_y0 + layer_height {
                 for x in layer_x0 .. layer_x0 + layer_width {
-                    if let Some(color) = layer_cell_iter.next().unwrap().clone() {
-                        cells[y * x_max + x] = Some(color);
+                    if let Some(color) = *layer_cell_iter.next().unwrap() {
+                        cells[y * x_max + self.screen_column(x)] = Some(color);
                     }
                 }
             }
         }
+    }
+}
+
+
+/// Replaces the renderer of a window with a new one. If SDL can not create it, `renderer` keeps
+/// the window without an SDL renderer: every draw call fails, and the next attempt can still take
+/// the window out of it.
+#[cfg(feature = "sdl")]
+fn recreate_renderer(renderer: &mut Renderer) -> Result<(), String> {
+    let placeholder = Surface::new(1, 1, PixelFormatEnum::RGB24)
+        .and_then(Renderer::from_surface)
+        .map_err(|e| e.to_string())?;
+    let window = std::mem::replace(renderer, placeholder).into_window()
+        .ok_or_else(|| "the renderer does not draw into a window".to_string())?;
+    // Building through window.renderer() would drop the window on failure.
+    let raw = unsafe { sdl2_sys::render::SDL_CreateRenderer(window.raw(), -1, 0) };
+    let error = if raw.is_null() { Some(sdl2::get_error().0) } else { None };
+    *renderer = unsafe { Renderer::from_ll(raw, RendererParent::Window(window)) };
+    match error {
+        Some(error) => Err(error),
+        None => Ok(()),
+    }
+}
+
+
+#[cfg(feature = "sdl")]
+trait CellScreenRenderer {
+    fn render_cell_screen(&self, renderer: &mut Renderer) -> Result<(), String>;
+}
+
+
+#[cfg(feature = "sdl")]
+impl <C: CellScreen> CellScreenRenderer for C {
+    fn render_cell_screen(&self, renderer: &mut Renderer) -> Result<(), String> {
+        renderer.try_set_draw_color(Color::RGB(0, 0, 0))?;
+        renderer.try_clear()?;
+        draw_cell_screen(self, renderer, Point(0, 0))
+    }
+}
+
+
+struct ScreenLayout {
+    window_dim: Dimensions,
+    panels: Vec<(Point, Box<dyn CellScreen>)>,
+}
+
+
+impl ScreenLayout {
+    fn new(window_dim: Dimensions) -> Self {
+        ScreenLayout {
+            window_dim,
+            panels: Vec::new(),
+        }
+    }
+
+    fn add_panel(&mut self, origin: Point, screen: Box<dyn CellScreen>) {
+        self.panels.push((origin, screen));
+    }
+}
+
+
+#[cfg(feature = "sdl")]
+impl CellScreenRenderer for ScreenLayout {
+    fn render_cell_screen(&self, renderer: &mut Renderer) -> Result<(), String> {
+        renderer.try_set_draw_color(Color::RGB(0, 0, 0))?;
+        renderer.try_clear()?;
+        for &(origin, ref screen) in &self.panels {
+            if origin.0 < self.window_dim.0 && origin.1 < self.window_dim.1 {
+                draw_cell_screen(&**screen, renderer, origin)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+
+#[cfg(feature = "sdl")]
+fn draw_cell_screen<C: CellScreen + ?Sized>(screen: &C, renderer: &mut Renderer, origin: Point)
+                                             -> Result<(), String> {
+    let Dimensions(x_glob_offset, y_glob_offset) = screen.global_offset();
+    let (x_glob_offset, y_glob_offset) = (origin.0 + x_glob_offset, origin.1 + y_glob_offset);
+
+    let Dimensions(x_max, y_max) = screen.dimensions();
+
+    let cell_size = screen.cell_size();
+    let cell_spacing = match screen.drawn_cell_spacing() {
+        Some(spacing) => spacing,
+        None => return Ok(()),
+    };
+
+    renderer.try_set_draw_color(Color::RGB(127, 127, 127))?;
+    renderer.try_draw_rect(sdl2::rect::Rect::new_unwrap(
+        x_glob_offset as i32,
+        y_glob_offset as i32,
+        (cell_size.0 * x_max) as u32,
+        (cell_size.1 * y_max) as u32,
+        ))?;
 
-        let cells = cells;
+    let ghost_cells = screen.ghost_cells();
+    let mut drawn: Result<(), String> = Ok(());
+    screen.with_composed_cells(&mut |cells| {
         let mut cell_iter = cells.iter();
 
-        for y in 0 .. y_max {
+        drawn = (0 .. y_max).try_for_each(|y| {
+            let row_color = screen.row_color(y);
             for x in 0 .. x_max {
                 let cell = cell_iter.next().unwrap();
-                renderer.set_draw_color(match cell {
-                    &None => Color::RGB(0, 0, 0),
-                    &Some(ref cell) => cell.get_sdl_color(),
-                });
-                let rect = sdl2::rect::Rect::new_unwrap(
-                    (x_glob_offset + x * cell_size.0 + cell_spacing.0) as i32,
-                    (y_glob_offset + y * cell_size.1 + cell_spacing.1) as i32,
-                    (cell_size.0 - cell_spacing.0 * 2) as u32,
-                    (cell_size.1 - cell_spacing.1 * 2) as u32,
-                    );
-                renderer.fill_rect(rect);
+                renderer.try_set_draw_color(match (row_color, cell) {
+                    (Some((r, g, b)), _) => Color::RGB(r, g, b),
+                    (None, &None) => match ghost_cells[y * x_max + x] {
+                        Some(color) if ! screen.monochrome() => {
+                            let (r, g, b) = color.to_dim_rgb(GHOST_ALPHA, screen.colors());
+                            Color::RGB(r, g, b)
+                        },
+                        _ => Color::RGB(0, 0, 0),
+                    },
+                    (None, &Some(_)) if screen.monochrome() => Color::RGB(255, 255, 255),
+                    (None, &Some(cell)) => screen.sdl_color(cell),
+                })?;
+                let (left, top) = (x_glob_offset + x * cell_size.0 + cell_spacing.0,
+                                   y_glob_offset + y * cell_size.1 + cell_spacing.1);
+                let (width, height) = (cell_size.0 - cell_spacing.0 * 2,
+                                       cell_size.1 - cell_spacing.1 * 2);
+                renderer.try_fill_rect(sdl2::rect::Rect::new_unwrap(
+                    left as i32, top as i32, width as u32, height as u32))?;
+
+                if let (true, &Some(cell), None) = (screen.monochrome(), cell, row_color) {
+                    renderer.try_set_draw_color(Color::RGB(0, 0, 0))?;
+                    for &(glyph_x, glyph_y, glyph_width, glyph_height) in cell.glyph() {
+                        renderer.try_fill_rect(sdl2::rect::Rect::new_unwrap(
+                            (left + width * glyph_x / 8) as i32,
+                            (top + height * glyph_y / 8) as i32,
+                            max(width * glyph_width / 8, 1) as u32,
+                            max(height * glyph_height / 8, 1) as u32))?;
+                    }
+                }
             }
+            Ok(())
+        });
+    });
+    drawn?;
+
+    if screen.monochrome() {
+        renderer.try_set_draw_color(Color::RGB(255, 255, 255))?;
+        for (i, _) in ghost_cells.iter().enumerate().filter(|&(_, cell)| cell.is_some()) {
+            renderer.try_draw_rect(sdl2::rect::Rect::new_unwrap(
+                (x_glob_offset + i % x_max * cell_size.0 + cell_spacing.0) as i32,
+                (y_glob_offset + i / x_max * cell_size.1 + cell_spacing.1) as i32,
+                (cell_size.0 - cell_spacing.0 * 2) as u32,
+                (cell_size.1 - cell_spacing.1 * 2) as u32,
+                ))?;
+        }
+    }
+
+    if let Some(lock_progress) = screen.lock_progress() {
+        let (Point(fig_x, fig_y), Dimensions(fig_width, fig_height), progress) = lock_progress;
+        let bar_width = (cell_size.0 as f32 * progress) as u32;
+        if bar_width > 0 {
+            let double_center = if screen.mirrored() {
+                x_max * 2 - 1 - fig_x * 2 - fig_width
+            } else {
+                fig_x * 2 + fig_width - 1
+            };
+            renderer.try_set_draw_color(Color::RGB(220, 220, 0))?;
+            renderer.try_fill_rect(sdl2::rect::Rect::new_unwrap(
+                (x_glob_offset + double_center * cell_size.0 / 2) as i32,
+                (y_glob_offset + (fig_y + fig_height) * cell_size.1) as i32,
+                bar_width,
+                4,
+                ))?;
         }
     }
+    Ok(())
 }
 
 
+#[derive(Clone, Copy, Debug, PartialEq)]
 enum GameInputEvent {
     RotateClockwise,
+    Rotate180,
     MoveLeft,
     MoveRight,
+    SoftDrop,
     Timer,
+    Undo,
+    Hold,
+}
+
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum GameEvent {
+    LinesCleared(usize),
+    LevelUp(usize),
+    PerfectClear,
+    TimePlayed(GameMode, u64),
+}
+
+
+#[cfg(feature = "sdl")]
+impl GameInputEvent {
+    fn from_scancode(code: Scancode, bindings: &KeyBindings) -> Option<GameInputEvent> {
+        [
+            (&bindings.move_left, GameInputEvent::MoveLeft),
+            (&bindings.move_right, GameInputEvent::MoveRight),
+            (&bindings.rotate, GameInputEvent::RotateClockwise),
+            (&bindings.rotate_180, GameInputEvent::Rotate180),
+            (&bindings.soft_drop, GameInputEvent::SoftDrop),
+            (&bindings.hold, GameInputEvent::Hold),
+        ].iter()
+            .find(|&&(codes, _)| codes.contains(&code))
+            .map(|&(_, event)| event)
+    }
+}
+
+
+#[cfg(feature = "sdl")]
+#[derive(Clone, Debug, PartialEq)]
+struct KeyBindings {
+    move_left: Vec<Scancode>,
+    move_right: Vec<Scancode>,
+    rotate: Vec<Scancode>,
+    rotate_180: Vec<Scancode>,
+    soft_drop: Vec<Scancode>,
+    hold: Vec<Scancode>,
+}
+
+
+#[cfg(feature = "sdl")]
+impl KeyBindings {
+    fn scancodes(&self, event: GameInputEvent) -> &[Scancode] {
+        match event {
+            GameInputEvent::MoveLeft => &self.move_left,
+            GameInputEvent::MoveRight => &self.move_right,
+            GameInputEvent::RotateClockwise => &self.rotate,
+            GameInputEvent::Rotate180 => &self.rotate_180,
+            GameInputEvent::SoftDrop => &self.soft_drop,
+            GameInputEvent::Hold => &self.hold,
+            GameInputEvent::Timer | GameInputEvent::Undo => &[],
+        }
+    }
+}
+
+
+#[cfg(feature = "sdl")]
+impl Default for KeyBindings {
+    fn default() -> Self {
+        KeyBindings {
+            move_left: vec![Scancode::Left],
+            move_right: vec![Scancode::Right],
+            rotate: vec![Scancode::Up],
+            rotate_180: vec![Scancode::A],
+            soft_drop: vec![Scancode::Down, Scancode::Space],
+            hold: vec![Scancode::C],
+        }
+    }
+}
+
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum GravityResult {
+    Moved,
+    Locked,
+}
+
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum GameExit {
+    Quit,
+    Title,
+    /// A new game in the same mode, without going through the title screen.
+    Restart,
 }
 
 
+#[cfg(feature = "sdl")]
 trait Game {
-    fn run(&mut self, &mut sdl2::EventPump, &mut Renderer);
+    fn run(&mut self, event_pump: &mut sdl2::EventPump, renderer: &mut Renderer) -> GameExit;
     fn window_size(&self) -> (u32, u32);
 }
 
 
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum GameError {
+    BoardSizeMismatch { dim: Dimensions, cells: usize },
+    BitboardSizeMismatch { dim: Dimensions, words: usize },
+    BoardDimensionsMismatch { expected: Dimensions, got: Dimensions },
+    BoardTooSmall { dim: Dimensions, needed: Dimensions },
+    EventLogExport(std::io::ErrorKind),
+    BoardOverflow { at_row: usize },
+}
+
+
+impl fmt::Display for GameError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            GameError::BoardSizeMismatch { dim: Dimensions(columns, lines), cells } =>
+                write!(f, "a {}x{} board needs {} cells, got {}",
+                       columns, lines, columns * lines, cells),
+            GameError::BitboardSizeMismatch { dim, words } =>
+                write!(f, "a {}x{} board needs {} bitboard words, got {}",
+                       dim.0, dim.1, bitboard_words_per_row(dim.0) * dim.1, words),
+            GameError::BoardDimensionsMismatch { expected, got } =>
+                write!(f, "expected a {}x{} board, got {}x{}", expected.0, expected.1, got.0, got.1),
+            GameError::BoardTooSmall { dim, needed } =>
+                write!(f, "the stack needs at least a {}x{} board, got {}x{}",
+                       needed.0, needed.1, dim.0, dim.1),
+            GameError::EventLogExport(kind) =>
+                write!(f, "can not write the event log: {}", kind),
+            GameError::BoardOverflow { at_row } =>
+                write!(f, "the new figure does not fit into the board at row {}", at_row),
+        }
+    }
+}
+
+
+const FNV_OFFSET_BASIS: u64 = 0xcbf2_9ce4_8422_2325;
+const FNV_PRIME: u64 = 0x100_0000_01b3;
+
+
+fn fnv_mix(hash: u64, value: u64) -> u64 {
+    (hash ^ value).wrapping_mul(FNV_PRIME)
+}
+
+
+#[derive(Clone, Debug, PartialEq)]
+struct BoardState {
+    cells: Vec<Option<TetrisCellColor>>,
+    dim: Dimensions,
+}
+
+
+#[derive(Clone, Debug)]
+struct GameState<Random> {
+    board: BoardState,
+    figure: Option<(Point, TetrisCellColor, Figure)>,
+    figures_generated: usize,
+    piece_stats: PieceStats,
+    lines_cleared: usize,
+    garbage_remaining: usize,
+    last_garbage_gap: Option<usize>,
+    pending_garbage: VecDeque<usize>,
+    state_hash: u64,
+    held_figure: Option<Figure>,
+    hold_used: bool,
+    rng: Random,
+}
+
+
+/// Generators have no equality of their own, so two states are equal when their generators are
+/// going to draw the same numbers. Four draws pin down a whole xorshift state.
+impl <Random: rand::Rng + Clone> PartialEq for GameState<Random> {
+    fn eq(&self, other: &Self) -> bool {
+        let (mut rng, mut other_rng) = (self.rng.clone(), other.rng.clone());
+        self.board == other.board
+            && self.figure == other.figure
+            && self.figures_generated == other.figures_generated
+            && self.piece_stats == other.piece_stats
+            && self.lines_cleared == other.lines_cleared
+            && self.garbage_remaining == other.garbage_remaining
+            && self.last_garbage_gap == other.last_garbage_gap
+            && self.pending_garbage == other.pending_garbage
+            && self.state_hash == other.state_hash
+            && self.held_figure == other.held_figure
+            && self.hold_used == other.hold_used
+            && (0 .. 4).all(|_| rng.next_u32() == other_rng.next_u32())
+    }
+}
+
+
+fn bitboard_words_per_row(columns: usize) -> usize {
+    columns.div_ceil(64)
+}
+
+
+fn bitboard_word(cells: &[Option<TetrisCellColor>]) -> u64 {
+    cells.iter().enumerate().fold(0u64, |word, (bit, cell)| word | (cell.is_some() as u64) << bit)
+}
+
+
+#[derive(Clone)]
 struct TetrisCellScreen {
     cells: Vec<Option<TetrisCellColor>>,
     dim: Dimensions,
     _cell_size: usize,
     _figure: Option<(Point, TetrisCellColor, Figure)>,
     _figure_layer: Vec<Option<TetrisCellColor>>,
+    _top_out_layer: Vec<Option<TetrisCellColor>>,
+    _row_colors: Vec<(usize, (u8, u8, u8))>,
+    _lock_progress: Option<f32>,
+    _drop_distance: usize,
+    _mirrored: bool,
+    _invisible: bool,
+    _monochrome: bool,
+    _colors: ColorScheme,
+    _composed: RefCell<Vec<Option<TetrisCellColor>>>,
+    _dirty: Cell<bool>,
 }
 
 
 impl TetrisCellScreen {
     fn new(columns: usize, lines: usize, cell_size: usize) -> Self {
+        let cells = std::iter::repeat_n(None, columns * lines).collect();
         TetrisCellScreen {
-            cells: std::iter::repeat(None).take(columns * lines).collect(),
-            dim: Dimensions(columns, lines),
             _cell_size: cell_size,
+            .. TetrisCellScreen::from_cells(cells, Dimensions(columns, lines)).unwrap()
+        }
+    }
+
+    fn from_cells(cells: Vec<Option<TetrisCellColor>>, dim: Dimensions) -> Result<Self, GameError> {
+        if cells.len() != dim.0 * dim.1 {
+            return Err(GameError::BoardSizeMismatch { dim, cells: cells.len() });
+        }
+        Ok(TetrisCellScreen {
+            cells,
+            dim,
+            _cell_size: 1,
             _figure: None,
             _figure_layer: Vec::new(),
+            _top_out_layer: Vec::new(),
+            _row_colors: Vec::new(),
+            _lock_progress: None,
+            _drop_distance: 0,
+            _mirrored: false,
+            _invisible: false,
+            _monochrome: false,
+            _colors: ColorScheme::default(),
+            _composed: RefCell::new(Vec::new()),
+            _dirty: Cell::new(true),
+        })
+    }
+
+    /// A board with every set bit filled with `color`, for when only collisions matter.
+    fn from_bitboard(bits: &[u64], dim: Dimensions, color: TetrisCellColor)
+                     -> Result<Self, GameError> {
+        let words_per_row = bitboard_words_per_row(dim.0);
+        if bits.len() != words_per_row * dim.1 {
+            return Err(GameError::BitboardSizeMismatch { dim, words: bits.len() });
+        }
+        let cells = bits.chunks(words_per_row)
+            .flat_map(|row| (0 .. dim.0).map(move |x| row[x / 64] >> (x % 64) & 1 == 1))
+            .map(|filled| if filled { Some(color) } else { None })
+            .collect();
+        TetrisCellScreen::from_cells(cells, dim)
+    }
+
+    fn cells_as_bitboard(&self) -> Vec<u64> {
+        self.iter_rows()
+            .flat_map(|row| row.chunks(64))
+            .map(bitboard_word)
+            .collect()
+    }
+
+    #[cfg(any(test, feature = "debug-tools"))]
+    fn render_to_buffer(&self, buf: &mut [u8], stride: usize, colors: &ColorScheme) {
+        let Dimensions(x_off, y_off) = self.global_offset();
+        let Dimensions(columns, lines) = self.dim;
+        let Dimensions(cell_width, cell_height) = self.cell_size();
+        let Dimensions(spacing_x, spacing_y) = self.drawn_cell_spacing().unwrap_or(Dimensions(0, 0));
+        let Dimensions(width, height) = self.window_size();
+        assert!(stride >= width * 4 && buf.len() >= stride * (height - 1) + width * 4);
+
+        let mut fill = |left: usize, top: usize, w: usize, h: usize, (r, g, b): (u8, u8, u8)| {
+            for y in top .. top + h {
+                for pixel in buf[y * stride + left * 4 .. y * stride + (left + w) * 4].chunks_mut(4) {
+                    pixel.copy_from_slice(&[r, g, b, 255]);
+                }
+            }
+        };
+        fill(0, 0, width, height, (0, 0, 0));
+        let (board_width, board_height) = (cell_width * columns, cell_height * lines);
+        for &(left, top, w, h) in &[(x_off, y_off, board_width, 1),
+                                    (x_off, y_off + board_height - 1, board_width, 1),
+                                    (x_off, y_off, 1, board_height),
+                                    (x_off + board_width - 1, y_off, 1, board_height)] {
+            fill(left, top, w, h, (127, 127, 127));
         }
+
+        let ghost_cells = self.ghost_cells();
+        self.with_composed_cells(&mut |cells| {
+            for (i, cell) in cells.iter().enumerate() {
+                let (x, y) = (i % columns, i / columns);
+                let rgb = match (self.row_color(y), cell, ghost_cells[i]) {
+                    (Some(rgb), _, _) => rgb,
+                    (None, &None, Some(color)) if ! self.monochrome() =>
+                        color.to_dim_rgb(GHOST_ALPHA, colors),
+                    (None, &None, _) => (0, 0, 0),
+                    (None, &Some(_), _) if self.monochrome() => (255, 255, 255),
+                    (None, &Some(color), _) => color.to_rgb(colors),
+                };
+                fill(x_off + x * cell_width + spacing_x, y_off + y * cell_height + spacing_y,
+                     cell_width - spacing_x * 2, cell_height - spacing_y * 2, rgb);
+            }
+        });
+    }
+
+    fn board_hash(&self) -> u64 {
+        self.cells.iter()
+            .map(|cell| cell.map_or(0, |color| color.to_index() as u64 + 1))
+            .fold(FNV_OFFSET_BASIS, fnv_mix)
+    }
+
+    /// Compares from the bottom up, where boards that differ usually do, without packing the
+    /// whole board first.
+    fn bitboard_eq(&self, other: &[u64]) -> bool {
+        let words_per_row = bitboard_words_per_row(self.dim.0);
+        other.len() == words_per_row * self.dim.1
+            && self.iter_rows().rev().zip(other.chunks(words_per_row).rev())
+                .all(|(row, words)| row.chunks(64).map(bitboard_word).eq(words.iter().cloned()))
     }
 
     fn has_figure(&self) -> bool {
@@ -170,738 +835,4756 @@ impl TetrisCellScreen {
     }
 
     fn set_figure(&mut self, point: Point, color: TetrisCellColor, figure: Figure) {
-        match &self._figure {
-            &Some((_, old_color, ref old_figure))
-                if old_color == color && old_figure == &figure => {},
-            _ => {
-                let bitmap = figure.bitmap();
-                self._figure_layer.clear();
-                for flag in bitmap.iter() {
-                    self._figure_layer.push(
-                        if *flag { Some(color) } else { None });
-                }
-            },
-        };
+        self._figure_layer.clear();
+        self._figure_layer.extend(
+            figure.bitmap().iter().map(|&filled| if filled { Some(color) } else { None }));
 
         self._figure = Some((point, color, figure));
+        self._lock_progress = None;
+        self._dirty.set(true);
     }
-}
 
+    fn clear_figure(&mut self) {
+        self._figure = None;
+        self._lock_progress = None;
+        self._dirty.set(true);
+    }
 
-impl CellScreen for TetrisCellScreen {
-    fn reset(&mut self) {
-        for cell in self.cells.iter_mut() {
-            *cell = None;
+    fn set_cell_size(&mut self, cell_size: usize) {
+        self._cell_size = cell_size;
+    }
+
+    fn set_top_out_rows(&mut self, rows: usize) {
+        let cells = min(rows, self.dim.1) * self.dim.0;
+        if self._top_out_layer.len() != cells {
+            self._top_out_layer.resize(cells, Some(TetrisCellColor::Gray));
+            self._dirty.set(true);
         }
-        self._figure = None;
     }
 
-    fn set_cell(&mut self, point: Point, cell: Option<TetrisCellColor>) {
-        let dim = self.dimensions();
-        self.cells[point.1 * dim.0 + point.0] = cell;
+    fn set_row_colors(&mut self, row_colors: Vec<(usize, (u8, u8, u8))>) {
+        self._row_colors = row_colors;
     }
 
-    fn dimensions(&self) -> Dimensions {
-        self.dim.clone()
+    fn set_invisible(&mut self, invisible: bool) {
+        if self._invisible != invisible {
+            self._invisible = invisible;
+            self._dirty.set(true);
+        }
     }
 
-    fn layers<'a>(&'a self) -> Vec<(Point, Dimensions, &'a [Option<TetrisCellColor>])> {
-        let mut layers = Vec::with_capacity(2);
-        layers.push((
-            Point(0, 0),
-            self.dim.clone(),
-            self.cells.as_ref()));
-        if let Some((ref point, _, ref figure)) = self.get_figure() {
-            layers.push((
-                point.clone(),
-                figure.dimensions(),
-                self._figure_layer.borrow()));
+    /// Whether the figure at `point` sticks out of the board or covers a locked cell. The bounding
+    /// box is read from `FIGURE_SHAPES`, built at compile time, so the edge check needs no cells.
+    fn figure_overlaps(&self, point: Point, figure: &Figure) -> bool {
+        let (fig_width, fig_height) = figure.bounding_box();
+        let Dimensions(columns, lines) = self.dim;
+        if fig_width > columns.saturating_sub(point.0) || fig_height > lines.saturating_sub(point.1) {
+            return true;
         }
-        layers
+        figure.cells_iter(point).any(|cell| self[cell].is_some())
     }
 
-    fn cell_size(&self) -> Dimensions {
-        Dimensions(self._cell_size, self._cell_size)
+    fn iter_cells<'a>(&'a self) -> impl Iterator<Item = (Point, Option<TetrisCellColor>)> + 'a {
+        let columns = self.dim.0;
+        self.cells.iter().enumerate().map(move |(i, &cell)| (Point(i % columns, i / columns), cell))
     }
 
-    fn cell_spacing(&self) -> Dimensions {
-        let spacing = max(self._cell_size / 20, 1);
-        Dimensions(spacing, spacing)
+    fn iter_rows<'a>(&'a self) -> std::slice::Chunks<'a, Option<TetrisCellColor>> {
+        self.cells.chunks(self.dim.0)
     }
 
-    fn global_offset(&self) -> Dimensions {
-        Dimensions(10, 10)
+    #[cfg(any(test, not(feature = "simd")))]
+    fn filled_lines_reference(&self) -> Vec<usize> {
+        self.iter_rows().enumerate()
+            .filter(|&(_, row)| row.iter().all(|cell| cell.is_some()))
+            .map(|(line, _)| line)
+            .collect()
     }
 
-    fn window_size(&self) -> Dimensions {
-        let off = self.global_offset();
-        let cs = self.cell_size();
-        let dim = self.dimensions();
-        Dimensions(
-            (off.0 * 2 + cs.0 * dim.0) as usize,
-            (off.1 * 2 + cs.1 * dim.1) as usize,
-            )
+    #[cfg(not(feature = "simd"))]
+    fn filled_lines(&self) -> Vec<usize> {
+        self.filled_lines_reference()
     }
-}
 
+    #[cfg(feature = "simd")]
+    fn filled_lines(&self) -> Vec<usize> {
+        let columns = self.dim.0;
+        self.cells_as_bitboard().chunks(bitboard_words_per_row(columns)).enumerate()
+            .filter(|&(_, row)| {
+                row.iter().map(|word| word.count_ones() as usize).sum::<usize>() == columns
+            })
+            .map(|(line, _)| line)
+            .collect()
+    }
 
-struct TetrisGame<Random: rand::Rng> {
-    cell_screen: TetrisCellScreen,
-    rng: Random,
-    figures_generated: usize,
-}
+    fn column_heights(&self) -> Vec<u32> {
+        let Dimensions(columns, lines) = self.dim;
+        (0 .. columns).map(|x| {
+            match (0 .. lines).find(|&y| self.cells[y * columns + x].is_some()) {
+                Some(y) => (lines - y) as u32,
+                None => 0,
+            }
+        }).collect()
+    }
 
+    fn hole_count(&self) -> u32 {
+        let mut covered = vec![false; self.dim.0];
+        let mut holes = 0;
+        for row in self.iter_rows() {
+            for (covered, cell) in covered.iter_mut().zip(row) {
+                if cell.is_some() {
+                    *covered = true;
+                } else if *covered {
+                    holes += 1;
+                }
+            }
+        }
+        holes
+    }
 
-fn precise_time_ms() -> u64 {
-    time::precise_time_ns() / 1_000_000
-}
+    fn aggregate_transitions(&self) -> u32 {
+        self.iter_rows().map(|row| {
+            let filled = row.iter().map(|cell| cell.is_some());
+            std::iter::once(true).chain(filled.clone())
+                .zip(filled.chain(std::iter::once(true)))
+                .filter(|&(left, right)| left != right)
+                .count() as u32
+        }).sum()
+    }
 
+    fn vertical_transitions(&self) -> u32 {
+        let Dimensions(columns, lines) = self.dim;
+        (0 .. columns).map(|x| {
+            let filled = (0 .. lines).map(|y| self.cells[y * columns + x].is_some());
+            filled.clone()
+                .zip(filled.skip(1).chain(std::iter::once(true)))
+                .filter(|&(above, below)| above != below)
+                .count() as u32
+        }).sum()
+    }
 
-impl <Random: rand::Rng> Game for TetrisGame<Random> {
-    fn run(&mut self, event_pump: &mut sdl2::EventPump, renderer: &mut Renderer) {
-        let mut is_paused = false;
-        let mut running = true;
+    fn locked_cell_count(&self) -> usize {
+        self.iter_cells().filter(|&(_, cell)| cell.is_some()).count()
+    }
+
+    fn to_compact_string(&self) -> String {
+        let mut result = String::with_capacity((self.dim.0 + 1) * self.dim.1);
+        for row in self.iter_rows() {
+            for cell in row {
+                result.push(match *cell {
+                    Some(ref color) => color.symbol(),
+                    None => '.',
+                });
+            }
+            result.push('\n');
+        }
+        result
+    }
+
+    fn add_garbage_line(&mut self, gap: usize) {
+        let Dimensions(columns, lines) = self.dim;
+        self.cells.drain(.. columns);
+        for x in 0 .. columns {
+            self.cells.push(if x == gap { None } else { Some(TetrisCellColor::Gray) });
+        }
+        debug_assert_eq!(self.cells.len(), columns * lines);
+        self._dirty.set(true);
+    }
+
+    fn clear_bottom_half(&mut self) -> usize {
+        let Dimensions(columns, lines) = self.dim;
+        let rows = lines - lines / 2;
+        let kept = self.cells.len() - rows * columns;
+        let mut cells = Vec::with_capacity(self.cells.len());
+        cells.extend(std::iter::repeat_n(None, rows * columns));
+        cells.extend_from_slice(&self.cells[.. kept]);
+        self.cells = cells;
+        self._dirty.set(true);
+        rows
+    }
+
+    fn compaction_preview(&self, lines: &[usize]) -> TetrisCellScreen {
+        let kept_cells = self.iter_rows().enumerate()
+            .filter(|&(line, _)| ! lines.contains(&line))
+            .flat_map(|(_, row)| row.iter().cloned())
+            .collect::<Vec<_>>();
+        let mut preview = self.clone();
+        preview.cells = std::iter::repeat_n(None, self.cells.len() - kept_cells.len())
+            .chain(kept_cells)
+            .collect();
+        preview._dirty.set(true);
+        preview
+    }
+
+    /// Changes the board size keeping the stack on the floor: rows are added or removed at the
+    /// top and columns at the right edge. Fails and leaves the board as it is if a locked cell
+    /// would be cut off. The falling figure moves with the floor and is dropped if it no longer
+    /// fits.
+    fn resize(&mut self, new_dim: Dimensions) -> Result<(), GameError> {
+        let Dimensions(old_columns, old_lines) = self.dim;
+        let Dimensions(columns, lines) = new_dim;
+        let needed = self.iter_cells()
+            .filter(|&(_, cell)| cell.is_some())
+            .fold(Dimensions(0, 0), |Dimensions(width, height), (Point(x, y), _)| {
+                Dimensions(max(width, x + 1), max(height, old_lines - y))
+            });
+        if needed.0 > columns || needed.1 > lines {
+            return Err(GameError::BoardTooSmall { dim: new_dim, needed });
+        }
+
+        let mut cells = vec![None; columns * lines];
+        let width = min(old_columns, columns);
+        for y in 0 .. min(old_lines, lines) {
+            let from = (old_lines - 1 - y) * old_columns;
+            let to = (lines - 1 - y) * columns;
+            cells[to .. to + width].copy_from_slice(&self.cells[from .. from + width]);
+        }
+        self.cells = cells;
+        self.dim = new_dim;
+        self._top_out_layer.clear();
+        self._row_colors.clear();
+
+        let figure = self._figure.take().and_then(|(Point(x, y), color, figure)| {
+            let (width, height) = figure.bounding_box();
+            (y + lines).checked_sub(old_lines)
+                .filter(|&y| x + width <= columns && y + height <= lines)
+                .map(|y| (Point(x, y), color, figure))
+        });
+        self.clear_figure();
+        if let Some((point, color, figure)) = figure {
+            self.set_figure(point, color, figure);
+        }
+        Ok(())
+    }
+
+    fn set_lock_progress(&mut self, progress: Option<f32>) {
+        self._lock_progress = progress;
+    }
+
+    fn set_drop_distance(&mut self, distance: usize) {
+        self._drop_distance = distance;
+    }
+}
+
+
+impl Index<Point> for TetrisCellScreen {
+    type Output = Option<TetrisCellColor>;
+
+    fn index(&self, point: Point) -> &Option<TetrisCellColor> {
+        let Dimensions(columns, lines) = self.dim;
+        if point.0 >= columns || point.1 >= lines {
+            panic!("{:?} is out of the {}x{} board", point, columns, lines);
+        }
+        &self.cells[point.1 * columns + point.0]
+    }
+}
+
+
+impl CellScreen for TetrisCellScreen {
+    fn reset(&mut self) {
+        for cell in self.cells.iter_mut() {
+            *cell = None;
+        }
+        self.clear_figure();
+    }
+
+    fn set_cell(&mut self, point: Point, cell: Option<TetrisCellColor>) {
+        let dim = self.dimensions();
+        self.cells[point.1 * dim.0 + point.0] = cell;
+        self._dirty.set(true);
+    }
+
+    fn copy_cells_into(&self, dest: &mut Vec<Option<TetrisCellColor>>) {
+        dest.clear();
+        dest.extend_from_slice(&self.cells);
+    }
+
+    fn dimensions(&self) -> Dimensions {
+        self.dim.clone()
+    }
+
+    fn with_composed_cells(&self, f: &mut dyn FnMut(&[Option<TetrisCellColor>])) {
+        if self._dirty.get() {
+            self.compose_cells_into(&mut self._composed.borrow_mut());
+            self._dirty.set(false);
+        }
+        f(&self._composed.borrow())
+    }
+
+    fn layers<'a>(&'a self) -> Vec<(Point, Dimensions, &'a [Option<TetrisCellColor>])> {
+        let mut layers = Vec::with_capacity(3);
+        if ! self._invisible {
+            layers.push((
+                Point(0, 0),
+                self.dim,
+                self.cells.as_ref()));
+        }
+        if let Some((ref point, _, ref figure)) = self.get_figure() {
+            layers.push((
+                point.clone(),
+                figure.bounding_box().into(),
+                self._figure_layer.borrow()));
+        }
+        if ! self._top_out_layer.is_empty() {
+            let rows = self._top_out_layer.len() / self.dim.0;
+            layers.push((
+                Point(0, self.dim.1 - rows),
+                Dimensions(self.dim.0, rows),
+                self._top_out_layer.borrow()));
+        }
+        layers
+    }
+
+    fn cell_size(&self) -> Dimensions {
+        Dimensions(self._cell_size, self._cell_size)
+    }
+
+    fn cell_spacing(&self) -> Dimensions {
+        let spacing = min(max(self._cell_size / 20, 1), self._cell_size.saturating_sub(1) / 2);
+        Dimensions(spacing, spacing)
+    }
+
+    fn global_offset(&self) -> Dimensions {
+        Dimensions(10, 10)
+    }
+
+    fn lock_progress(&self) -> Option<(Point, Dimensions, f32)> {
+        match (&self._figure, self._lock_progress) {
+            (&Some((point, _, ref figure)), Some(progress)) =>
+                Some((point, figure.bounding_box().into(), progress)),
+            _ => None,
+        }
+    }
+
+    fn ghost_layer(&self) -> Option<(Point, Dimensions, &[Option<TetrisCellColor>])> {
+        match self._figure {
+            Some((Point(x, y), _, ref figure)) if self._drop_distance > 0 =>
+                Some((Point(x, y + self._drop_distance), figure.bounding_box().into(),
+                      self._figure_layer.borrow())),
+            _ => None,
+        }
+    }
+
+    fn mirrored(&self) -> bool {
+        self._mirrored
+    }
+
+    fn monochrome(&self) -> bool {
+        self._monochrome
+    }
+
+    fn row_color(&self, y: usize) -> Option<(u8, u8, u8)> {
+        self._row_colors.iter().find(|&&(row, _)| row == y).map(|&(_, color)| color)
+    }
+
+    fn colors(&self) -> &ColorScheme {
+        &self._colors
+    }
+
+    fn window_size(&self) -> Dimensions {
+        let off = self.global_offset();
+        let cs = self.cell_size();
+        let dim = self.dimensions();
+        Dimensions(
+            (off.0 * 2 + cs.0 * dim.0) as usize,
+            (off.1 * 2 + cs.1 * dim.1) as usize,
+            )
+    }
+}
+
+
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+struct SpawnModifiers {
+    rotate: bool,
+}
+
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum GamePhase {
+    Countdown { remaining: u8, last_tick_ms: u64 },
+    Playing,
+    GameOver,
+}
+
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum GameMode {
+    Endless,
+    Sprint,
+    Ultra,
+    Invisible,
+    Cheese,
+    HotSeat,
+    Zen,
+}
+
+
+const GAME_MODES: [GameMode; 7] = [
+    GameMode::Endless,
+    GameMode::Sprint,
+    GameMode::Ultra,
+    GameMode::Invisible,
+    GameMode::Cheese,
+    GameMode::HotSeat,
+    GameMode::Zen,
+    ];
+
+const SPRINT_LINES: usize = 40;
+const ULTRA_TIME_MS: u64 = 2 * 60 * 1000;
+const CHEESE_LINES: usize = 10;
+const ZEN_GRAVITY_ROWS_PER_SECOND: f64 = 1.0;
+
+
+impl GameMode {
+    fn name(&self, strings: &strings::Strings) -> &'static str {
+        strings.get(match *self {
+            GameMode::Endless => "mode_endless",
+            GameMode::Sprint => "mode_sprint",
+            GameMode::Ultra => "mode_ultra",
+            GameMode::Invisible => "mode_invisible",
+            GameMode::Cheese => "mode_cheese",
+            GameMode::HotSeat => "mode_hot_seat",
+            GameMode::Zen => "mode_zen",
+        })
+    }
+}
+
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum GarbageStyle {
+    Messy,
+    Clean,
+    Shifting,
+}
+
+
+const GARBAGE_STYLES: [GarbageStyle; 3] = [
+    GarbageStyle::Messy,
+    GarbageStyle::Clean,
+    GarbageStyle::Shifting,
+    ];
+
+
+impl GarbageStyle {
+    fn name(&self) -> &'static str {
+        match *self {
+            GarbageStyle::Messy => "messy",
+            GarbageStyle::Clean => "clean",
+            GarbageStyle::Shifting => "shifting",
+        }
+    }
+
+    fn from_name(name: &str) -> Option<Self> {
+        GARBAGE_STYLES.iter().find(|style| style.name() == name).cloned()
+    }
+
+    fn to_index(self) -> usize {
+        GARBAGE_STYLES.iter().position(|&style| style == self).unwrap()
+    }
+}
+
+
+fn garbage_gaps<Random: rand::Rng>(style: GarbageStyle, rows: usize, columns: usize,
+                                   rng: &mut Random) -> Vec<usize> {
+    if rows == 0 {
+        return Vec::new();
+    }
+    let first = rng.gen_range(0, columns);
+    (0 .. rows).map(|row| match style {
+        GarbageStyle::Messy if row > 0 => rng.gen_range(0, columns),
+        GarbageStyle::Messy | GarbageStyle::Clean => first,
+        GarbageStyle::Shifting => (first + row) % columns,
+    }).collect()
+}
+
+
+fn format_game_time(time_ms: u64) -> String {
+    format!("{}:{:02}.{}", time_ms / 60000, time_ms / 1000 % 60, time_ms / 100 % 10)
+}
+
+
+#[derive(Clone, Debug)]
+struct GameConfig {
+    mode: GameMode,
+    columns: usize,
+    lines: usize,
+    cell_size: usize,
+    randomizer: FigureRandomizer,
+    show_countdown: bool,
+    starting_garbage: usize,
+    garbage_style: GarbageStyle,
+    starting_level: usize,
+    gravity_rows_per_second: f64,
+    soft_drop_factor: f64,
+    mirrored: bool,
+    invisible: bool,
+    big_mode: bool,
+    high_contrast: bool,
+    colors: ColorScheme,
+    #[cfg(feature = "sdl")]
+    key_bindings: KeyBindings,
+    drop_protection: bool,
+    drop_protection_ms: u64,
+    preserve_das: bool,
+    spawn_grace: bool,
+    undo_enabled: bool,
+    undo_history: usize,
+    allow_hold: bool,
+    hold_resets_on_lock: bool,
+    endless_cheese: bool,
+    record_log: bool,
+    debug_timing: bool,
+    debug_board: bool,
+    strings: strings::Strings,
+}
+
+
+impl Default for GameConfig {
+    fn default() -> Self {
+        GameConfig {
+            mode: GameMode::Endless,
+            columns: 10,
+            lines: 16,
+            cell_size: 40,
+            randomizer: FigureRandomizer::default(),
+            show_countdown: true,
+            starting_garbage: 0,
+            garbage_style: GarbageStyle::Messy,
+            starting_level: 0,
+            gravity_rows_per_second: 2.0,
+            soft_drop_factor: 30.0,
+            mirrored: false,
+            invisible: false,
+            big_mode: false,
+            high_contrast: false,
+            colors: ColorScheme::default(),
+            #[cfg(feature = "sdl")]
+            key_bindings: KeyBindings::default(),
+            drop_protection: false,
+            drop_protection_ms: 80,
+            preserve_das: false,
+            spawn_grace: false,
+            undo_enabled: false,
+            undo_history: 10,
+            allow_hold: true,
+            hold_resets_on_lock: true,
+            endless_cheese: false,
+            record_log: false,
+            debug_timing: false,
+            debug_board: false,
+            strings: strings::Strings::default(),
+        }
+    }
+}
+
+
+impl GameConfig {
+    fn validate(&self) -> Result<(), String> {
+        if self.columns < MAX_FIGURE_SIZE {
+            return Err(format!("--columns must be at least {} to fit a line figure, got {}",
+                               MAX_FIGURE_SIZE, self.columns));
+        }
+        if self.lines < MAX_FIGURE_SIZE {
+            return Err(format!("--lines must be at least {} to fit a line figure, got {}",
+                               MAX_FIGURE_SIZE, self.lines));
+        }
+        if self.cell_size < MIN_CELL_SIZE {
+            return Err(format!("--cell-size must be at least {} pixels, got {}",
+                               MIN_CELL_SIZE, self.cell_size));
+        }
+        if self.starting_garbage + 2 > self.lines {
+            return Err(format!("--garbage must be between 0 and {} on a board with {} lines, got {}",
+                               self.lines - 2, self.lines, self.starting_garbage));
+        }
+        if self.gravity_rows_per_second.is_nan() || self.gravity_rows_per_second <= 0.0 {
+            return Err(format!("--gravity must be a positive number of lines per second or inf, \
+                                got {}", self.gravity_rows_per_second));
+        }
+        if self.soft_drop_factor.is_nan() || self.soft_drop_factor < 1.0 {
+            return Err(format!("--soft-drop-factor must be at least 1 or inf, got {}",
+                               self.soft_drop_factor));
+        }
+        Ok(())
+    }
+
+    fn screen_cell_size(&self) -> usize {
+        if self.big_mode { self.cell_size * 2 } else { self.cell_size }
+    }
+}
+
+
+struct TetrisGame<Random: rand::Rng + Clone> {
+    config: GameConfig,
+    phase: GamePhase,
+    spawn_modifiers: SpawnModifiers,
+    cell_screen: TetrisCellScreen,
+    rng: Random,
+    figures_generated: usize,
+    piece_stats: PieceStats,
+    lines_cleared: usize,
+    gravity_rows_per_second: f64,
+    garbage_remaining: usize,
+    last_garbage_gap: Option<usize>,
+    /// Incoming garbage attacks in rows, oldest first, added on the next lock.
+    pending_garbage: VecDeque<usize>,
+    state_hash: u64,
+    held_figure: Option<Figure>,
+    hold_used: bool,
+    /// Game time of the first gravity tick of the current figure, unset until that tick.
+    figure_spawned_ms: Option<u64>,
+    lock_deadline_ms: Option<u64>,
+    level_flash_until_ms: Option<u64>,
+    lines_cleared_this_piece: u32,
+    clear_flash: Option<ClearFlash>,
+    game_over_error: Option<GameError>,
+    race: Option<replay::GhostRace>,
+    gif: Option<gif::GifRecorder>,
+    achievements: Option<achievements::Achievements>,
+    toast: Option<(String, u64)>,
+    placement_log: Option<placements::PlacementLog>,
+    event_log: Option<eventlog::GameEventLog>,
+    spectators: Option<spectate::Spectators>,
+    history: VecDeque<GameState<Random>>,
+    /// The figure that the current run of soft drop rows started on, which all undo as one step.
+    soft_drop_figure: Option<usize>,
+    drop_trails: Vec<DropTrail>,
+}
+
+
+#[derive(Clone, Debug, PartialEq)]
+struct ClearFlash {
+    lines: Vec<usize>,
+    size: usize,
+    until_ms: u64,
+}
+
+
+impl ClearFlash {
+    fn color(&self) -> (u8, u8, u8) {
+        match self.size {
+            1 | 2 => (255, 255, 255),
+            3 => (255, 255, 0),
+            _ => (0, 255, 255),
+        }
+    }
+}
+
+
+#[derive(Clone, Debug, PartialEq)]
+struct DropTrail {
+    columns: Vec<usize>,
+    from_line: usize,
+    to_line: usize,
+    color: TetrisCellColor,
+    started_ms: u64,
+}
+
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum HudButton {
+    Pause,
+    Restart,
+    Quit,
+}
+
+
+const HUD_BUTTONS: [(HudButton, &str); 3] = [
+    (HudButton::Pause, "pause"),
+    (HudButton::Restart, "restart"),
+    (HudButton::Quit, "quit"),
+    ];
+
+
+const LINES_PER_LEVEL: usize = 10;
+const GRAVITY_SPEED_UP: f64 = 4.0 / 3.0;
+const FAST_MOVE_DOWN_MS: u64 = 15;
+const GIF_CELL_PIXELS: usize = 8;
+#[cfg(feature = "debug-tools")]
+const SLOW_MOTION_FACTOR: u64 = 4;
+const LEVEL_FLASH_MS: u64 = 500;
+const CLEAR_FLASH_MS: u64 = 200;
+const LOCK_DELAY_MS: u64 = 500;
+const TOAST_MS: u64 = 3000;
+const DROP_TRAIL_MS: u64 = 150;
+const DROP_TRAIL_ALPHA: u8 = 128;
+const GHOST_ALPHA: u8 = 96;
+const WINDOW_TITLE: &str = "Tetris";
+const WINDOW_ICON_SIZE: usize = 64;
+const MAX_FIGURE_SIZE: usize = 4;
+const MIN_CELL_SIZE: usize = 5;
+const MAX_CATCH_UP_TICKS: u64 = 3;
+const SOFT_DROP_FACTORS: [f64; 6] = [2.0, 6.0, 10.0, 20.0, 30.0, f64::INFINITY];
+const ROTATE_180_KICKS: [PointOffset; 4] = [
+    PointOffset(0, 0),
+    PointOffset(1, 0),
+    PointOffset(-1, 0),
+    PointOffset(0, -1),
+    ];
+
+
+#[cfg(not(all(feature = "web", target_arch = "wasm32")))]
+fn precise_time_ms() -> u64 {
+    time::precise_time_ns() / 1_000_000
+}
+
+
+/// `time` has no clock in a browser, so there the game runs on the time passed to `web::tick`.
+#[cfg(all(feature = "web", target_arch = "wasm32"))]
+fn precise_time_ms() -> u64 {
+    web::clock_ms()
+}
+
+
+fn faster_gravity(rows_per_second: f64) -> f64 {
+    (rows_per_second * GRAVITY_SPEED_UP).min(1000.0 / FAST_MOVE_DOWN_MS as f64).max(rows_per_second)
+}
+
+
+fn seeded_rng(seed: u32) -> rand::XorShiftRng {
+    rand::XorShiftRng::from_seed([seed, 0x193a6754, 0xa8a7d469, 0x97830e05])
+}
+
+
+struct GameTimer {
+    gravity_rows_per_second: f64,
+    drop_progress: f64,
+    soft_drop_factor: f64,
+    move_das_ms: u64,
+    move_arr_ms: u64,
+    last_drop_ms: Option<u64>,
+    last_soft_drop_ms: Option<u64>,
+    last_move_ms: Option<u64>,
+    last_shift_ms: Option<u64>,
+    move_repeating: bool,
+    time_scale: u64,
+}
+
+
+fn next_tick_ms(last_ms: u64, period_ms: u64, now_ms: u64) -> u64 {
+    if now_ms >= last_ms + period_ms * MAX_CATCH_UP_TICKS {
+        now_ms
+    } else {
+        last_ms + period_ms
+    }
+}
+
+
+impl GameTimer {
+    fn new(gravity_rows_per_second: f64, soft_drop_factor: f64) -> Self {
+        GameTimer {
+            gravity_rows_per_second,
+            drop_progress: 0.0,
+            soft_drop_factor,
+            move_das_ms: 120,
+            move_arr_ms: 120,
+            last_drop_ms: None,
+            last_soft_drop_ms: None,
+            last_move_ms: None,
+            last_shift_ms: None,
+            move_repeating: false,
+            time_scale: 1,
+        }
+    }
+
+    fn scaled_gravity(&self) -> f64 {
+        self.gravity_rows_per_second / self.time_scale as f64
+    }
+
+    fn scaled(&self, period_ms: u64) -> u64 {
+        period_ms * self.time_scale
+    }
+
+    fn reset_drop(&mut self, now_ms: u64) {
+        self.last_drop_ms = Some(now_ms);
+        self.drop_progress = 0.0;
+    }
+
+    fn rows_to_drop(&mut self, now_ms: u64) -> usize {
+        if self.gravity_rows_per_second.is_infinite() {
+            return usize::MAX;
+        }
+        self.drop_progress += match self.last_drop_ms {
+            Some(last) => self.scaled_gravity() * (now_ms - last) as f64 / 1000.0,
+            None => 1.0,
+        };
+        self.last_drop_ms = Some(now_ms);
+        let rows = self.drop_progress.floor();
+        self.drop_progress -= rows;
+        rows as usize
+    }
+
+    fn soft_drop_period_ms(&self) -> u64 {
+        max((1000.0 / (self.gravity_rows_per_second * self.soft_drop_factor)) as u64, 1)
+    }
+
+    fn should_soft_drop(&mut self, now_ms: u64, held: bool) -> bool {
+        if ! held {
+            self.last_soft_drop_ms = None;
+            return false;
+        }
+        let period = self.scaled(self.soft_drop_period_ms());
+        match self.last_soft_drop_ms {
+            Some(last) if last + period > now_ms => false,
+            Some(last) => {
+                self.last_soft_drop_ms = Some(next_tick_ms(last, period, now_ms));
+                true
+            },
+            None => {
+                self.last_soft_drop_ms = Some(now_ms);
+                true
+            },
+        }
+    }
+
+    fn should_move(&mut self, now_ms: u64, held: bool) -> bool {
+        if ! held {
+            self.last_move_ms = None;
+            self.move_repeating = false;
+            return false;
+        }
+        let period = self.scaled(if self.move_repeating { self.move_arr_ms } else { self.move_das_ms });
+        let should_move = match self.last_move_ms {
+            Some(last) if last + period > now_ms => false,
+            Some(last) => {
+                self.last_move_ms = Some(next_tick_ms(last, period, now_ms));
+                self.move_repeating = true;
+                true
+            },
+            None => {
+                self.last_move_ms = Some(now_ms);
+                true
+            },
+        };
+        if should_move {
+            self.last_shift_ms = Some(now_ms);
+        }
+        should_move
+    }
+
+    fn shifted_within(&self, now_ms: u64, window_ms: u64) -> bool {
+        match self.last_shift_ms {
+            Some(last) => now_ms < last + self.scaled(window_ms),
+            None => false,
+        }
+    }
+
+    fn restart_move(&mut self) {
+        self.last_move_ms = None;
+        self.move_repeating = false;
+    }
+
+    fn charge_move(&mut self, now_ms: u64) {
+        self.last_move_ms = Some(now_ms.saturating_sub(self.scaled(self.move_arr_ms)));
+        self.move_repeating = true;
+    }
+
+    fn figure_spawned(&mut self, now_ms: u64, held: bool, preserve_das: bool) {
+        if ! held {
+            self.restart_move();
+        } else if preserve_das {
+            self.charge_move(now_ms);
+        } else {
+            self.last_move_ms = Some(now_ms);
+            self.move_repeating = false;
+        }
+    }
+
+    fn ms_until_next(&self, now_ms: u64) -> Option<u64> {
+        let next_move = self.last_move_ms.map(|last| {
+            last + self.scaled(if self.move_repeating { self.move_arr_ms } else { self.move_das_ms })
+        });
+        [
+            self.last_drop_ms
+                .filter(|_| self.gravity_rows_per_second.is_finite())
+                .map(|last| {
+                    let rows_left = 1.0 - self.drop_progress;
+                    last + (rows_left * 1000.0 / self.scaled_gravity()).ceil() as u64
+                }),
+            self.last_soft_drop_ms.map(|last| last + self.scaled(self.soft_drop_period_ms())),
+            next_move,
+        ].iter()
+            .filter_map(|&deadline| deadline)
+            .min()
+            .map(|deadline| deadline.saturating_sub(now_ms))
+    }
+}
+
+
+#[cfg(feature = "sdl")]
+impl <Random: rand::Rng + Clone> Game for TetrisGame<Random> {
+    fn run(&mut self, event_pump: &mut sdl2::EventPump, renderer: &mut Renderer) -> GameExit {
+        let mut is_paused = false;
+        let mut exit = GameExit::Quit;
+        let mut confirming_exit = false;
+        let mut resume_after_confirm = false;
+        let mut result_banner: Option<String> = None;
+
+        const COUNTDOWN_TICK_MS: u64 = 1000;
+        const GO_BANNER_MS: u64 = 500;
+        self.phase = if self.config.show_countdown {
+            GamePhase::Countdown { remaining: 3, last_tick_ms: precise_time_ms() }
+        } else {
+            GamePhase::Playing
+        };
+
+        let mut game_started_ms = precise_time_ms();
+        let mut paused_since_ms = 0;
+        let mut paused_total_ms = 0;
+
+        let mut timer = GameTimer::new(self.gravity_rows_per_second, self.config.soft_drop_factor);
+        timer.reset_drop(precise_time_ms());
+
+        const SPEED_UP_AFTER_FIGURE_COUNT: usize = 100;
+        let mut last_speed_up_was_at_figure = 0;
+
+        let mut figure_when_move_down_pressed = None;
+
+
+        const LOCK_BAR_FRAME_MS: u32 = 20;
+
+        const TOP_OUT_MS: u64 = 1000;
+        const TOP_OUT_FRAME_MS: u32 = 20;
+        const MAX_WAIT_MS: u64 = 1000;
+        let mut pending_events = VecDeque::new();
+        let mut top_out_started_ms = None;
+        let mut top_out_done = false;
+
+        let mut keys = input::KeyEdgeDetector::new();
+        let mut debug_title = String::new();
+        let mut piece_age_ms = 0;
+        let bindings = self.config.key_bindings.clone();
+        let mut move_priority = input::MovePriority::default();
+        let mut figure_when_move_charged = self.figures_generated;
+        let mut drop_suppressed = false;
+        let mut buffered_rotate = false;
+        let mut pressed_button = None;
+
+        let mut timings = if self.config.debug_timing {
+            Some(timing::LoopTimings::new(time::precise_time_ns()))
+        } else {
+            None
+        };
+        let mut last_iteration_ns = None;
+        let mut renderer_lost = false;
+
+        'game_loop: loop {
+            let iteration_start_ns = time::precise_time_ns();
+            let drop_distance = self.drop_distance();
+            self.cell_screen.set_drop_distance(drop_distance);
+            let invisible = self.config.invisible && self.phase != GamePhase::GameOver;
+            self.cell_screen.set_invisible(invisible);
+            if self.phase == GamePhase::GameOver && result_banner.is_none() {
+                let now_ms = precise_time_ms();
+                let started_ms = *top_out_started_ms.get_or_insert(now_ms);
+                let lines = self.cell_screen.dimensions().1;
+                let rows = min(lines as u64, (now_ms - started_ms) * lines as u64 / TOP_OUT_MS);
+                self.cell_screen.set_top_out_rows(rows as usize);
+                top_out_done = rows as usize == lines;
+            }
+
+            if let Some(ref spectators) = self.spectators {
+                spectators.send(spectate::snapshot(&self.cell_screen, self.lines_cleared));
+            }
+
+            if let Some(ref mut gif) = self.gif {
+                let frame = raster::rasterize(&self.cell_screen, GIF_CELL_PIXELS);
+                gif.add_frame(precise_time_ms(), frame);
+            }
+            if let (Some(ref mut timings), Some((start_ns, rendered_ns, waited_ns))) =
+                (timings.as_mut(), last_iteration_ns) {
+                timings.record(rendered_ns - start_ns, waited_ns - rendered_ns,
+                               iteration_start_ns - waited_ns);
+                timings.report_if_due(iteration_start_ns);
+            }
+
+            self.update_clear_flash();
+            let mut frame_rendered = false;
+            if pending_events.is_empty() && ! renderer_lost {
+                self.drop_trails.retain(|trail| precise_time_ms() < trail.started_ms + DROP_TRAIL_MS);
+                let strings = self.config.strings;
+                let banner = match self.phase {
+                    _ if confirming_exit => Some(strings.get("quit_confirm").to_string()),
+                    GamePhase::Countdown { remaining, .. } => Some(remaining.to_string()),
+                    GamePhase::Playing if self.config.show_countdown
+                        && precise_time_ms() < game_started_ms + GO_BANNER_MS =>
+                        Some(strings.get("go").to_string()),
+                    GamePhase::GameOver => match result_banner {
+                        Some(ref result) => Some(result.clone()),
+                        None if top_out_done => Some(strings.get("game_over").to_string()),
+                        None => None,
+                    },
+                    _ => None,
+                };
+                frame_rendered = match self.render_frame(renderer, pressed_button, banner.as_deref()) {
+                    Ok(()) => true,
+                    Err(e) => {
+                        println!("Rendering failed, recreating the renderer: {}", e);
+                        match recreate_renderer(renderer).and_then(|()| {
+                            self.render_frame(renderer, pressed_button, banner.as_deref())
+                        }) {
+                            Ok(()) => true,
+                            Err(e) => {
+                                println!("Can not recreate the renderer, the game is paused until \
+                                          P is pressed: {}", e);
+                                renderer_lost = true;
+                                if self.phase == GamePhase::Playing && ! is_paused {
+                                    is_paused = true;
+                                    paused_since_ms = precise_time_ms();
+                                }
+                                false
+                            },
+                        }
+                    },
+                };
+            }
+            if frame_rendered && self.config.debug_board {
+                let title = format!("{} - {} - {} - {:.1}s", WINDOW_TITLE,
+                                    self.config.mode.name(&self.config.strings),
+                                    boarddump::summary(&self.cell_screen),
+                                    piece_age_ms as f64 / 1000.0);
+                if title != debug_title {
+                    set_window_title(renderer, &title);
+                    debug_title = title;
+                }
+            }
+            pressed_button = None;
+            let rendered_ns = time::precise_time_ns();
+
+            let mut pause_pressed = false;
+            let mut undo_pressed = false;
+            #[cfg(feature = "debug-tools")]
+            let mut step_pressed = false;
+
+            let now_ms = precise_time_ms();
+            let wait_timeout: u32 = if let GamePhase::Countdown { last_tick_ms, .. } = self.phase {
+                max(1, (last_tick_ms + COUNTDOWN_TICK_MS).saturating_sub(now_ms)) as u32
+            } else if self.phase == GamePhase::GameOver && result_banner.is_none() && ! top_out_done {
+                TOP_OUT_FRAME_MS
+            } else if is_paused || self.phase == GamePhase::GameOver {
+                self.ms_until_animation(now_ms).unwrap_or(MAX_WAIT_MS).clamp(1, MAX_WAIT_MS) as u32
+            } else {
+                let go_banner_ms = Some(game_started_ms + GO_BANNER_MS)
+                    .filter(|&until_ms| self.config.show_countdown && now_ms < until_ms)
+                    .map(|until_ms| until_ms - now_ms);
+                let result = [timer.ms_until_next(now_ms), self.ms_until_animation(now_ms), go_banner_ms]
+                    .iter()
+                    .filter_map(|&ms| ms)
+                    .min()
+                    .unwrap_or(MAX_WAIT_MS).clamp(1, MAX_WAIT_MS) as u32;
+                if self.lock_deadline_ms.is_some() {
+                    min(LOCK_BAR_FRAME_MS, result)
+                } else {
+                    result
+                }
+            };
+
+            if pending_events.is_empty() {
+                pending_events.extend(event_pump.wait_event_timeout(wait_timeout));
+                while let Some(event) = event_pump.poll_event() {
+                    if let Event::MouseMotion {..} = event {
+                        continue;
+                    }
+                    pending_events.push_back(event);
+                }
+            }
+            let event = pending_events.pop_front();
+            let waited_ns = time::precise_time_ns();
+            last_iteration_ns = Some((iteration_start_ns, rendered_ns, waited_ns));
+
+            match event {
+                Some(Event::KeyDown {keycode: Some(kc), scancode, keymod, repeat: false, ..})
+                    if scancode.and_then(|code| GameInputEvent::from_scancode(code, &bindings))
+                        .is_none() => match kc {
+                    Keycode::Q => { break 'game_loop },
+                    Keycode::Y if confirming_exit => {
+                        exit = GameExit::Title;
+                        break 'game_loop;
+                    },
+                    Keycode::N | Keycode::Escape if confirming_exit => {
+                        confirming_exit = false;
+                        pause_pressed = resume_after_confirm;
+                    },
+                    Keycode::Escape if self.phase == GamePhase::GameOver => {
+                        exit = GameExit::Title;
+                        break 'game_loop;
+                    },
+                    Keycode::Escape => {
+                        confirming_exit = true;
+                        resume_after_confirm = self.phase == GamePhase::Playing && ! is_paused;
+                        pause_pressed = resume_after_confirm;
+                    },
+                    Keycode::P => pause_pressed = true,
+                    Keycode::Z if keymod.intersects(LCTRLMOD | RCTRLMOD) => undo_pressed = true,
+                    Keycode::F6 => self.change_soft_drop_factor(false),
+                    Keycode::F7 => self.change_soft_drop_factor(true),
+                    Keycode::F8 => print!("{}", boarddump::dump(&self.cell_screen)),
+                    #[cfg(feature = "debug-tools")]
+                    Keycode::F2 => {
+                        timer.time_scale = if timer.time_scale == 1 { SLOW_MOTION_FACTOR } else { 1 };
+                    },
+                    #[cfg(feature = "debug-tools")]
+                    Keycode::F3 => step_pressed = true,
+                    #[cfg(feature = "debug-tools")]
+                    Keycode::F4 => self.queue_garbage(2),
+                    #[cfg(feature = "debug-tools")]
+                    Keycode::F5 => {
+                        let path = format!("tetris-{}.ppm", now_ms);
+                        if let Err(e) = self.save_board_image(Path::new(&path)) {
+                            println!("Can not save {}: {}", path, e);
+                        }
+                    },
+                    _ => {},
+                },
+                Some(Event::MouseButtonDown {mouse_btn: Mouse::Left, x, y, ..}) => {
+                    pressed_button = self.hud_button_at(x, y);
+                    match pressed_button {
+                        Some(HudButton::Pause) => pause_pressed = true,
+                        Some(HudButton::Restart) => {
+                            exit = GameExit::Restart;
+                            break 'game_loop;
+                        },
+                        Some(HudButton::Quit) if ! confirming_exit => {
+                            confirming_exit = true;
+                            resume_after_confirm = self.phase == GamePhase::Playing && ! is_paused;
+                            pause_pressed = resume_after_confirm;
+                        },
+                        Some(HudButton::Quit) | None => {},
+                    }
+                },
+                _ => {},
+            }
+
+            keys.update(event.as_ref());
+            let input = input::InputSnapshot::capture(&keys, &bindings, self.config.mirrored,
+                                                      precise_time_ms());
+            if move_priority.update(&input) {
+                timer.restart_move();
+            }
+            let move_direction = move_priority.direction(&input);
+            if input.space.released {
+                drop_suppressed = false;
+            }
+            if self.config.drop_protection && input.space.pressed
+                && timer.shifted_within(input.time_ms, self.config.drop_protection_ms) {
+                drop_suppressed = true;
+            }
+            let move_down_pressed = if drop_suppressed {
+                input.soft_drop_without_space.held
+            } else {
+                input.soft_drop.held
+            };
+            let rotate_pressed = input.rotate.pressed;
+            let rotate_180_pressed = input.rotate_180.pressed;
+            let hold_pressed = input.hold.pressed && self.config.allow_hold;
+            self.spawn_modifiers.rotate = input.rotate.held;
+
+            if pause_pressed && renderer_lost {
+                match recreate_renderer(renderer) {
+                    Ok(()) => renderer_lost = false,
+                    Err(e) => {
+                        println!("Can not recreate the renderer: {}", e);
+                        pause_pressed = false;
+                    },
+                }
+            }
+            if pause_pressed && self.phase == GamePhase::Playing {
+                is_paused = ! is_paused;
+                if is_paused {
+                    paused_since_ms = precise_time_ms();
+                } else {
+                    paused_total_ms += precise_time_ms() - paused_since_ms;
+                }
+            }
+
+
+            if let GamePhase::Countdown { remaining, last_tick_ms } = self.phase {
+                buffered_rotate |= rotate_pressed;
+                let current_time_ms = precise_time_ms();
+                if last_tick_ms + COUNTDOWN_TICK_MS <= current_time_ms {
+                    if remaining > 1 {
+                        self.phase = GamePhase::Countdown {
+                            remaining: remaining - 1,
+                            last_tick_ms: last_tick_ms + COUNTDOWN_TICK_MS,
+                        };
+                    } else {
+                        self.phase = GamePhase::Playing;
+                        game_started_ms = current_time_ms;
+                        timer.reset_drop(current_time_ms);
+                        if move_direction.is_some() {
+                            timer.charge_move(current_time_ms);
+                        }
+                        if buffered_rotate {
+                            buffered_rotate = false;
+                            self.dispatch_event(GameInputEvent::RotateClockwise, 0);
+                        }
+                    }
+                }
+                continue;
+            }
+
+            if self.phase == GamePhase::GameOver { continue }
+            #[cfg(feature = "debug-tools")]
+            {
+                if is_paused && step_pressed {
+                    let game_time_ms = paused_since_ms - game_started_ms - paused_total_ms;
+                    self.dispatch_event(GameInputEvent::Timer, game_time_ms);
+                }
+            }
+
+            if is_paused { continue }
+
+            let current_time_ms = precise_time_ms();
+            let game_time_ms = current_time_ms - game_started_ms - paused_total_ms;
+
+            if let Some(ref mut race) = self.race {
+                race.advance(game_time_ms);
+            }
+            let mode = self.config.mode;
+            self.notify(GameEvent::TimePlayed(mode, game_time_ms));
+
+            if figure_when_move_charged != self.figures_generated {
+                figure_when_move_charged = self.figures_generated;
+                timer.figure_spawned(current_time_ms, move_direction.is_some(),
+                                     self.config.preserve_das);
+            }
+
+            if timer.should_move(current_time_ms, move_direction.is_some()) {
+                if let Some(event) = move_direction {
+                    self.dispatch_event(event, game_time_ms);
+                }
+            }
+
+            if rotate_pressed {
+                self.dispatch_event(GameInputEvent::RotateClockwise, game_time_ms);
+            }
+
+            if rotate_180_pressed {
+                self.dispatch_event(GameInputEvent::Rotate180, game_time_ms);
+            }
+
+            if hold_pressed {
+                self.dispatch_event(GameInputEvent::Hold, game_time_ms);
+            }
+
+            if undo_pressed {
+                self.dispatch_event(GameInputEvent::Undo, game_time_ms);
+            }
+
+            if self.config.mode != GameMode::Zen
+                && last_speed_up_was_at_figure + SPEED_UP_AFTER_FIGURE_COUNT <= self.figures_generated {
+                last_speed_up_was_at_figure = self.figures_generated;
+                timer.gravity_rows_per_second = faster_gravity(timer.gravity_rows_per_second);
+            }
+
+            let soft_dropping = if move_down_pressed {
+                match figure_when_move_down_pressed {
+                    None => {
+                        figure_when_move_down_pressed = Some(self.figures_generated);
+                        true
+                    },
+                    Some(x) => x == self.figures_generated,
+                }
+            } else {
+                figure_when_move_down_pressed = None;
+                false
+            };
+
+            self.gravity_tick(&mut timer, soft_dropping, current_time_ms, game_time_ms);
+            piece_age_ms = self.current_piece_age_ms(game_time_ms);
+
+            let lock_progress = self.lock_deadline_ms.map(|deadline| {
+                deadline.saturating_sub(game_time_ms) as f32 / LOCK_DELAY_MS as f32
+            });
+            self.cell_screen.set_lock_progress(lock_progress);
+
+            let strings = self.config.strings;
+            let result = match self.config.mode {
+                GameMode::Sprint if self.lines_cleared >= SPRINT_LINES =>
+                    Some(format_game_time(game_time_ms)),
+                GameMode::Ultra if game_time_ms >= ULTRA_TIME_MS =>
+                    Some(strings.fill("lines", &[&self.lines_cleared])),
+                GameMode::Cheese if self.garbage_remaining == 0 =>
+                    Some(strings.fill("pieces_time", &[&(self.figures_generated - 1),
+                                                       &format_game_time(game_time_ms)])),
+                _ => None,
+            };
+            if result.is_some() {
+                self.phase = GamePhase::GameOver;
+                result_banner = result;
+            }
+        }
+
+        if let Some(ref mut race) = self.race {
+            race.finish(self.lines_cleared, self.state_hash);
+        }
+        if let Some(ref gif) = self.gif {
+            if let Err(e) = gif.save() {
+                println!("Can not save the recording: {}", e);
+            }
+        }
+        if let Some(ref achievements) = self.achievements {
+            if let Err(e) = achievements.save() {
+                println!("Can not save {}: {}", achievements.path().display(), e);
+            }
+        }
+        self.flush_placement_log();
+        if let Some(e) = self.game_over_error {
+            println!("Game over: {}", e);
+        }
+
+        exit
+    }
+
+    fn window_size(&self) -> (u32, u32) {
+        let ws = self.cell_screen.window_size();
+        let extra_width = match self.race {
+            Some(ref race) => race.extra_width(&self.cell_screen),
+            None => 0,
+        };
+        ((ws.0 + extra_width) as u32, (ws.1 + self.hud_height()) as u32)
+    }
+}
+
+
+impl <Random: rand::Rng + Clone> TetrisGame<Random> {
+    fn new(rng: Random, config: GameConfig) -> Self {
+        let mut game = TetrisGame {
+            cell_screen: TetrisCellScreen::new(config.columns, config.lines, config.screen_cell_size()),
+            config,
+            phase: GamePhase::Playing,
+            spawn_modifiers: SpawnModifiers::default(),
+            rng: rng,
+            figures_generated: 0,
+            piece_stats: PieceStats::default(),
+            lines_cleared: 0,
+            gravity_rows_per_second: 0.0,
+            garbage_remaining: 0,
+            last_garbage_gap: None,
+            pending_garbage: VecDeque::new(),
+            state_hash: FNV_OFFSET_BASIS,
+            figure_spawned_ms: None,
+            lock_deadline_ms: None,
+            level_flash_until_ms: None,
+            lines_cleared_this_piece: 0,
+            clear_flash: None,
+            game_over_error: None,
+            race: None,
+            gif: None,
+            achievements: None,
+            toast: None,
+            placement_log: None,
+            event_log: None,
+            spectators: None,
+            history: VecDeque::new(),
+            soft_drop_figure: None,
+            held_figure: None,
+            hold_used: false,
+            drop_trails: Vec::new(),
+        };
+        game.cell_screen._mirrored = game.config.mirrored;
+        game.cell_screen._colors = game.config.colors;
+        game.cell_screen._monochrome = game.config.high_contrast;
+        if game.config.record_log {
+            game.event_log = Some(eventlog::GameEventLog::new());
+        }
+        let starting_level = game.config.starting_level;
+        game.set_level(starting_level);
+        let starting_garbage = game.config.starting_garbage;
+        game.add_garbage(starting_garbage);
+        if game.config.mode == GameMode::Cheese {
+            for _ in 0 .. game.cheese_lines() {
+                game.add_cheese_line();
+            }
+        }
+        let first_figure = game.create_new_figure();
+        assert!(first_figure.is_ok());
+        game
+    }
+
+    fn add_garbage(&mut self, rows: usize) {
+        for gap in garbage_gaps(self.config.garbage_style, rows, self.config.columns, &mut self.rng) {
+            self.cell_screen.add_garbage_line(gap);
+        }
+        self.garbage_remaining += rows;
+    }
+
+    /// Writes the board as a binary PPM image, drawn without SDL.
+    #[cfg(feature = "debug-tools")]
+    fn save_board_image(&self, path: &Path) -> std::io::Result<()> {
+        use std::io::Write;
+
+        let Dimensions(width, height) = self.cell_screen.window_size();
+        let mut rgba = vec![0; width * height * 4];
+        self.cell_screen.render_to_buffer(&mut rgba, width * 4, &self.config.colors);
+        let mut out = std::io::BufWriter::new(std::fs::File::create(path)?);
+        write!(out, "P6\n{} {}\n255\n", width, height)?;
+        for pixel in rgba.chunks(4) {
+            out.write_all(&pixel[.. 3])?;
+        }
+        out.flush()
+    }
+
+    #[cfg(any(test, feature = "debug-tools"))]
+    fn queue_garbage(&mut self, rows: usize) {
+        if rows > 0 {
+            self.pending_garbage.push_back(rows);
+        }
+    }
+
+    fn pending_garbage_rows(&self) -> usize {
+        self.pending_garbage.iter().sum()
+    }
+
+    /// Cancels pending garbage with the lines cleared by the last lock and adds the rest to
+    /// the board.
+    fn receive_garbage(&mut self) -> Result<(), GameError> {
+        let mut cancelled = self.lines_cleared_this_piece as usize;
+        while cancelled > 0 {
+            match self.pending_garbage.pop_front() {
+                Some(rows) if rows > cancelled => {
+                    self.pending_garbage.push_front(rows - cancelled);
+                    cancelled = 0;
+                },
+                Some(rows) => cancelled -= rows,
+                None => break,
+            }
+        }
+
+        let rows = min(self.pending_garbage.drain(..).sum(), self.config.lines);
+        if rows == 0 {
+            return Ok(());
+        }
+        let pushed_out = self.cell_screen.iter_rows().take(rows)
+            .any(|row| row.iter().any(|cell| cell.is_some()));
+        self.add_garbage(rows);
+        self.state_hash = fnv_mix(self.state_hash, self.cell_screen.board_hash());
+        match self.cell_screen.get_figure() {
+            _ if pushed_out => Err(GameError::BoardOverflow { at_row: 0 }),
+            Some((point, _, ref figure)) if self._figure_overlaps_cells(&point, figure) =>
+                Err(GameError::BoardOverflow { at_row: point.1 }),
+            _ => Ok(()),
+        }
+    }
+
+    fn cheese_lines(&self) -> usize {
+        min(CHEESE_LINES, self.config.lines.saturating_sub(self.config.starting_garbage + 2))
+    }
+
+    fn add_cheese_line(&mut self) {
+        let columns = self.config.columns;
+        let gap = match self.last_garbage_gap {
+            Some(last) => (last + self.rng.gen_range(1, columns)) % columns,
+            None => self.rng.gen_range(0, columns),
+        };
+        self.cell_screen.add_garbage_line(gap);
+        self.last_garbage_gap = Some(gap);
+        self.garbage_remaining += 1;
+    }
+
+    fn refill_cheese(&mut self) {
+        while self.garbage_remaining < self.cheese_lines()
+            && self.cell_screen.iter_rows().next().unwrap().iter().all(|cell| cell.is_none()) {
+            self.add_cheese_line();
+        }
+    }
+
+    fn spawn_point(&self, figure: &Figure) -> Point {
+        let offset = figure.offset_from_top_center();
+        assert!(offset.1 == 0);
+        let columns = self.cell_screen.dimensions().0 as isize;
+        let width = figure.bounding_box().0 as isize;
+        Point(
+            min(max(columns / 2 + offset.0, 0), columns - width) as usize,
+            offset.1 as usize,
+            )
+    }
+
+    fn hold_figure(&mut self) -> Result<(), GameError> {
+        let (_, _, figure) = match self.cell_screen.get_figure() {
+            Some(current) => current,
+            None => return Ok(()),
+        };
+        self.cell_screen.clear_figure();
+        self.lock_deadline_ms = None;
+        let held = self.held_figure.replace(Figure::new(figure.kind, 0));
+        let spawned = match held {
+            Some(figure) => self.spawn_or_top_out(figure),
+            None => self.create_new_figure(),
+        };
+        self.hold_used = true;
+        spawned
+    }
+
+    fn create_new_figure(&mut self) -> Result<(), GameError> {
+        self.cell_screen.clear_figure();
+        let figure = self.config.randomizer.next_figure(&mut self.rng);
+        if ! self.config.hold_resets_on_lock {
+            self.hold_used = false;
+        }
+
+        self.figures_generated += 1;
+        self.piece_stats.record(figure.kind);
+
+        if self.spawn_modifiers.rotate {
+            let (_, rotated_figure) = figure.clone().rotate_clockwise();
+            if let Some(point) = self.free_spawn_point(&rotated_figure) {
+                self.spawn_figure(point, rotated_figure);
+                return Ok(());
+            }
+        }
+
+        self.spawn_or_top_out(figure)
+    }
+
+    fn spawn_or_top_out(&mut self, figure: Figure) -> Result<(), GameError> {
+        if self.free_spawn_point(&figure).is_none() && self.config.mode == GameMode::Zen {
+            let rows = self.cell_screen.clear_bottom_half();
+            self.garbage_remaining = self.garbage_remaining.saturating_sub(rows);
+            self.drop_trails.clear();
+            self.state_hash = fnv_mix(self.state_hash, self.cell_screen.board_hash());
+        }
+        match self.free_spawn_point(&figure) {
+            Some(point) => {
+                self.spawn_figure(point, figure);
+                Ok(())
+            },
+            None => Err(GameError::BoardOverflow { at_row: self.spawn_point(&figure).1 }),
+        }
+    }
+
+    fn spawn_figure(&mut self, point: Point, figure: Figure) {
+        self.state_hash = fnv_mix(self.state_hash, figure.kind_index() as u64);
+        self.state_hash = fnv_mix(self.state_hash, point.0 as u64);
+        let color = self.config.colors.figure_color(&figure);
+        self.cell_screen.set_figure(point, color, figure);
+        self.figure_spawned_ms = None;
+    }
+
+    /// How long the current figure has been in play, counted from the first gravity tick after it
+    /// spawned or came out of hold.
+    fn current_piece_age_ms(&self, game_time_ms: u64) -> u64 {
+        self.figure_spawned_ms.map_or(0, |spawned| game_time_ms.saturating_sub(spawned))
+    }
+
+    fn free_spawn_point(&self, figure: &Figure) -> Option<Point> {
+        let Point(x, y) = self.spawn_point(figure);
+        let shifts: &[isize] = if self.config.spawn_grace { &[0, -1, 1] } else { &[0] };
+        shifts.iter()
+            .filter_map(|&shift| {
+                let shifted_x = x as isize + shift;
+                if shifted_x >= 0 { Some(Point(shifted_x as usize, y)) } else { None }
+            })
+            .find(|point| ! self._figure_overlaps_cells(point, figure))
+    }
+
+    fn dispatch_event(&mut self, event: GameInputEvent, game_time_ms: u64) {
+        if let Some(ref mut race) = self.race {
+            race.record(game_time_ms, event, self.spawn_modifiers);
+        }
+        let level = self.level();
+        if let Err(e) = self.handle_event(event) {
+            self.phase = GamePhase::GameOver;
+            self.game_over_error = Some(e);
+            self.flush_placement_log();
+        }
+        if self.level() > level {
+            self.level_flash_until_ms = Some(precise_time_ms() + LEVEL_FLASH_MS);
+            let level = self.level();
+            self.notify(GameEvent::LevelUp(level));
+        }
+    }
+
+    fn flush_placement_log(&mut self) {
+        if let Some(ref mut log) = self.placement_log {
+            if let Err(e) = log.flush() {
+                println!("Can not write to {}: {}", log.path().display(), e);
+            }
+        }
+    }
+
+    fn notify(&mut self, event: GameEvent) {
+        let achievements = match self.achievements {
+            Some(ref mut achievements) => achievements,
+            None => return,
+        };
+        let unlocked = achievements.handle(event);
+        if let Some(achievement) = unlocked.last() {
+            let title = achievement.title(&self.config.strings).to_string();
+            self.toast = Some((title, precise_time_ms() + TOAST_MS));
+            if let Err(e) = achievements.save() {
+                println!("Can not save {}: {}", achievements.path().display(), e);
+            }
+        }
+    }
+
+    fn gravity_tick(&mut self, timer: &mut GameTimer, soft_dropping: bool, current_time_ms: u64,
+                    game_time_ms: u64) {
+        timer.soft_drop_factor = self.config.soft_drop_factor;
+        let instant_soft_drop = soft_dropping && self.config.soft_drop_factor.is_infinite();
+        if instant_soft_drop {
+            self.drop_figure_to_floor(game_time_ms);
+        }
+
+        let can_move_down = self.figure_can_move_down();
+        let soft_drop_held = soft_dropping && ! instant_soft_drop && can_move_down;
+        if timer.should_soft_drop(current_time_ms, soft_drop_held) {
+            self.lock_deadline_ms = None;
+            self.dispatch_event(GameInputEvent::SoftDrop, game_time_ms);
+            timer.reset_drop(current_time_ms);
+        } else if soft_drop_held {
+            self.lock_deadline_ms = None;
+        } else if can_move_down {
+            self.lock_deadline_ms = None;
+            let rows = timer.rows_to_drop(current_time_ms);
+            self.drop_rows(rows, game_time_ms);
+        } else {
+            timer.reset_drop(current_time_ms);
+            match self.lock_deadline_ms {
+                None => {
+                    self.lock_deadline_ms = Some(game_time_ms + LOCK_DELAY_MS);
+                },
+                Some(deadline) if deadline <= game_time_ms => {
+                    self.lock_deadline_ms = None;
+                    self.dispatch_event(GameInputEvent::Timer, game_time_ms);
+                },
+                Some(_) => {},
+            }
+        }
+
+        if timer.gravity_rows_per_second.is_infinite() {
+            self.drop_rows(usize::MAX, game_time_ms);
+        }
+        self.figure_spawned_ms.get_or_insert(game_time_ms);
+    }
+
+    fn drop_rows(&mut self, rows: usize, game_time_ms: u64) {
+        for _ in 0 .. rows {
+            if ! self.figure_can_move_down() {
+                break;
+            }
+            self.dispatch_event(GameInputEvent::Timer, game_time_ms);
+        }
+    }
+
+    fn level(&self) -> usize {
+        self.lines_cleared / LINES_PER_LEVEL
+    }
+
+    fn lines_to_next_level(&self) -> usize {
+        (self.level() + 1) * LINES_PER_LEVEL - self.lines_cleared
+    }
+
+    fn set_level(&mut self, level: usize) {
+        if self.config.mode == GameMode::Zen {
+            self.gravity_rows_per_second = ZEN_GRAVITY_ROWS_PER_SECOND;
+            return;
+        }
+        self.gravity_rows_per_second = (0 .. level)
+            .fold(self.config.gravity_rows_per_second, |gravity, _| faster_gravity(gravity));
+        if self.config.mode != GameMode::Sprint {
+            self.lines_cleared = level * LINES_PER_LEVEL;
+        }
+    }
+
+    fn expected_game_over_height(&self) -> u32 {
+        self.cell_screen.column_heights().iter().max().cloned().unwrap_or(0)
+    }
+
+    fn board_fill_ratio(&self) -> f32 {
+        let Dimensions(columns, lines) = self.cell_screen.dimensions();
+        self.cell_screen.locked_cell_count() as f32 / (columns * lines) as f32
+    }
+
+    /// Draws the board with everything on top of it and presents the frame, stopping at the first
+    /// failed draw call.
+    #[cfg(feature = "sdl")]
+    fn render_frame(&self, renderer: &mut Renderer, pressed_button: Option<HudButton>,
+                    banner: Option<&str>) -> Result<(), String> {
+        self.cell_screen.render_cell_screen(renderer)?;
+        self.render_drop_trails(renderer)?;
+        if let Some(ref race) = self.race {
+            race.render(renderer, &self.cell_screen, self.lines_cleared)?;
+        }
+        self.render_hud(renderer, pressed_button)?;
+        self.render_danger_bar(renderer)?;
+        self.render_garbage_meter(renderer)?;
+        self.render_toast(renderer)?;
+        if let Some(text) = banner {
+            self.render_banner(renderer, text)?;
+        }
+        renderer.present();
+        Ok(())
+    }
+
+    #[cfg(feature = "sdl")]
+    fn render_drop_trails(&self, renderer: &mut Renderer) -> Result<(), String> {
+        let Dimensions(x_off, y_off) = self.cell_screen.global_offset();
+        let Dimensions(cell_width, cell_height) = self.cell_screen.cell_size();
+        renderer.try_set_blend_mode(BlendMode::Blend)?;
+        for trail in &self.drop_trails {
+            let age_ms = precise_time_ms().saturating_sub(trail.started_ms);
+            let remaining_ms = DROP_TRAIL_MS.saturating_sub(age_ms);
+            let alpha = (DROP_TRAIL_ALPHA as u64 * remaining_ms / DROP_TRAIL_MS) as u8;
+            let (r, g, b) = self.cell_screen.rgb(trail.color);
+            renderer.try_set_draw_color(Color::RGBA(r, g, b, alpha))?;
+            for &column in &trail.columns {
+                let x = self.cell_screen.screen_column(column);
+                renderer.try_fill_rect(sdl2::rect::Rect::new_unwrap(
+                    (x_off + x * cell_width) as i32, (y_off + trail.from_line * cell_height) as i32,
+                    cell_width as u32, ((trail.to_line - trail.from_line) * cell_height) as u32))?;
+            }
+        }
+        renderer.try_set_blend_mode(BlendMode::None)
+    }
+
+    #[cfg(feature = "sdl")]
+    fn render_danger_bar(&self, renderer: &mut Renderer) -> Result<(), String> {
+        let Dimensions(x_off, y_off) = self.cell_screen.global_offset();
+        let board_height = self.cell_screen.cell_size().1 * self.cell_screen.dimensions().1;
+        let bar_height = board_height * self.expected_game_over_height() as usize
+            / self.cell_screen.dimensions().1;
+        if bar_height > 0 && x_off >= 4 {
+            renderer.try_set_draw_color(Color::RGB(200, 0, 0))?;
+            renderer.try_fill_rect(sdl2::rect::Rect::new_unwrap(
+                (x_off / 4) as i32, (y_off + board_height - bar_height) as i32,
+                (x_off / 2) as u32, bar_height as u32))?;
+        }
+        Ok(())
+    }
+
+    #[cfg(feature = "sdl")]
+    fn render_garbage_meter(&self, renderer: &mut Renderer) -> Result<(), String> {
+        let Dimensions(x_off, y_off) = self.cell_screen.global_offset();
+        let Dimensions(_, cell_height) = self.cell_screen.cell_size();
+        let Dimensions(_, lines) = self.cell_screen.dimensions();
+        if x_off < 4 || cell_height < 2 {
+            return Ok(());
+        }
+        renderer.try_set_draw_color(Color::RGB(255, 40, 40))?;
+        for row in 0 .. min(self.pending_garbage_rows(), lines) {
+            renderer.try_fill_rect(sdl2::rect::Rect::new_unwrap(
+                (x_off - x_off / 4) as i32, (y_off + (lines - 1 - row) * cell_height) as i32,
+                (x_off / 4) as u32, (cell_height - 1) as u32))?;
+        }
+        Ok(())
+    }
+
+    #[cfg(feature = "sdl")]
+    fn render_banner(&self, renderer: &mut Renderer, text: &str) -> Result<(), String> {
+        let Dimensions(x_off, y_off) = self.cell_screen.global_offset();
+        let Dimensions(cell_width, cell_height) = self.cell_screen.cell_size();
+        let Dimensions(columns, lines) = self.cell_screen.dimensions();
+        let board_width = cell_width * columns;
+        let pixel_size = max(min(board_width / 16, board_width / (font::text_width(text, 1) + 2)), 1);
+        font::draw_text(
+            renderer, text,
+            x_off + board_width.saturating_sub(font::text_width(text, pixel_size)) / 2,
+            y_off + (cell_height * lines).saturating_sub(font::text_height(pixel_size)) / 2,
+            pixel_size, Color::RGB(255, 255, 255))?;
+        Ok(())
+    }
+
+    fn update_clear_flash(&mut self) {
+        let row_colors = match self.clear_flash {
+            Some(ref flash) if precise_time_ms() < flash.until_ms =>
+                flash.lines.iter().map(|&line| (line, flash.color())).collect(),
+            _ => Vec::new(),
+        };
+        if row_colors.is_empty() {
+            self.clear_flash = None;
+        }
+        self.cell_screen.set_row_colors(row_colors);
+    }
+
+    fn ms_until_animation(&self, now_ms: u64) -> Option<u64> {
+        const ANIMATION_FRAME_MS: u64 = 20;
+        [
+            if self.drop_trails.is_empty() { None } else { Some(now_ms + ANIMATION_FRAME_MS) },
+            self.clear_flash.as_ref().map(|flash| flash.until_ms),
+            self.toast.as_ref().map(|&(_, until_ms)| until_ms),
+            self.level_flash_until_ms,
+        ].iter()
+            .filter_map(|&deadline| deadline)
+            .filter(|&deadline| deadline > now_ms)
+            .min()
+            .map(|deadline| deadline - now_ms)
+    }
+
+    fn change_soft_drop_factor(&mut self, faster: bool) {
+        let current = self.config.soft_drop_factor;
+        let factor = if faster {
+            SOFT_DROP_FACTORS.iter().cloned().find(|&factor| factor > current)
+        } else {
+            SOFT_DROP_FACTORS.iter().cloned().rev().find(|&factor| factor < current)
+        };
+        if let Some(factor) = factor {
+            self.config.soft_drop_factor = factor;
+            let strings = self.config.strings;
+            let label = if factor.is_infinite() {
+                strings.get("soft_drop_instant").to_string()
+            } else {
+                strings.fill("soft_drop_factor", &[&factor])
+            };
+            self.toast = Some((label, precise_time_ms() + TOAST_MS));
+        }
+    }
+
+    #[cfg(feature = "sdl")]
+    fn render_toast(&self, renderer: &mut Renderer) -> Result<(), String> {
+        let text = match self.toast {
+            Some((ref text, until_ms)) if precise_time_ms() < until_ms => text,
+            _ => return Ok(()),
+        };
+        let Dimensions(x_off, y_off) = self.cell_screen.global_offset();
+        let Dimensions(cell_width, _) = self.cell_screen.cell_size();
+        let board_width = cell_width * self.cell_screen.dimensions().0;
+        let pixel_size = max(min(board_width / 40, board_width / (font::text_width(text, 1) + 2)), 1);
+        let text_width = font::text_width(text, pixel_size);
+        let (x, y) = (x_off + board_width.saturating_sub(text_width) / 2, y_off + pixel_size * 2);
+        renderer.try_set_draw_color(Color::RGB(40, 40, 40))?;
+        renderer.try_fill_rect(sdl2::rect::Rect::new_unwrap(
+            x.saturating_sub(pixel_size) as i32, (y - pixel_size) as i32,
+            (text_width + pixel_size * 2) as u32,
+            (font::text_height(pixel_size) + pixel_size * 2) as u32))?;
+        font::draw_text(renderer, text, x, y, pixel_size, Color::RGB(255, 215, 0))?;
+        Ok(())
+    }
+
+    #[cfg(feature = "sdl")]
+    fn hud_pixel_size(&self) -> usize {
+        max(self.cell_screen.cell_size().1 / 10, 1)
+    }
+
+    #[cfg(feature = "sdl")]
+    fn hud_height(&self) -> usize {
+        let pixel_size = self.hud_pixel_size();
+        let y_off = self.cell_screen.global_offset().1;
+        font::text_height(pixel_size) * 2 + pixel_size * 2 + y_off / 2 + y_off
+    }
+
+    #[cfg(feature = "sdl")]
+    fn hud_buttons(&self) -> Vec<(HudButton, &'static str, Point, Dimensions)> {
+        let Dimensions(x_off, y_off) = self.cell_screen.global_offset();
+        let pixel_size = self.hud_pixel_size();
+        let text_height = font::text_height(pixel_size);
+        let y = self.cell_screen.window_size().1 + text_height + y_off / 2;
+
+        let mut x = x_off;
+        HUD_BUTTONS.iter().map(|&(button, key)| {
+            let label = self.config.strings.get(key);
+            let dim = Dimensions(font::text_width(label, pixel_size) + pixel_size * 2,
+                                 text_height + pixel_size * 2);
+            let point = Point(x, y);
+            x += dim.0 + x_off;
+            (button, label, point, dim)
+        }).collect()
+    }
+
+    #[cfg(feature = "sdl")]
+    fn hud_button_at(&self, x: i32, y: i32) -> Option<HudButton> {
+        if x < 0 || y < 0 {
+            return None;
+        }
+        let (x, y) = (x as usize, y as usize);
+        self.hud_buttons().into_iter()
+            .find(|&(_, _, Point(bx, by), Dimensions(width, height))| {
+                bx <= x && x < bx + width && by <= y && y < by + height
+            })
+            .map(|(button, _, _, _)| button)
+    }
+
+    #[cfg(feature = "sdl")]
+    fn render_hud(&self, renderer: &mut Renderer, pressed_button: Option<HudButton>)
+                  -> Result<(), String> {
+        let Dimensions(x_off, _) = self.cell_screen.global_offset();
+        let Dimensions(board_window_width, y) = self.cell_screen.window_size();
+        let pixel_size = self.hud_pixel_size();
+
+        let level_color = match self.level_flash_until_ms {
+            Some(until) if precise_time_ms() < until => Color::RGB(255, 255, 255),
+            _ => Color::RGB(160, 160, 160),
+        };
+        let strings = self.config.strings;
+        let level_text = strings.fill("level", &[&self.level()]);
+        font::draw_text(renderer, &level_text, x_off, y, pixel_size, level_color)?;
+
+        if let Some(ref figure) = self.held_figure {
+            let hold_x = x_off * 2 + font::text_width(&level_text, pixel_size);
+            renderer.try_set_draw_color(if self.hold_used {
+                Color::RGB(100, 100, 100)
+            } else {
+                self.cell_screen.sdl_color(self.config.colors.figure_color(figure))
+            })?;
+            let shown = if self.cell_screen.mirrored() { figure.mirror() } else { figure.clone() };
+            for Point(cell_x, cell_y) in shown.cells_iter(Point(0, 0)) {
+                renderer.try_fill_rect(sdl2::rect::Rect::new_unwrap(
+                    (hold_x + cell_x * pixel_size) as i32, (y + cell_y * pixel_size) as i32,
+                    pixel_size as u32, pixel_size as u32))?;
+            }
+        }
+
+        let drought = if self.phase == GamePhase::GameOver {
+            let (kind, drought) = self.piece_stats.max_drought_ever();
+            let kind_name = strings.get(&format!("figure_{}", kind.name()));
+            strings.fill("drought", &[&kind_name, &drought])
+        } else {
+            strings.fill("i_drought", &[&self.piece_stats.drought(FigureKind::Line)])
+        };
+        font::draw_text(
+            renderer, &drought,
+            board_window_width.saturating_sub(font::text_width(&drought, pixel_size)) / 2, y,
+            pixel_size, Color::RGB(160, 160, 160))?;
+
+        let next = if self.config.mode == GameMode::Cheese {
+            strings.fill("garbage", &[&self.garbage_remaining])
+        } else if self.config.mode == GameMode::Zen {
+            strings.fill("lines_count", &[&self.lines_cleared])
+        } else {
+            strings.fill("next", &[&self.lines_to_next_level()])
+        };
+        font::draw_text(
+            renderer, &next,
+            (board_window_width - x_off).saturating_sub(font::text_width(&next, pixel_size)), y,
+            pixel_size, Color::RGB(160, 160, 160))?;
+
+        for (button, label, Point(x, y), Dimensions(width, height)) in self.hud_buttons() {
+            let rect = sdl2::rect::Rect::new_unwrap(x as i32, y as i32, width as u32, height as u32);
+            renderer.try_set_draw_color(Color::RGB(160, 160, 160))?;
+            if pressed_button == Some(button) {
+                renderer.try_fill_rect(rect)?;
+            } else {
+                renderer.try_draw_rect(rect)?;
+            }
+            font::draw_text(renderer, label, x + pixel_size, y + pixel_size, pixel_size,
+                            if pressed_button == Some(button) {
+                                Color::RGB(0, 0, 0)
+                            } else {
+                                Color::RGB(160, 160, 160)
+                            })?;
+        }
+
+        let buttons = self.hud_buttons();
+        if let Some(&(_, _, Point(_, bar_y), Dimensions(_, bar_height))) = buttons.last() {
+            let bar_width = (board_window_width - x_off * 2) / 4;
+            let bar_x = board_window_width - x_off - bar_width;
+            let fill_ratio = self.board_fill_ratio();
+            renderer.try_set_draw_color(Color::RGB(160, 160, 160))?;
+            renderer.try_draw_rect(sdl2::rect::Rect::new_unwrap(
+                bar_x as i32, bar_y as i32, bar_width as u32, bar_height as u32))?;
+            let filled_width = ((bar_width - 2) as f32 * fill_ratio) as u32;
+            if filled_width > 0 {
+                renderer.try_set_draw_color(if fill_ratio < 0.5 {
+                    Color::RGB(0, 160, 0)
+                } else if fill_ratio <= 0.75 {
+                    Color::RGB(180, 180, 0)
+                } else {
+                    Color::RGB(200, 0, 0)
+                })?;
+                renderer.try_fill_rect(sdl2::rect::Rect::new_unwrap(
+                    bar_x as i32 + 1, bar_y as i32 + 1, filled_width, (bar_height - 2) as u32))?;
+            }
+        }
+        Ok(())
+    }
+
+    fn handle_event(&mut self, event: GameInputEvent) -> Result<(), GameError> {
+        self.lines_cleared_this_piece = 0;
+        if self.starts_undo_step(event) {
+            self.remember_state();
+        }
+        let result = self.apply_event(event)
+            .and_then(|locked| if locked { self.receive_garbage() } else { Ok(()) });
+        if let Some(ref mut log) = self.event_log {
+            log.record(event, self.lines_cleared, self.config.soft_drop_factor);
+        }
+        result
+    }
+
+    /// Returns whether the event locked the falling figure.
+    fn apply_event(&mut self, event: GameInputEvent) -> Result<bool, GameError> {
+        match event {
+            GameInputEvent::Timer | GameInputEvent::SoftDrop => {
+                return self.gravity_step().map(|result| result == GravityResult::Locked);
+            },
+            GameInputEvent::MoveLeft => {
+                if self.cell_screen.has_figure() { self.move_figure_left() }
+            },
+            GameInputEvent::MoveRight => {
+                if self.cell_screen.has_figure() { self.move_figure_right() }
+            },
+            GameInputEvent::RotateClockwise => {
+                if self.cell_screen.has_figure() {
+                    self.rotate_clockwise();
+                }
+            },
+            GameInputEvent::Rotate180 => {
+                if self.cell_screen.has_figure() {
+                    self.rotate_180();
+                }
+            },
+            GameInputEvent::Undo => {
+                self.undo();
+            },
+            GameInputEvent::Hold => {
+                if self.config.allow_hold && ! self.hold_used && self.cell_screen.has_figure() {
+                    return self.hold_figure().map(|()| false);
+                }
+            },
+        };
+
+        Ok(false)
+    }
+
+    fn gravity_step(&mut self) -> Result<GravityResult, GameError> {
+        if self.try_move_figure_down() {
+            return Ok(GravityResult::Moved);
+        }
+
+        let locked_figure = self.cell_screen.get_figure();
+        if self.config.hold_resets_on_lock {
+            self.hold_used = false;
+        }
+        let garbage_cleared = self.remove_filled_lines();
+        self.state_hash = fnv_mix(self.state_hash, self.cell_screen.board_hash());
+        if garbage_cleared > 0 && self.config.mode == GameMode::Cheese && self.config.endless_cheese {
+            self.refill_cheese();
+        }
+        if let Some((point, _, figure)) = locked_figure {
+            self.log_placement(&figure, point, self.lines_cleared_this_piece as usize);
+        }
+
+        self.create_new_figure()?;
+        Ok(GravityResult::Locked)
+    }
+
+    fn apply_placement(&mut self, col: usize, rotation: u8) -> bool {
+        let (Point(_, y), color, mut figure) = match self.cell_screen.get_figure() {
+            Some(current) => current,
+            None => return false,
+        };
+        for _ in 0 .. rotation % 4 {
+            figure = figure.rotate_clockwise().1;
+        }
+        let point = Point(col, y);
+        if self._figure_overlaps_cells(&point, &figure) {
+            return false;
+        }
+
+        self.cell_screen.set_figure(point, color, figure);
+        let distance = self.drop_distance();
+        let (point, color, figure) = self.cell_screen.get_figure().unwrap();
+        self.cell_screen.set_figure(Point(point.0, point.1 + distance), color, figure);
+        self.lock_deadline_ms = None;
+        if let Err(e) = self.gravity_step().and_then(|_| self.receive_garbage()) {
+            self.phase = GamePhase::GameOver;
+            self.game_over_error = Some(e);
+            return false;
+        }
+        true
+    }
+
+    fn export_board_state(&self) -> BoardState {
+        BoardState {
+            cells: self.cell_screen.cells.clone(),
+            dim: self.cell_screen.dimensions(),
+        }
+    }
+
+    fn import_board_state(&mut self, state: &BoardState) -> Result<(), GameError> {
+        let dim = self.cell_screen.dimensions();
+        if state.dim != dim {
+            return Err(GameError::BoardDimensionsMismatch { expected: dim, got: state.dim });
+        }
+        if state.cells.len() != dim.0 * dim.1 {
+            return Err(GameError::BoardSizeMismatch { dim, cells: state.cells.len() });
+        }
+        self.cell_screen.cells.clone_from(&state.cells);
+        self.cell_screen.clear_figure();
+        self.drop_trails.clear();
+        self.clear_flash = None;
+        self.cell_screen.set_row_colors(Vec::new());
+        self.lock_deadline_ms = None;
+        self.garbage_remaining = 0;
+        Ok(())
+    }
+
+    /// Loads a dump taken on a board of any size, as long as its stack fits into this one.
+    fn load_board_dump(&mut self, dump: &boarddump::BoardDump) -> Result<(), GameError> {
+        let mut board = TetrisCellScreen::from_cells(dump.board.cells.clone(), dump.board.dim)?;
+        if let Some((ref figure, point)) = dump.figure {
+            board.set_figure(point, self.config.colors.figure_color(figure), figure.clone());
+        }
+        board.resize(self.cell_screen.dimensions())?;
+        let figure = board.get_figure();
+        self.import_board_state(&BoardState { cells: board.cells, dim: board.dim })?;
+        match figure {
+            Some((point, _, figure)) if ! self._figure_overlaps_cells(&point, &figure) =>
+                self.spawn_figure(point, figure),
+            _ => self.create_new_figure()?,
+        }
+        Ok(())
+    }
+
+    /// One undo step per user action: a move, a rotation, a hold, or a whole soft or hard drop
+    /// of a figure, however many rows it takes. Gravity and undo itself are not steps. This only
+    /// looks at the events, so a replay takes the same snapshots as the game it recorded.
+    fn starts_undo_step(&mut self, event: GameInputEvent) -> bool {
+        match event {
+            GameInputEvent::Timer => false,
+            GameInputEvent::Undo => {
+                self.soft_drop_figure = None;
+                false
+            },
+            GameInputEvent::SoftDrop => {
+                let figure = Some(self.figures_generated);
+                std::mem::replace(&mut self.soft_drop_figure, figure) != figure
+            },
+            _ => {
+                self.soft_drop_figure = None;
+                true
+            },
+        }
+    }
+
+    fn snapshot(&self) -> GameState<Random> {
+        GameState {
+            board: self.export_board_state(),
+            figure: self.cell_screen.get_figure(),
+            figures_generated: self.figures_generated,
+            piece_stats: self.piece_stats,
+            lines_cleared: self.lines_cleared,
+            garbage_remaining: self.garbage_remaining,
+            last_garbage_gap: self.last_garbage_gap,
+            pending_garbage: self.pending_garbage.clone(),
+            state_hash: self.state_hash,
+            held_figure: self.held_figure.clone(),
+            hold_used: self.hold_used,
+            rng: self.rng.clone(),
+        }
+    }
+
+    fn restore(&mut self, state: GameState<Random>) {
+        self.import_board_state(&state.board).unwrap();
+        if let Some((point, color, figure)) = state.figure {
+            self.cell_screen.set_figure(point, color, figure);
+        }
+        self.figures_generated = state.figures_generated;
+        self.piece_stats = state.piece_stats;
+        self.lines_cleared = state.lines_cleared;
+        self.garbage_remaining = state.garbage_remaining;
+        self.last_garbage_gap = state.last_garbage_gap;
+        self.pending_garbage = state.pending_garbage;
+        self.state_hash = state.state_hash;
+        self.held_figure = state.held_figure;
+        self.hold_used = state.hold_used;
+        self.rng = state.rng;
+    }
+
+    fn remember_state(&mut self) {
+        if ! self.config.undo_enabled || self.config.undo_history == 0 {
+            return;
+        }
+        if self.history.len() == self.config.undo_history {
+            self.history.pop_front();
+        }
+        let state = self.snapshot();
+        self.history.push_back(state);
+    }
+
+    fn undo(&mut self) -> bool {
+        match self.history.pop_back() {
+            Some(state) => {
+                self.restore(state);
+                true
+            },
+            None => false,
+        }
+    }
+
+    /// Board evaluation only: timers, the score and the figure queue are left untouched.
+    fn simulate_placement(&self, figure: Figure, col: usize, rotation: u8)
+                          -> Option<(GameState<Random>, u32)> {
+        let mut figure = figure;
+        for _ in 0 .. rotation % 4 {
+            figure = figure.rotate_clockwise().1;
+        }
+        let mut point = Point(col, self.spawn_point(&figure).1);
+        if self._figure_overlaps_cells(&point, &figure) {
+            return None;
+        }
+        while ! self._figure_overlaps_cells(&Point(point.0, point.1 + 1), &figure) {
+            point.1 += 1;
+        }
+
+        let Dimensions(columns, lines) = self.cell_screen.dimensions();
+        let mut cells = self.cell_screen.cells.clone();
+        let color = self.config.colors.figure_color(&figure);
+        for Point(x, y) in figure.cells_iter(point) {
+            cells[y * columns + x] = Some(color);
+        }
+        let kept: Vec<_> = cells.chunks(columns)
+            .filter(|row| row.iter().any(|cell| cell.is_none()))
+            .flat_map(|row| row.iter().cloned())
+            .collect();
+        let lines_cleared = lines - kept.len() / columns;
+        let mut cells = vec![None; lines_cleared * columns];
+        cells.extend(kept);
+
+        let mut state = self.snapshot();
+        state.board = BoardState { cells, dim: Dimensions(columns, lines) };
+        state.figure = None;
+        state.lines_cleared += lines_cleared;
+        Some((state, lines_cleared as u32))
+    }
+
+    #[cfg(test)]
+    fn force_figure(&mut self, figure: Figure, col: usize) -> bool {
+        self.cell_screen.clear_figure();
+        let point = Point(col, self.spawn_point(&figure).1);
+        if self._figure_overlaps_cells(&point, &figure) {
+            return false;
+        }
+        let color = self.config.colors.figure_color(&figure);
+        self.cell_screen.set_figure(point, color, figure);
+        self.lock_deadline_ms = None;
+        true
+    }
+
+    fn log_placement(&mut self, figure: &Figure, point: Point, lines_cleared: usize) {
+        let log = match self.placement_log {
+            Some(ref mut log) => log,
+            None => return,
+        };
+        let placement = placements::Placement {
+            figure,
+            point,
+            lines_cleared,
+            aggregate_height: self.cell_screen.column_heights().iter().sum(),
+            holes: self.cell_screen.hole_count(),
+        };
+        if let Err(e) = log.record(&placement) {
+            println!("Can not write to {}: {}", log.path().display(), e);
+            self.placement_log = None;
+        }
+    }
+
+    fn move_figure_left(&mut self) {
+        let (mut point, color, figure) = self.cell_screen.get_figure().unwrap();
+        if point.0 > 0 {
+            point.0 -= 1;
+            if ! self._figure_overlaps_cells(&point, &figure) {
+                self.cell_screen.set_figure(point, color, figure);
+                self.lock_deadline_ms = None;
+            }
+        }
+    }
+
+    fn move_figure_right(&mut self) {
+        let (mut point, color, figure) = self.cell_screen.get_figure().unwrap();
+        point.0 += 1;
+        if ! self._figure_overlaps_cells(&point, &figure) {
+            self.cell_screen.set_figure(point, color, figure);
+            self.lock_deadline_ms = None;
+        }
+    }
+
+    fn figure_can_move_down(&self) -> bool {
+        match self.cell_screen.get_figure() {
+            Some((point, _, figure)) =>
+                ! self._figure_overlaps_cells(&Point(point.0, point.1 + 1), &figure),
+            None => false,
+        }
+    }
+
+    fn move_figure_down(&mut self, game_time_ms: u64) -> Option<(usize, usize)> {
+        let (Point(_, from_line), _, _) = self.cell_screen.get_figure()?;
+        while self.figure_can_move_down() {
+            self.dispatch_event(GameInputEvent::SoftDrop, game_time_ms);
+        }
+        let (Point(_, to_line), _, _) = self.cell_screen.get_figure()?;
+        Some((from_line, to_line))
+    }
+
+    fn drop_figure_to_floor(&mut self, game_time_ms: u64) {
+        let (Point(x, _), color, figure) = match self.cell_screen.get_figure() {
+            Some(current) => current,
+            None => return,
+        };
+        match self.move_figure_down(game_time_ms) {
+            Some((from_line, to_line)) if to_line > from_line => {
+                let mut columns: Vec<usize> = figure.cells_iter(Point(x, 0)).map(|p| p.0).collect();
+                columns.sort();
+                columns.dedup();
+                self.drop_trails.push(DropTrail {
+                    columns,
+                    from_line,
+                    to_line,
+                    color,
+                    started_ms: precise_time_ms(),
+                });
+            },
+            _ => {},
+        }
+    }
+
+    fn drop_distance(&self) -> usize {
+        match self.cell_screen.get_figure() {
+            Some((Point(x, y), _, figure)) =>
+                (1 ..).take_while(|&d| ! self._figure_overlaps_cells(&Point(x, y + d), &figure))
+                    .count(),
+            None => 0,
+        }
+    }
+
+    fn try_move_figure_down(&mut self) -> bool {
+        let (point, color, figure) = self.cell_screen.get_figure().unwrap();
+
+        if self.figure_can_move_down() {
+            self.cell_screen.set_figure(Point(point.0, point.1 + 1), color, figure);
+            true
+        } else {
+            for cell in figure.cells_iter(point) {
+                self.cell_screen.set_cell(cell, Some(color));
+            }
+            false
+        }
+    }
+
+    fn _figure_overlaps_cells(&self, new_point: &Point, figure: &Figure) -> bool {
+        self.cell_screen.figure_overlaps(*new_point, figure)
+    }
+
+    fn rotate_clockwise(&mut self) {
+        let (point, color, figure) = self.cell_screen.get_figure().unwrap();
+        let (offset, rotated_figure) = figure.rotate_clockwise();
+
+        let PointOffset(new_x, new_y) = PointOffset(point.0 as isize, point.1 as isize) + offset;
+        let new_x = max(0, new_x) as usize;
+        let new_y = max(0, new_y) as usize;
+        let dim = self.cell_screen.dimensions();
+        let fig_dim = rotated_figure.bounding_box();
+
+        let new_point = Point(
+            min(new_x, dim.0 - fig_dim.0),
+            min(new_y, dim.1 - fig_dim.1));
+        
+        if ! self._figure_overlaps_cells(&new_point, &rotated_figure) {
+            self.cell_screen.set_figure(new_point, color, rotated_figure);
+            self.lock_deadline_ms = None;
+        }
+    }
+
+    fn rotate_180(&mut self) {
+        let (point, color, figure) = self.cell_screen.get_figure().unwrap();
+        let (first_offset, half_turned) = figure.rotate_clockwise();
+        let (second_offset, rotated_figure) = half_turned.rotate_clockwise();
+
+        let PointOffset(new_x, new_y) =
+            PointOffset(point.0 as isize, point.1 as isize) + first_offset + second_offset;
+        let dim = self.cell_screen.dimensions();
+        let fig_dim = rotated_figure.bounding_box();
+        let new_x = min(max(0, new_x), (dim.0 - fig_dim.0) as isize);
+        let new_y = min(max(0, new_y), (dim.1 - fig_dim.1) as isize);
+
+        let kicked_point = ROTATE_180_KICKS.iter()
+            .map(|&kick| PointOffset(new_x, new_y) + kick)
+            .filter(|&PointOffset(x, y)| x >= 0 && y >= 0)
+            .map(|PointOffset(x, y)| Point(x as usize, y as usize))
+            .find(|point| ! self._figure_overlaps_cells(point, &rotated_figure));
+
+        if let Some(new_point) = kicked_point {
+            self.cell_screen.set_figure(new_point, color, rotated_figure);
+            self.lock_deadline_ms = None;
+        }
+    }
+
+    #[cfg(test)]
+    fn check_invariants(&self, locked_cells_before: usize) -> Result<(), String> {
+        if let Some((point, _, figure)) = self.cell_screen.get_figure() {
+            if self._figure_overlaps_cells(&point, &figure) {
+                return Err(format!("{:?} at {:?} is out of the board or overlaps cells",
+                                   figure, point));
+            }
+        }
+
+        if let Some((_, color, figure)) = self.cell_screen.get_figure() {
+            let layer_matches = self.cell_screen._figure_layer.len() == figure.bitmap().len()
+                && self.cell_screen._figure_layer.iter().zip(figure.bitmap())
+                    .all(|(cell, &filled)| *cell == if filled { Some(color) } else { None });
+            if ! layer_matches {
+                return Err(format!("figure layer {:?} does not match {:?}",
+                                   self.cell_screen._figure_layer, figure));
+            }
+        }
+
+        if let Some((_, _, figure)) = self.cell_screen.get_figure() {
+            let (width, height) = figure.bounding_box();
+            let expected = rotate_bitmap_cw(figure.bitmap(), width, height);
+            let rotated = figure.rotate_clockwise().1;
+            let (rotated_width, rotated_height) = rotated.bounding_box();
+            if (&expected.0[..], expected.1, expected.2)
+                != (rotated.bitmap(), rotated_width, rotated_height) {
+                return Err(format!("{:?} does not match the computed clockwise rotation", rotated));
+            }
+        }
+
+        let figures_counted: u32 = self.piece_stats.count.iter().sum();
+        if figures_counted as usize != self.figures_generated {
+            return Err(format!("piece stats counted {} figures out of {}",
+                               figures_counted, self.figures_generated));
+        }
+
+        let composed = self.cell_screen.composed_cells();
+        let mut cache_matches = false;
+        self.cell_screen.with_composed_cells(&mut |cached| cache_matches = cached == &composed[..]);
+        if ! cache_matches {
+            return Err("cached composed cells are stale".to_string());
+        }
+
+        if let Some(&y) = self.cell_screen.filled_lines_reference().first() {
+            return Err(format!("line {} is filled but was not removed", y));
+        }
+
+        let mut with_figure = self.cell_screen.clone();
+        if let Some((point, color, figure)) = self.cell_screen.get_figure() {
+            for cell in figure.cells_iter(point) {
+                with_figure.set_cell(cell, Some(color));
+            }
+        }
+        if with_figure.filled_lines() != with_figure.filled_lines_reference() {
+            return Err(format!("filled lines {:?} differ from the reference {:?}",
+                               with_figure.filled_lines(), with_figure.filled_lines_reference()));
+        }
+
+        let columns = self.cell_screen.dimensions().0;
+        let locked_cells = self.cell_screen.locked_cell_count();
+        if locked_cells != locked_cells_before
+            && (locked_cells_before + 4 < locked_cells
+                || ! (locked_cells_before + 4 - locked_cells).is_multiple_of(columns)) {
+            return Err(format!("locked cell count went from {} to {}",
+                               locked_cells_before, locked_cells));
+        }
+
+        Ok(())
+    }
+
+    fn remove_filled_lines(&mut self) -> usize {
+        let filled_lines = self.cell_screen.filled_lines();
+        self.lines_cleared_this_piece = filled_lines.len() as u32;
+        if filled_lines.is_empty() {
+            return 0;
+        }
+
+        let first_garbage_line = self.cell_screen.dimensions().1 - self.garbage_remaining;
+        let garbage_cleared = filled_lines.iter().filter(|&&line| line >= first_garbage_line).count();
+        self.garbage_remaining -= garbage_cleared;
+        self.lines_cleared += filled_lines.len();
+        self.cell_screen = self.cell_screen.compaction_preview(&filled_lines);
+        self.notify(GameEvent::LinesCleared(filled_lines.len()));
+        if self.cell_screen.locked_cell_count() == 0 {
+            self.notify(GameEvent::PerfectClear);
+        }
+        self.clear_flash = Some(ClearFlash {
+            size: filled_lines.len(),
+            lines: filled_lines,
+            until_ms: precise_time_ms() + CLEAR_FLASH_MS,
+        });
+        garbage_cleared
+    }
+}
+
+
+#[derive(Clone, PartialEq, Debug)]
+struct Figure {
+    kind: FigureKind,
+    rotation: u8,
+}
+
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum FigureKind {
+    Cube, Line, LeftL, RightL, LeftZigzag, RightZigzag, Pyramid,
+}
+
+
+const FIGURE_KINDS: [FigureKind; 7] = [
+    FigureKind::Cube,
+    FigureKind::Line,
+    FigureKind::LeftL,
+    FigureKind::RightL,
+    FigureKind::LeftZigzag,
+    FigureKind::RightZigzag,
+    FigureKind::Pyramid,
+    ];
+
+
+const CANONICAL_FIGURES: [[bool; 16]; 7] = [
+    [
+        true,  true,  false, false,
+        true,  true,  false, false,
+        false, false, false, false,
+        false, false, false, false,
+    ],
+    [
+        true,  true,  true,  true,
+        false, false, false, false,
+        false, false, false, false,
+        false, false, false, false,
+    ],
+    [
+        true,  true,  false, false,
+        false, true,  false, false,
+        false, true,  false, false,
+        false, false, false, false,
+    ],
+    [
+        true,  true,  false, false,
+        true,  false, false, false,
+        true,  false, false, false,
+        false, false, false, false,
+    ],
+    [
+        false, true,  true,  false,
+        true,  true,  false, false,
+        false, false, false, false,
+        false, false, false, false,
+    ],
+    [
+        true,  true,  false, false,
+        false, true,  true,  false,
+        false, false, false, false,
+        false, false, false, false,
+    ],
+    [
+        false, true,  false, false,
+        true,  true,  true,  false,
+        false, false, false, false,
+        false, false, false, false,
+    ],
+    ];
+
+
+#[derive(Clone, Copy)]
+struct FigureShape {
+    cells: [bool; 16],
+    width: usize,
+    height: usize,
+}
+
+
+const fn figure_shape(canonical: &[bool; 16], rotation: u8) -> FigureShape {
+    let mut rotated = *canonical;
+    let mut turn = 0;
+    while turn < rotation {
+        let mut next = [false; 16];
+        let mut i = 0;
+        while i < 16 {
+            let (x, y) = (i % 4, i / 4);
+            next[i] = rotated[(3 - x) * 4 + y];
+            i += 1;
+        }
+        rotated = next;
+        turn += 1;
+    }
+
+    let (mut left, mut top, mut right, mut bottom) = (4, 4, 0, 0);
+    let mut i = 0;
+    while i < 16 {
+        if rotated[i] {
+            let (x, y) = (i % 4, i / 4);
+            if x < left { left = x }
+            if y < top { top = y }
+            if x + 1 > right { right = x + 1 }
+            if y + 1 > bottom { bottom = y + 1 }
+        }
+        i += 1;
+    }
+
+    let mut shape = FigureShape { cells: [false; 16], width: right - left, height: bottom - top };
+    let mut i = 0;
+    while i < shape.width * shape.height {
+        shape.cells[i] = rotated[(top + i / shape.width) * 4 + left + i % shape.width];
+        i += 1;
+    }
+    shape
+}
+
+
+#[cfg(test)]
+fn rotate_bitmap_cw(bitmap: &[bool], width: usize, height: usize) -> (Vec<bool>, usize, usize) {
+    let rotated = (0 .. width * height)
+        .map(|i| bitmap[(height - 1 - i % height) * width + i / height])
+        .collect();
+    (rotated, height, width)
+}
+
+
+const fn figure_shapes() -> [[FigureShape; 4]; 7] {
+    let empty = FigureShape { cells: [false; 16], width: 0, height: 0 };
+    let mut shapes = [[empty; 4]; 7];
+    let mut kind = 0;
+    while kind < 7 {
+        let mut rotation = 0;
+        while rotation < 4 {
+            shapes[kind][rotation] = figure_shape(&CANONICAL_FIGURES[kind], rotation as u8);
+            rotation += 1;
+        }
+        kind += 1;
+    }
+    shapes
+}
+
+
+static FIGURE_SHAPES: [[FigureShape; 4]; 7] = figure_shapes();
+
+
+impl Figure {
+    fn new(kind: FigureKind, rotation: u8) -> Self {
+        Figure { kind, rotation: rotation % kind.rotation_count() }
+    }
+
+    fn offset_from_top_center(&self) -> PointOffset {
+        match (self.kind, self.rotation) {
+            (FigureKind::Line, 0) => PointOffset(-2, 0),
+            (FigureKind::Line, _) => PointOffset(0, 0),
+            (FigureKind::LeftL, 1) | (FigureKind::LeftL, 3) => PointOffset(-2, 0),
+            (FigureKind::RightL, 1) | (FigureKind::RightL, 3) => PointOffset(-2, 0),
+            _ => PointOffset(-1, 0),
+        }
+    }
+
+    fn bounding_box(&self) -> (usize, usize) {
+        let shape = &FIGURE_SHAPES[self.kind_index()][self.rotation as usize];
+        (shape.width, shape.height)
+    }
+
+    fn kind_index(&self) -> usize {
+        self.kind as usize
+    }
+
+    /// The old name of `bounding_box`, kept for code that still calls it.
+    #[deprecated(note = "use bounding_box")]
+    #[cfg_attr(not(test), allow(dead_code))]
+    fn dimensions(&self) -> Dimensions {
+        self.bounding_box().into()
+    }
+
+    fn color(&self) -> TetrisCellColor {
+        match self.kind {
+            FigureKind::Cube => TetrisCellColor::Red,
+            FigureKind::Line => TetrisCellColor::Orange,
+            FigureKind::LeftL => TetrisCellColor::Yellow,
+            FigureKind::RightL => TetrisCellColor::Green,
+            FigureKind::LeftZigzag => TetrisCellColor::Blue,
+            FigureKind::RightZigzag => TetrisCellColor::DeepBlue,
+            FigureKind::Pyramid => TetrisCellColor::Purple,
+        }
+    }
+
+    fn bitmap(&self) -> &'static [bool] {
+        let shape = &FIGURE_SHAPES[self.kind_index()][self.rotation as usize];
+        &shape.cells[.. shape.width * shape.height]
+    }
+
+    fn cells_iter(&self, origin: Point) -> impl Iterator<Item = Point> {
+        let (width, _) = self.bounding_box();
+        self.bitmap().iter().enumerate()
+            .filter(|&(_, &filled)| filled)
+            .map(move |(i, _)| Point(origin.0 + i % width, origin.1 + i / width))
+    }
+
+    fn rotate_clockwise(self) -> (PointOffset, Self) {
+        let offset = match (self.kind, self.rotation) {
+            (FigureKind::Line, 0) => PointOffset(2, -2),
+            (FigureKind::Line, _) => PointOffset(-2, 2),
+            (FigureKind::LeftL, 1) | (FigureKind::RightL, 1) => PointOffset(1, -1),
+            (FigureKind::LeftL, 2) | (FigureKind::RightL, 0) => PointOffset(-1, 1),
+            (FigureKind::LeftZigzag, 0) | (FigureKind::RightZigzag, 0) => PointOffset(1, -1),
+            (FigureKind::LeftZigzag, _) | (FigureKind::RightZigzag, _) => PointOffset(-1, 1),
+            (FigureKind::Pyramid, 0) => PointOffset(1, 0),
+            (FigureKind::Pyramid, 1) => PointOffset(-1, 1),
+            (FigureKind::Pyramid, 2) => PointOffset(0, -1),
+            _ => PointOffset(0, 0),
+        };
+        (offset, Figure::new(self.kind, self.rotation + 1))
+    }
+
+    fn mirror(&self) -> Figure {
+        let kind = match self.kind {
+            FigureKind::LeftL => FigureKind::RightL,
+            FigureKind::RightL => FigureKind::LeftL,
+            FigureKind::LeftZigzag => FigureKind::RightZigzag,
+            FigureKind::RightZigzag => FigureKind::LeftZigzag,
+            kind => kind,
+        };
+        Figure::new(kind, kind.rotation_count() - self.rotation)
+    }
+}
+
+
+impl FigureKind {
+    fn name(&self) -> &'static str {
+        match *self {
+            FigureKind::Cube => "cube",
+            FigureKind::Line => "line",
+            FigureKind::LeftL => "left_l",
+            FigureKind::RightL => "right_l",
+            FigureKind::LeftZigzag => "left_zigzag",
+            FigureKind::RightZigzag => "right_zigzag",
+            FigureKind::Pyramid => "pyramid",
+        }
+    }
+
+    const fn rotation_count(&self) -> u8 {
+        match self {
+            &FigureKind::Cube => 1,
+            &FigureKind::Line | &FigureKind::LeftZigzag | &FigureKind::RightZigzag => 2,
+            &FigureKind::LeftL | &FigureKind::RightL | &FigureKind::Pyramid => 4,
+        }
+    }
+
+    fn rotations(&self) -> Vec<Figure> {
+        (0 .. self.rotation_count()).map(|rotation| Figure::new(*self, rotation)).collect()
+    }
+}
+
+
+const _: () = {
+    let mut kind = 0;
+    while kind < CANONICAL_FIGURES.len() {
+        let mut filled = 0;
+        let mut i = 0;
+        while i < 16 {
+            if CANONICAL_FIGURES[kind][i] { filled += 1 }
+            i += 1;
+        }
+        assert!(filled == 4);
+        kind += 1;
+    }
+};
+
+
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+struct PieceStats {
+    count: [u32; 7],
+    droughts: [u32; 7],
+    longest_droughts: [u32; 7],
+}
+
+
+impl PieceStats {
+    fn record(&mut self, kind: FigureKind) {
+        for (index, drought) in self.droughts.iter_mut().enumerate() {
+            *drought = if index == kind as usize { 0 } else { *drought + 1 };
+        }
+        for (longest, &drought) in self.longest_droughts.iter_mut().zip(self.droughts.iter()) {
+            *longest = max(*longest, drought);
+        }
+        self.count[kind as usize] += 1;
+    }
+
+    fn drought(&self, kind: FigureKind) -> u32 {
+        self.droughts[kind as usize]
+    }
+
+    fn max_drought_ever(&self) -> (FigureKind, u32) {
+        FIGURE_KINDS.iter().cloned()
+            .zip(self.longest_droughts.iter().cloned())
+            .fold((FIGURE_KINDS[0], 0), |best, (kind, drought)| {
+                if drought > best.1 { (kind, drought) } else { best }
+            })
+    }
+}
+
+
+/// Draws every figure independently of the ones before, so droughts have no upper bound. A 7-bag
+/// would cap them at 12, but it needs the bag to live in the game state next to `rng`, be saved
+/// with undo snapshots and replays, and make the weights meaningless; the config stays a plain
+/// `Copy` value instead.
+#[derive(Clone, Copy, Debug, PartialEq)]
+struct FigureRandomizer {
+    weights: [u32; 7],
+}
+
+
+impl Default for FigureRandomizer {
+    fn default() -> Self {
+        FigureRandomizer { weights: [1; 7] }
+    }
+}
+
+
+impl FigureRandomizer {
+    /// One weight per figure kind; `None` unless the sum is positive and fits into `u32`.
+    fn with_weights(weights: &[u32]) -> Option<Self> {
+        if weights.len() != FIGURE_KINDS.len() {
+            return None;
+        }
+        match weights.iter().try_fold(0u32, |total, &w| total.checked_add(w)) {
+            Some(total) if total > 0 => {
+                let mut randomizer = FigureRandomizer { weights: [0; 7] };
+                randomizer.weights.copy_from_slice(weights);
+                Some(randomizer)
+            },
+            _ => None,
+        }
+    }
+
+    fn from_weights(weights: &str) -> Result<Self, String> {
+        let parsed = weights.split(',')
+            .map(|w| w.trim().parse::<u32>())
+            .collect::<Result<Vec<_>, _>>();
+        match parsed.ok().and_then(|w| FigureRandomizer::with_weights(&w)) {
+            Some(randomizer) => Ok(randomizer),
+            None => Err(format!(
+                "expected {} comma-separated non-negative weights with a positive sum \
+                 below 2^32, got {:?}",
+                FIGURE_KINDS.len(), weights)),
+        }
+    }
+
+    fn next_figure<R: rand::Rng>(&self, rng: &mut R) -> Figure {
+        // with_weights guarantees that the sum does not overflow.
+        let total: u32 = self.weights.iter().sum();
+        let mut n = rng.gen_range(0, total);
+        for (kind, &weight) in FIGURE_KINDS.iter().zip(self.weights.iter()) {
+            if n < weight {
+                return Figure::new(*kind, rng.gen_range(0, kind.rotation_count() as usize) as u8);
+            }
+            n -= weight;
+        }
+        unreachable!()
+    }
+}
+
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+struct ColorScheme {
+    figure_colors: [TetrisCellColor; 7],
+    rgb: [(u8, u8, u8); 8],
+}
+
+
+impl Default for ColorScheme {
+    fn default() -> Self {
+        let mut scheme = ColorScheme {
+            figure_colors: [TetrisCellColor::Gray; 7],
+            rgb: [(0, 0, 0); 8],
+        };
+        for (color, kind) in scheme.figure_colors.iter_mut().zip(FIGURE_KINDS.iter()) {
+            *color = kind.rotations()[0].color();
+        }
+        for (index, rgb) in scheme.rgb.iter_mut().enumerate() {
+            *rgb = TetrisCellColor::from_index(index as u8).unwrap().default_rgb();
+        }
+        scheme
+    }
+}
+
+
+fn parse_hex_color(hex: &str) -> Option<(u8, u8, u8)> {
+    if hex.len() != 7 || ! hex.starts_with('#') || ! hex[1 ..].chars().all(|c| c.is_ascii_hexdigit()) {
+        return None;
+    }
+    let channel = |i: usize| u8::from_str_radix(&hex[i .. i + 2], 16).unwrap();
+    Some((channel(1), channel(3), channel(5)))
+}
+
+
+impl ColorScheme {
+    fn from_spec(spec: &str) -> Result<Self, String> {
+        let mut scheme = ColorScheme::default();
+        for entry in spec.split(',') {
+            let mut parts = entry.splitn(2, '=').map(|part| part.trim());
+            let (shape, value) = match (parts.next(), parts.next()) {
+                (Some(shape), Some(value)) => (shape, value),
+                _ => return Err(format!("expected shape=color, got {:?}", entry)),
+            };
+            let kind = match FIGURE_KINDS.iter().position(|kind| kind.name() == shape) {
+                Some(kind) => kind,
+                None => return Err(format!(
+                    "unknown shape {:?}, expected one of {}", shape,
+                    FIGURE_KINDS.iter().map(|kind| kind.name()).collect::<Vec<_>>().join(", "))),
+            };
+            let named = (0 ..).map_while(TetrisCellColor::from_index).find(|c| c.name() == value);
+            match (named, parse_hex_color(value)) {
+                (Some(color), _) => scheme.figure_colors[kind] = color,
+                (None, Some(rgb)) => scheme.rgb[scheme.figure_colors[kind].to_index() as usize] = rgb,
+                (None, None) => return Err(format!(
+                    "{}: {:?} is neither a color name nor #rrggbb", shape, value)),
+            }
+        }
+        Ok(scheme)
+    }
+
+    fn figure_color(&self, figure: &Figure) -> TetrisCellColor {
+        self.figure_colors[figure.kind_index()]
+    }
+}
+
+
+fn new_game(config: &GameConfig, mut seed: u32, ghost_path: &str, gif_path: &str,
+            achievements_path: &str, placements_path: &str) -> TetrisGame<rand::XorShiftRng> {
+    let race = if ghost_path.is_empty() {
+        None
+    } else {
+        let path = PathBuf::from(ghost_path);
+        let best = match replay::Replay::load(&path) {
+            Ok(ref best) if best.columns != config.columns || best.lines != config.lines => {
+                println!("Ignoring {}: it was recorded on a {}x{} board",
+                         path.display(), best.columns, best.lines);
+                None
+            },
+            Ok(ref best) if best.starting_garbage != config.starting_garbage => {
+                println!("Ignoring {}: it was recorded with {} garbage lines",
+                         path.display(), best.starting_garbage);
+                None
+            },
+            Ok(ref best) if best.starting_level != config.starting_level => {
+                println!("Ignoring {}: it was recorded from level {}",
+                         path.display(), best.starting_level);
+                None
+            },
+            Ok(ref best) if best.garbage_style != config.garbage_style => {
+                println!("Ignoring {}: it was recorded with --garbage-style {}",
+                         path.display(), best.garbage_style.name());
+                None
+            },
+            Ok(ref best) if best.randomizer != config.randomizer => {
+                println!("Ignoring {}: it was recorded with figure weights {:?}",
+                         path.display(), best.randomizer.weights);
+                None
+            },
+            Ok(ref best) if best.spawn_grace != config.spawn_grace => {
+                println!("Ignoring {}: it was recorded with --spawn-grace {}",
+                         path.display(), if best.spawn_grace { "on" } else { "off" });
+                None
+            },
+            Ok(best) => {
+                seed = best.seed;
+                Some(best)
+            },
+            Err(e) => {
+                println!("Racing without a ghost: {}", e);
+                None
+            },
+        };
+        let recording = replay::Replay::new(seed, config);
+        Some(replay::GhostRace::new(path, recording, best))
+    };
+
+    let mut game = TetrisGame::new(seeded_rng(seed), config.clone());
+    game.race = race;
+    if ! gif_path.is_empty() {
+        let palette = raster::palette(&game.cell_screen);
+        game.gif = Some(gif::GifRecorder::new(PathBuf::from(gif_path), palette));
+    }
+    if ! achievements_path.is_empty() {
+        match achievements::Achievements::load(PathBuf::from(achievements_path)) {
+            Ok(achievements) => game.achievements = Some(achievements),
+            Err(e) => println!("Playing without achievements: {}", e),
+        }
+    }
+    if ! placements_path.is_empty() {
+        match placements::PlacementLog::open(PathBuf::from(placements_path)) {
+            Ok(log) => game.placement_log = Some(log),
+            Err(e) => println!("Can not log placements to {}: {}", placements_path, e),
+        }
+    }
+    game
+}
+
+
+#[cfg(feature = "sdl")]
+fn window_icon(screen: &TetrisCellScreen) -> sdl2::SdlResult<Surface<'static>> {
+    let figure = Figure::new(FigureKind::Pyramid, 0);
+    let color = screen._colors.figure_color(&figure);
+    let frame = raster::rasterize_figure(&figure, color, WINDOW_ICON_SIZE);
+    let pixels = frame.to_rgba8888(&raster::palette(screen));
+    let mut icon = Surface::new(frame.width as u32, frame.height as u32, PixelFormatEnum::RGBA8888)?;
+    let pitch = icon.pitch() as usize;
+    icon.with_lock_mut(|data| {
+        for (row, pixels) in pixels.chunks(frame.width).enumerate() {
+            for (x, pixel) in pixels.iter().enumerate() {
+                let offset = row * pitch + x * 4;
+                data[offset .. offset + 4].copy_from_slice(&pixel.to_ne_bytes());
+            }
+        }
+    });
+    Ok(icon)
+}
+
+
+#[cfg(feature = "sdl")]
+fn set_window_title(renderer: &mut Renderer, title: &str) {
+    if let Some(window) = renderer.window_mut() {
+        window.set_title(title);
+    }
+}
+
+
+#[cfg(feature = "sdl")]
+fn hot_seat(config: &GameConfig, achievements_path: &str, placements_path: &str,
+            event_pump: &mut sdl2::EventPump, renderer: &mut Renderer,
+            window_size: (u32, u32)) -> GameExit {
+    let seed = rand::random();
+    let strings = config.strings;
+    let mut lines_cleared = Vec::new();
+    let mut boards = Vec::new();
+    for player in 1 .. 3 {
+        let message = [strings.fill("player", &[&player])];
+        if ! title::prompt(event_pump, renderer, window_size, &strings, &message, None) {
+            return GameExit::Title;
+        }
+        let game = loop {
+            let mut game = new_game(config, seed, "", "", achievements_path, placements_path);
+            game.config.mode = GameMode::HotSeat;
+            match game.run(event_pump, renderer) {
+                GameExit::Quit => return GameExit::Quit,
+                GameExit::Title => break game,
+                GameExit::Restart => {},
+            }
+        };
+        lines_cleared.push(game.lines_cleared);
+        boards.push(game.cell_screen.clone());
+    }
+
+    let verdict = match lines_cleared[0].cmp(&lines_cleared[1]) {
+        Ordering::Greater => strings.fill("player_wins", &[&1]),
+        Ordering::Less => strings.fill("player_wins", &[&2]),
+        Ordering::Equal => strings.get("draw").to_string(),
+    };
+    let results = [
+        strings.fill("player_lines", &[&1, &lines_cleared[0]]),
+        strings.fill("player_lines", &[&2, &lines_cleared[1]]),
+        verdict,
+    ];
+    title::prompt(event_pump, renderer, window_size, &strings, &results,
+                  Some(&versus_layout(&boards, window_size)));
+    GameExit::Title
+}
+
+
+fn versus_layout(boards: &[TetrisCellScreen], window_size: (u32, u32)) -> ScreenLayout {
+    let (width, height) = (window_size.0 as usize, window_size.1 as usize);
+    let mut layout = ScreenLayout::new(Dimensions(width, height));
+    let panel_width = width / boards.len();
+    for (i, board) in boards.iter().enumerate() {
+        let mut panel = board.clone();
+        let Dimensions(columns, lines) = panel.dimensions();
+        let Dimensions(x_off, y_off) = panel.global_offset();
+        let cell_size = min(panel_width.saturating_sub(x_off * 2) / columns, height / 2 / lines);
+        panel.set_cell_size(cell_size);
+        panel.clear_figure();
+        panel.set_drop_distance(0);
+        panel.set_top_out_rows(0);
+        panel.set_invisible(false);
+        let origin = Point(i * panel_width + (panel_width - cell_size * columns) / 2 - x_off,
+                           (height / 3).saturating_sub(y_off));
+        layout.add_panel(origin, Box::new(panel));
+    }
+    layout
+}
+
+
+#[cfg(feature = "sdl")]
+fn main() {
+    let mut config = GameConfig::default();
+    let mut seed: u32 = rand::random();
+    let mut ghost_path = String::new();
+    let mut gif_path = String::new();
+    let mut achievements_path = String::new();
+    let mut placements_path = String::new();
+    let mut event_log_path = String::new();
+    let mut board_dump_path = String::new();
+    let mut spectate_port: u16 = 0;
+    let mut spectate_public = false;
+    let mut figure_weights = String::new();
+    let mut colors = String::new();
+    let mut lang = String::new();
+    let mut garbage_style = String::new();
+    let mut print_replay_path = String::new();
+    let mut bench_games = 0;
+    let mut bench_ai_games = 0;
+    let mut bench_ai_policies = String::from("heuristic,random");
+    let mut bench_ai_csv = String::new();
+    let mut optimize_generations = 0;
+    let mut population_size = 16;
+    {
+        let mut parser = argparse::ArgumentParser::new();
+        parser.refer(&mut config.cell_size)
+            .add_option(&["--cell-size"], argparse::Store,
+                        "One cell size in pixels");
+        parser.refer(&mut config.columns)
+            .add_option(&["--columns"], argparse::Store, "");
+        parser.refer(&mut config.lines)
+            .add_option(&["--lines"], argparse::Store, "");
+        parser.refer(&mut config.big_mode)
+            .add_option(&["--big"], argparse::StoreTrue,
+                        "Draw every cell twice as large");
+        parser.refer(&mut config.high_contrast)
+            .add_option(&["--high-contrast"], argparse::StoreTrue,
+                        "Draw cells white on black with a different pattern for every color");
+        parser.refer(&mut config.show_countdown)
+            .add_option(&["--no-countdown"], argparse::StoreFalse,
+                        "Start playing without the 3-2-1 countdown");
+        parser.refer(&mut config.gravity_rows_per_second)
+            .add_option(&["--gravity"], argparse::Store,
+                        "Initial falling speed in lines per second, \"inf\" drops figures \
+                         onto the stack as soon as they spawn");
+        parser.refer(&mut config.starting_level)
+            .add_option(&["--level"], argparse::Store,
+                        "Start at this level, counting its lines as cleared and speeding up \
+                         gravity once per level");
+        parser.refer(&mut config.soft_drop_factor)
+            .add_option(&["--soft-drop-factor"], argparse::Store,
+                        "How many times faster than gravity soft drop moves the figure, \
+                         \"inf\" drops it to the floor without locking; F6 and F7 change it \
+                         while playing");
+        parser.refer(&mut config.mirrored)
+            .add_option(&["--left-handed"], argparse::StoreTrue,
+                        "Show the board mirrored left to right and swap the left and right keys");
+        parser.refer(&mut config.invisible)
+            .add_option(&["--invisible"], argparse::StoreTrue,
+                        "Hide locked cells until the game is over in every mode");
+        parser.refer(&mut config.drop_protection)
+            .add_option(&["--drop-protection"], argparse::StoreTrue,
+                        "Ignore Space when it is pressed right after moving the figure sideways");
+        parser.refer(&mut config.drop_protection_ms)
+            .add_option(&["--drop-protection-ms"], argparse::Store,
+                        "How long after a sideways move --drop-protection ignores Space, \
+                         in milliseconds");
+        parser.refer(&mut config.preserve_das)
+            .add_option(&["--preserve-das"], argparse::StoreTrue,
+                        "Keep the auto-shift charge when a new figure spawns, so it moves at \
+                         once while Left or Right is still held");
+        parser.refer(&mut config.spawn_grace)
+            .add_option(&["--spawn-grace"], argparse::StoreTrue,
+                        "When a new figure does not fit at its spawn point, try one cell to the \
+                         left and to the right before ending the game");
+        parser.refer(&mut config.undo_enabled)
+            .add_option(&["--undo"], argparse::StoreTrue,
+                        "Let Ctrl+Z take back the last moves, for practice");
+        parser.refer(&mut config.undo_history)
+            .add_option(&["--undo-history"], argparse::Store,
+                        "How many moves --undo can take back");
+        parser.refer(&mut config.allow_hold)
+            .add_option(&["--no-hold"], argparse::StoreFalse,
+                        "Disable holding the current figure with C");
+        parser.refer(&mut config.hold_resets_on_lock)
+            .add_option(&["--hold-resets-on-spawn"], argparse::StoreFalse,
+                        "Let hold be used again when a new figure is drawn, including the one \
+                         drawn by the first hold, instead of when a figure locks");
+        parser.refer(&mut config.endless_cheese)
+            .add_option(&["--endless-cheese"], argparse::StoreTrue,
+                        "In cheese mode, add new garbage lines as old ones are cleared");
+        parser.refer(&mut config.debug_board)
+            .add_option(&["--debug-board"], argparse::StoreTrue,
+                        "Show a short board hash and the current figure in the window title; \
+                         F8 prints the whole board in the format --load-board reads");
+        parser.refer(&mut board_dump_path)
+            .add_option(&["--load-board"], argparse::Store,
+                        "Start single-player games from a board printed with F8, \
+                         on a board of any size that holds its stack");
+        parser.refer(&mut config.debug_timing)
+            .add_option(&["--debug-timing"], argparse::StoreTrue,
+                        "Print how long rendering, game logic and waiting for events \
+                         take, averaged every second");
+        parser.refer(&mut seed)
+            .add_option(&["--seed"], argparse::Store,
+                        "Random seed for the figure sequence");
+        parser.refer(&mut ghost_path)
+            .add_option(&["--ghost"], argparse::Store,
+                        "Replay file to race against; replaced when beaten");
+        parser.refer(&mut gif_path)
+            .add_option(&["--record-gif"], argparse::Store,
+                        "Record the board into an animated GIF saved when the game window closes");
+        parser.refer(&mut achievements_path)
+            .add_option(&["--achievements"], argparse::Store,
+                        "File to keep unlocked achievements and the total line count in \
+                         between sessions");
+        parser.refer(&mut placements_path)
+            .add_option(&["--log-placements"], argparse::Store,
+                        "Append a CSV row per locked figure to this file: time, shape, rotation, \
+                         column, row, lines cleared, aggregate height and holes");
+        parser.refer(&mut event_log_path)
+            .add_option(&["--event-log"], argparse::Store,
+                        "Write every input event of a single-player game with the line count \
+                         after it into this CSV file when the game ends");
+        parser.refer(&mut spectate_port)
+            .add_option(&["--spectate-port"], argparse::Store,
+                        "Stream the board to TCP clients connecting to this port, see \
+                         examples/spectator.rs");
+        parser.refer(&mut spectate_public)
+            .add_option(&["--spectate-public"], argparse::StoreTrue,
+                        "Accept spectators from other machines, not only from this one");
+        parser.refer(&mut print_replay_path)
+            .add_option(&["--print-replay"], argparse::Store,
+                        "Replay a recorded game without a window and print the final board");
+        parser.refer(&mut bench_games)
+            .add_option(&["--bench"], argparse::Store,
+                        "Let a built-in player play this many games without a window and \
+                         report figures and lines per second");
+        parser.refer(&mut bench_ai_games)
+            .add_option(&["--bench-ai"], argparse::Store,
+                        "Let every --ai player play this many games with the same seeds without a \
+                         window and print a comparison table");
+        parser.refer(&mut bench_ai_policies)
+            .add_option(&["--ai"], argparse::Store,
+                        "Comma-separated players for --bench-ai: heuristic, random");
+        parser.refer(&mut bench_ai_csv)
+            .add_option(&["--bench-ai-csv"], argparse::Store,
+                        "Write a CSV row per --bench-ai game to this file: player, seed, figures, \
+                         lines, duration and final state hash");
+        parser.refer(&mut optimize_generations)
+            .add_option(&["--optimize"], argparse::Store,
+                        "Tune the built-in player's weights with a genetic algorithm for this \
+                         many generations without a window, saving the best ones to \
+                         optimizer_result.json");
+        parser.refer(&mut population_size)
+            .add_option(&["--population"], argparse::Store,
+                        "Number of weight sets playing in every --optimize generation");
+        parser.refer(&mut figure_weights)
+            .add_option(&["--figure-weights"], argparse::Store,
+                        "Relative weights of cube, line, left L, right L, left zigzag, \
+                         right zigzag and pyramid, e.g. 1,1,1,1,1,1,1");
+        parser.refer(&mut colors)
+            .add_option(&["--colors"], argparse::Store,
+                        "Comma-separated shape=color overrides, where shape is cube, line, left_l, \
+                         right_l, left_zigzag, right_zigzag or pyramid and color is a color \
+                         name (red, orange, yellow, green, blue, deep_blue, purple, gray) or \
+                         #rrggbb, e.g. line=blue,pyramid=#ff8800");
+        parser.refer(&mut lang)
+            .add_option(&["--lang"], argparse::Store,
+                        "Language of the on-screen text, en or es; defaults to the LANG \
+                         environment variable and falls back to English");
+        parser.refer(&mut config.starting_garbage)
+            .add_option(&["--garbage"], argparse::Store,
+                        "Start with this many garbage lines at the bottom of the board");
+        parser.refer(&mut garbage_style)
+            .add_option(&["--garbage-style"], argparse::Store,
+                        "Where the holes in garbage lines go: messy puts every hole in a random \
+                         column, clean puts them all in one column, shifting moves the hole one \
+                         column right on every line");
+        parser.parse_args_or_exit();
+    }
+
+    config.record_log = ! event_log_path.is_empty();
+
+    if let Err(e) = config.validate() {
+        println!("{}", e);
+        std::process::exit(2);
+    }
+
+    if ! figure_weights.is_empty() {
+        config.randomizer = match FigureRandomizer::from_weights(&figure_weights) {
+            Ok(randomizer) => randomizer,
+            Err(e) => {
+                println!("--figure-weights: {}", e);
+                std::process::exit(2);
+            },
+        };
+    }
+
+    if ! colors.is_empty() {
+        config.colors = match ColorScheme::from_spec(&colors) {
+            Ok(colors) => colors,
+            Err(e) => {
+                println!("--colors: {}", e);
+                std::process::exit(2);
+            },
+        };
+    }
+
+    if ! garbage_style.is_empty() {
+        config.garbage_style = match GarbageStyle::from_name(&garbage_style) {
+            Some(style) => style,
+            None => {
+                println!("--garbage-style: expected messy, clean or shifting, got {}", garbage_style);
+                std::process::exit(2);
+            },
+        };
+    }
+
+    config.strings = if lang.is_empty() {
+        strings::Strings::from_env()
+    } else {
+        match strings::Strings::for_code(&lang) {
+            Some(strings) => strings,
+            None => {
+                println!("--lang: unknown language {}", lang);
+                std::process::exit(2);
+            },
+        }
+    };
+
+    if bench_games > 0 {
+        bench::run(seed, bench_games, &config);
+        return;
+    }
+
+    if bench_ai_games > 0 {
+        match tournament::parse_policies(&bench_ai_policies) {
+            Ok(policies) => tournament::run(seed, bench_ai_games, &policies, &bench_ai_csv, &config),
+            Err(e) => {
+                println!("--ai: {}", e);
+                std::process::exit(2);
+            },
+        }
+        return;
+    }
+
+    if optimize_generations > 0 {
+        optimize::run(seed, optimize_generations, max(population_size, 2), &config);
+        return;
+    }
+
+    if ! print_replay_path.is_empty() {
+        match replay::Replay::load(&PathBuf::from(&print_replay_path)) {
+            Ok(replay) => print!("{}", replay.final_board()),
+            Err(e) => {
+                println!("Can not load {}: {}", print_replay_path, e);
+                std::process::exit(1);
+            },
+        }
+        return;
+    }
+
+    let board_dump = if board_dump_path.is_empty() {
+        None
+    } else {
+        let parsed = std::fs::read_to_string(&board_dump_path)
+            .map_err(|e| e.to_string())
+            .and_then(|text| boarddump::parse(&text));
+        match parsed {
+            Ok(dump) => Some(dump),
+            Err(e) => {
+                println!("Can not load {}: {}", board_dump_path, e);
+                std::process::exit(1);
+            },
+        }
+    };
+
+    let spectators = if spectate_port == 0 {
+        None
+    } else {
+        match spectate::Spectators::listen(spectate_port, spectate_public) {
+            Ok(spectators) => Some(spectators),
+            Err(e) => {
+                println!("Can not listen for spectators on port {}: {}", spectate_port, e);
+                std::process::exit(1);
+            },
+        }
+    };
+
+    let mut game = new_game(&config, seed, &ghost_path, &gif_path, &achievements_path, &placements_path);
+    let window_size = game.window_size();
+
+    let sdl_context = sdl2::init().unwrap();
+
+    let mut window = sdl_context.video().unwrap().window(WINDOW_TITLE, window_size.0, window_size.1)
+        .hidden().build().unwrap();
+    match window_icon(&game.cell_screen) {
+        Ok(icon) => window.set_icon(icon),
+        Err(e) => println!("Can not create the window icon: {}", e),
+    }
+    window.show();
+
+    let mut event_pump = sdl_context.event_pump().unwrap();
+    let mut renderer = window.renderer().build().unwrap();
+
+    let mut mode = config.mode;
+    let mut exit = GameExit::Title;
+    loop {
+        if exit != GameExit::Restart {
+            match title::run(&mut event_pump, &mut renderer, window_size, &config.strings, mode) {
+                Some(selected) => mode = selected,
+                None => break,
+            }
+        }
+        set_window_title(&mut renderer,
+                         &format!("{} - {}", WINDOW_TITLE, mode.name(&config.strings)));
+        exit = if mode == GameMode::HotSeat {
+            hot_seat(&config, &achievements_path, &placements_path,
+                     &mut event_pump, &mut renderer, window_size)
+        } else {
+            if mode == GameMode::Cheese {
+                let cheese_config = GameConfig { mode, .. config.clone() };
+                game = new_game(&cheese_config, rand::random(), "", &gif_path,
+                                &achievements_path, &placements_path);
+            }
+            game.config.mode = mode;
+            game.config.invisible = config.invisible || mode == GameMode::Invisible;
+            let starting_level = game.config.starting_level;
+            game.set_level(starting_level);
+            game.spectators = spectators.clone();
+            if let Some(ref dump) = board_dump {
+                if let Err(e) = game.load_board_dump(dump) {
+                    println!("Can not load {}: {}", board_dump_path, e);
+                    std::process::exit(1);
+                }
+            }
+            game.run(&mut event_pump, &mut renderer)
+        };
+        set_window_title(&mut renderer, WINDOW_TITLE);
+        if let Some(ref log) = game.event_log {
+            if let Err(e) = log.export_csv(Path::new(&event_log_path)) {
+                println!("{}: {}", event_log_path, e);
+            }
+        }
+        if exit == GameExit::Quit {
+            break;
+        }
+        game = new_game(&config, rand::random(), &ghost_path, &gif_path,
+                        &achievements_path, &placements_path);
+    }
+}
+
+
+/// Without SDL there is no window to play in: the web build drives the game through the exports
+/// in `web`.
+#[cfg(not(feature = "sdl"))]
+fn main() {}
+
+
+#[cfg(test)]
+mod tests {
+    use rand::Rng;
+
+    use super::*;
+
+
+    #[test]
+    fn point_offsets_add_negate_and_scale() {
+        assert_eq!(PointOffset(1, -2) + PointOffset(-3, 5), PointOffset(-2, 3));
+        assert_eq!(-PointOffset(4, -7), PointOffset(-4, 7));
+        assert_eq!(PointOffset(2, -3) * 3, PointOffset(6, -9));
+        assert_eq!(PointOffset(2, -3) * -1, -PointOffset(2, -3));
+
+        assert_eq!(PointOffset(isize::MAX, isize::MIN) + PointOffset(isize::MIN, isize::MAX),
+                   PointOffset(-1, -1));
+        assert_eq!(-PointOffset(isize::MAX, -isize::MAX), PointOffset(-isize::MAX, isize::MAX));
+        assert_eq!(PointOffset(isize::MAX, isize::MIN) * 1, PointOffset(isize::MAX, isize::MIN));
+        assert_eq!(PointOffset(isize::MAX, 0) * -1, PointOffset(-isize::MAX, 0));
+    }
+
+
+    #[test]
+    #[cfg(debug_assertions)]
+    #[should_panic(expected = "overflow")]
+    fn point_offsets_do_not_wrap_around() {
+        let _ = PointOffset(isize::MAX, 0) + PointOffset(1, 0);
+    }
+
+
+    #[test]
+    #[cfg(debug_assertions)]
+    #[should_panic(expected = "overflow")]
+    fn negating_the_smallest_point_offset_overflows() {
+        let _ = -PointOffset(isize::MIN, 0);
+    }
+
+
+    #[test]
+    fn soft_drop_waits_for_lock_delay_on_the_floor() {
+        let mut game = TetrisGame::new(seeded_rng(1), GameConfig::default());
+        let mut timer = GameTimer::new(game.gravity_rows_per_second, game.config.soft_drop_factor);
+        game.drop_figure_to_floor(0);
+        let figures_generated = game.figures_generated;
+
+        for now_ms in (1000 .. 1000 + LOCK_DELAY_MS).step_by(5) {
+            game.gravity_tick(&mut timer, true, now_ms, now_ms);
+            assert_eq!(game.figures_generated, figures_generated, "locked at {} ms", now_ms);
+        }
+        assert_eq!(game.lock_deadline_ms, Some(1000 + LOCK_DELAY_MS));
+
+        game.gravity_tick(&mut timer, true, 1000 + LOCK_DELAY_MS, 1000 + LOCK_DELAY_MS);
+        assert_eq!(game.figures_generated, figures_generated + 1);
+    }
+
+
+    #[test]
+    fn piece_age_restarts_on_spawn_and_hold() {
+        let mut game = TetrisGame::new(seeded_rng(1), GameConfig::default());
+        let mut timer = GameTimer::new(game.gravity_rows_per_second, game.config.soft_drop_factor);
+        assert_eq!(game.current_piece_age_ms(500), 0);
+        timer.reset_drop(1000);
+        game.gravity_tick(&mut timer, false, 1000, 1000);
+        assert_eq!(game.current_piece_age_ms(1000), 0);
+        game.gravity_tick(&mut timer, false, 1300, 1300);
+        assert_eq!(game.current_piece_age_ms(1300), 300);
+
+        game.dispatch_event(GameInputEvent::Hold, 1400);
+        game.gravity_tick(&mut timer, false, 1400, 1400);
+        assert_eq!(game.current_piece_age_ms(1450), 50);
+
+        let figures_generated = game.figures_generated;
+        game.drop_figure_to_floor(1500);
+        let mut now_ms = 1500;
+        while game.figures_generated == figures_generated {
+            now_ms += 10;
+            game.gravity_tick(&mut timer, false, now_ms, now_ms);
+        }
+        assert_eq!(game.current_piece_age_ms(now_ms + 20), 20);
+    }
+
+
+    #[test]
+    fn hold_follows_allow_hold_and_hold_resets_on_lock() {
+        let falling = |game: &TetrisGame<rand::XorShiftRng>| game.cell_screen.get_figure().unwrap();
+        for &(allow_hold, hold_resets_on_lock) in &[(false, false), (false, true),
+                                                     (true, false), (true, true)] {
+            let config = GameConfig { allow_hold, hold_resets_on_lock, .. GameConfig::default() };
+            let mut game = TetrisGame::new(seeded_rng(1), config);
+            let first = falling(&game);
+            game.dispatch_event(GameInputEvent::Hold, 0);
+            if ! allow_hold {
+                assert_eq!(falling(&game), first);
+                assert_eq!((game.held_figure.clone(), game.hold_used), (None, false));
+                continue;
+            }
+
+            // The held figure is shown turned back to its spawn rotation and greyed out until
+            // hold is usable again.
+            let held = Figure::new(first.2.kind, 0);
+            assert_eq!((game.held_figure.clone(), game.hold_used), (Some(held.clone()), true));
+            let second = falling(&game);
+            game.dispatch_event(GameInputEvent::Hold, 0);
+            assert_eq!(falling(&game), second, "hold was used twice for one figure");
+
+            assert!(game.apply_placement((second.0).0, 0));
+            assert_eq!((game.held_figure.clone(), game.hold_used), (Some(held.clone()), false));
+            let third = falling(&game);
+            game.dispatch_event(GameInputEvent::Hold, 0);
+            assert_eq!(falling(&game).2, held);
+            assert_eq!(game.held_figure, Some(Figure::new(third.2.kind, 0)));
+            assert!(game.hold_used);
+        }
+    }
+
+
+    fn chi_square(randomizer: &FigureRandomizer, seed: u32, draws: usize) -> f64 {
+        let mut rng = seeded_rng(seed);
+        let mut counts = [0usize; 7];
+        for _ in 0 .. draws {
+            let kind = randomizer.next_figure(&mut rng).kind;
+            counts[FIGURE_KINDS.iter().position(|&k| k == kind).unwrap()] += 1;
+        }
+        let total: u32 = randomizer.weights.iter().sum();
+        counts.iter().zip(randomizer.weights.iter()).map(|(&observed, &weight)| {
+            let expected = draws as f64 * weight as f64 / total as f64;
+            (observed as f64 - expected).powi(2) / expected
+        }).sum()
+    }
+
+    #[test]
+    fn figure_kinds_follow_the_weights() {
+        // 22.46 is the 0.1% critical value for 6 degrees of freedom.
+        let uniform = FigureRandomizer::default();
+        let weighted = FigureRandomizer::from_weights("1,2,3,4,5,6,7").unwrap();
+        for &seed in &[1, 2, 3] {
+            let stat = chi_square(&uniform, seed, 100_000);
+            assert!(stat < 22.46, "uniform, seed {}: chi-square {}", seed, stat);
+            let stat = chi_square(&weighted, seed, 100_000);
+            assert!(stat < 22.46, "weighted, seed {}: chi-square {}", seed, stat);
+        }
+    }
+
+    #[test]
+    fn figure_weights_must_fit_into_u32() {
+        assert!(FigureRandomizer::from_weights("4294967295,0,0,0,0,0,0").is_ok());
+        assert!(FigureRandomizer::from_weights("4294967295,1,0,0,0,0,0").is_err());
+        assert!(FigureRandomizer::from_weights("2147483648,2147483648,0,0,0,0,0").is_err());
+        assert!(FigureRandomizer::from_weights("0,0,0,0,0,0,0").is_err());
+        assert!(FigureRandomizer::from_weights("1,1,1").is_err());
+    }
+
+    #[test]
+    fn every_drought_ends_within_a_bag_of_seven() {
+        let mut stats = PieceStats::default();
+        for &kind in &[FigureKind::Line, FigureKind::Line, FigureKind::Line, FigureKind::Cube] {
+            stats.record(kind);
+        }
+        assert_eq!(stats.drought(FigureKind::Line), 1);
+        assert_eq!(stats.drought(FigureKind::Cube), 0);
+        assert_eq!(stats.drought(FigureKind::Pyramid), 4);
+
+        let bag = [
+            FigureKind::Pyramid, FigureKind::RightL, FigureKind::Line, FigureKind::LeftZigzag,
+            FigureKind::Cube, FigureKind::LeftL, FigureKind::RightZigzag,
+        ];
+        for &kind in &bag {
+            stats.record(kind);
+        }
+        for (position, &kind) in bag.iter().enumerate() {
+            assert_eq!(stats.drought(kind), (bag.len() - 1 - position) as u32, "{:?}", kind);
+        }
+        assert_eq!(stats.count.iter().sum::<u32>(), 11);
+        assert_eq!(stats.count[FigureKind::Line as usize], 4);
+        // The right zigzag waited for the first four figures and six of the bag.
+        assert_eq!(stats.max_drought_ever(), (FigureKind::RightZigzag, 10));
+    }
+
+
+    #[test]
+    fn game_timer_follows_a_fake_clock() {
+        let mut timer = GameTimer::new(2.0, 20.0);
+        let mut now_ms = 0;
+        let mut ticks = |timer: &mut GameTimer, until_ms: u64, held: bool| {
+            let mut moves = Vec::new();
+            while now_ms < until_ms {
+                now_ms += 10;
+                if timer.should_move(now_ms, held) {
+                    moves.push(now_ms);
+                }
+            }
+            moves
+        };
+
+        // The first move is immediate, the second waits for DAS, then ARR repeats.
+        assert_eq!(ticks(&mut timer, 400, true), vec![10, 130, 250, 370]);
+        // Releasing the key restarts the DAS charge.
+        assert_eq!(ticks(&mut timer, 500, false), Vec::<u64>::new());
+        assert_eq!(ticks(&mut timer, 700, true), vec![510, 630]);
+        assert_eq!(ticks(&mut timer, 1000, false), Vec::<u64>::new());
+
+        timer.reset_drop(1000);
+        assert_eq!(timer.rows_to_drop(1250), 0);
+        assert_eq!(timer.ms_until_next(1250), Some(250));
+        assert_eq!(timer.rows_to_drop(1500), 1);
+        assert_eq!(timer.rows_to_drop(3000), 3);
+
+        // 2 rows per second sped up 20 times is one soft drop every 25 ms.
+        assert!(timer.should_soft_drop(3000, true));
+        assert!(! timer.should_soft_drop(3010, true));
+        assert!(timer.should_soft_drop(3025, true));
+        assert!(! timer.should_soft_drop(3030, false));
+        assert!(timer.should_soft_drop(3035, true));
+    }
+
+
+    #[test]
+    fn iter_cells_visits_every_cell_in_row_major_order() {
+        let mut screen = TetrisCellScreen::new(4, 4, 10);
+        screen.set_cell(Point(1, 2), Some(TetrisCellColor::Red));
+        let cells = screen.iter_cells().collect::<Vec<_>>();
+        assert_eq!(cells.len(), 16);
+        assert_eq!(cells[0].0, Point(0, 0));
+        assert_eq!(cells[4].0, Point(0, 1));
+        assert_eq!(cells[2 * 4 + 1], (Point(1, 2), Some(TetrisCellColor::Red)));
+        assert_eq!(screen.locked_cell_count(), 1);
+    }
+
+
+    #[test]
+    fn board_cells_are_readable_by_point_and_by_row() {
+        let cells = vec![None, None, None,
+                         Some(TetrisCellColor::Blue), None, Some(TetrisCellColor::Gray)];
+        let screen = TetrisCellScreen::from_cells(cells, Dimensions(3, 2)).unwrap();
+        assert_eq!(screen[Point(0, 1)], Some(TetrisCellColor::Blue));
+        assert_eq!(screen[Point(1, 1)], None);
+
+        let rows = screen.iter_rows().collect::<Vec<_>>();
+        assert_eq!(rows.len(), 2);
+        assert_eq!(rows[0], &[None, None, None][..]);
+        assert_eq!(rows[1], &[Some(TetrisCellColor::Blue), None, Some(TetrisCellColor::Gray)][..]);
+
+        assert_eq!(screen.to_compact_string(), "...\nB.X\n");
+    }
+
+    #[test]
+    #[should_panic(expected = "Point(3, 0) is out of the 3x2 board")]
+    fn indexing_outside_the_board_names_the_point() {
+        let screen = TetrisCellScreen::new(3, 2, 10);
+        let _ = screen[Point(3, 0)];
+    }
+
+
+    #[test]
+    fn filled_top_line_is_cleared() {
+        let config = GameConfig { columns: 4, lines: 4, .. GameConfig::default() };
+        let mut game = TetrisGame::new(seeded_rng(1), config);
+        let cells = (0 .. 16)
+            .map(|i| if i < 4 || i == 15 { Some(TetrisCellColor::Gray) } else { None })
+            .collect();
+        game.import_board_state(&BoardState { cells, dim: Dimensions(4, 4) }).unwrap();
+        assert_eq!(game.remove_filled_lines(), 0);
+        assert_eq!(game.lines_cleared, 1);
+        assert_eq!(game.cell_screen.to_compact_string(), "....\n....\n....\n...X\n");
+    }
 
-        const MOVE_PERIOD_MS: u64 = 120;
-        let mut last_move_time_ms: u64 = 0;
 
-        let mut auto_move_down_period = 500;
-        let mut last_auto_move_down_ms: u64 = precise_time_ms();
+    fn drop_line_into_the_left_column<Random: rand::Rng + Clone>(game: &mut TetrisGame<Random>)
+        -> Result<(), GameError> {
+        let line = FIGURE_KINDS[1].rotations()[1].clone();
+        assert!(game.force_figure(line, 0));
+        while game.figure_can_move_down() {
+            game.handle_event(GameInputEvent::SoftDrop)?;
+        }
+        game.handle_event(GameInputEvent::SoftDrop)
+    }
 
-        const SPEED_UP_AFTER_FIGURE_COUNT: usize = 100;
-        let mut last_speed_up_was_at_figure = 0;
+    #[test]
+    fn pending_garbage_is_cancelled_by_cleared_lines() {
+        let config = GameConfig { columns: 4, lines: 8, .. GameConfig::default() };
+        let mut game = TetrisGame::new(seeded_rng(1), config);
+        let cells = (0 .. 32)
+            .map(|i| if i >= 24 && i % 4 != 0 { Some(TetrisCellColor::Gray) } else { None })
+            .collect();
+        game.import_board_state(&BoardState { cells, dim: Dimensions(4, 8) }).unwrap();
+        game.queue_garbage(1);
+        game.queue_garbage(3);
+        assert_eq!(game.pending_garbage_rows(), 4);
+
+        game.handle_event(GameInputEvent::MoveRight).unwrap();
+        assert_eq!(game.pending_garbage_rows(), 4);
+
+        // Two cleared lines cancel the first attack and one row of the second.
+        drop_line_into_the_left_column(&mut game).unwrap();
+        assert_eq!(game.lines_cleared, 2);
+        assert_eq!(game.pending_garbage_rows(), 0);
+        assert_eq!(game.garbage_remaining, 2);
+        let rows = game.cell_screen.iter_rows().collect::<Vec<_>>();
+        assert!(rows[.. 4].iter().all(|row| row.iter().all(|cell| cell.is_none())));
+        assert!(rows[4 .. 6].iter().all(|row| row[0].is_some()));
+        assert!(rows[6 ..].iter().all(|row| row.iter().filter(|cell| cell.is_none()).count() == 1));
+    }
 
-        const FAST_MOVE_DOWN_MS: u64 = 15;
-        let mut figure_when_move_down_pressed = None;
+    #[test]
+    fn pending_garbage_can_top_out() {
+        let config = GameConfig { columns: 4, lines: 8, .. GameConfig::default() };
+        let mut game = TetrisGame::new(seeded_rng(1), config);
+        game.queue_garbage(5);
+        assert_eq!(drop_line_into_the_left_column(&mut game),
+                   Err(GameError::BoardOverflow { at_row: 0 }));
+        assert_eq!(game.pending_garbage_rows(), 0);
+    }
 
-        let mut move_left_pressed = false;
-        let mut move_right_pressed = false;
-        let mut move_down_pressed = false;
 
-        'game_loop: loop {
-            self.cell_screen.render_cell_screen(renderer);
-            renderer.present();
+    #[test]
+    fn every_figure_spawns_inside_narrow_boards() {
+        for &columns in &[4, 5, 9] {
+            let config = GameConfig { columns, lines: 10, .. GameConfig::default() };
+            let mut game = TetrisGame::new(seeded_rng(1), config);
+            for figure in FIGURE_KINDS.iter().flat_map(|kind| kind.rotations()) {
+                let Point(x, y) = game.spawn_point(&figure);
+                let (width, _) = figure.bounding_box();
+                assert!(x + width <= columns, "{:?} spawns at {} on {} columns", figure, x, columns);
+                assert_eq!(y, 0);
+                game.cell_screen.clear_figure();
+                assert_eq!(game.spawn_or_top_out(figure.clone()), Ok(()));
+            }
+        }
 
-            let mut rotate_pressed = false;
+        // Odd boards center like the even board one column narrower, leaving the extra
+        // column on the right.
+        let even = TetrisGame::new(seeded_rng(1), GameConfig { columns: 8, .. GameConfig::default() });
+        let odd = TetrisGame::new(seeded_rng(1), GameConfig { columns: 9, .. GameConfig::default() });
+        for figure in FIGURE_KINDS.iter().flat_map(|kind| kind.rotations()) {
+            assert_eq!(odd.spawn_point(&figure), even.spawn_point(&figure), "{:?}", figure);
+        }
+    }
 
-            let wait_timeout: u32 = if is_paused || ! running {
-                1000
-            } else {
-                let current_time_ms = precise_time_ms();
-                let result = min(
-                    max(1, auto_move_down_period as i32
-                        + last_auto_move_down_ms as i32
-                        - current_time_ms as i32),
-                    max(1, MOVE_PERIOD_MS as i32
-                        + last_move_time_ms as i32
-                        - current_time_ms as i32)
-                        ) as u32;
-                if move_down_pressed {
-                    min(FAST_MOVE_DOWN_MS as u32, result)
-                } else {
-                    result
-                }
-            };
+    #[test]
+    fn blocked_spawn_on_a_narrow_board_is_an_overflow() {
+        let config = GameConfig { columns: 4, lines: 10, .. GameConfig::default() };
+        let mut game = TetrisGame::new(seeded_rng(1), config);
+        game.cell_screen.clear_figure();
+        game.cell_screen.set_cell(Point(3, 0), Some(TetrisCellColor::Gray));
+        let line = FIGURE_KINDS[1].rotations()[0].clone();
+        assert_eq!(game.spawn_or_top_out(line), Err(GameError::BoardOverflow { at_row: 0 }));
+    }
 
-            match event_pump.wait_event_timeout(wait_timeout) {
-                Some(Event::KeyDown {keycode: Some(kc), repeat: false, ..}) => match kc {
-                    Keycode::Q | Keycode::Escape => { break 'game_loop },
-                    Keycode::P => is_paused = ! is_paused,
-                    Keycode::Left => move_left_pressed = true,
-                    Keycode::Right => move_right_pressed = true,
-                    Keycode::Up => rotate_pressed = true,
-                    Keycode::Down | Keycode::Space => move_down_pressed = true,
-                    _ => {},
-                },
-                Some(Event::KeyUp {keycode: Some(kc), ..}) => match kc {
-                    Keycode::Left => move_left_pressed = false,
-                    Keycode::Right => move_right_pressed = false,
-                    Keycode::Down | Keycode::Space => move_down_pressed = false,
-                    _ => {},
-                },
-                _ => {},
-            }
 
+    #[test]
+    fn line_drops_to_the_floor_of_an_empty_board() {
+        let mut game = TetrisGame::new(seeded_rng(1), GameConfig::default());
+        let lines = game.config.lines;
+        assert!(game.force_figure(FIGURE_KINDS[1].rotations()[1].clone(), 0));
+        assert_eq!(game.drop_distance(), lines - 4);
+        assert!(game.force_figure(FIGURE_KINDS[1].rotations()[0].clone(), 0));
+        assert_eq!(game.drop_distance(), lines - 1);
+    }
 
-            if ! running { continue }
-            if is_paused { continue }
 
-            let current_time_ms = precise_time_ms();
-            if move_left_pressed || move_right_pressed {
-                if last_move_time_ms + MOVE_PERIOD_MS <= current_time_ms {
-                    let event = if move_left_pressed {
-                        GameInputEvent::MoveLeft
-                    } else {
-                        GameInputEvent::MoveRight
-                    };
-                    running = self.handle_event(event);
-                    last_move_time_ms = current_time_ms;
-                }
-            } else {
-                last_move_time_ms = 0;
-            }
+    #[test]
+    fn mirror_flips_every_figure_left_to_right() {
+        let figures = FIGURE_KINDS.iter().flat_map(|kind| kind.rotations()).collect::<Vec<_>>();
+        assert_eq!(figures.len(), 19);
+        for figure in figures {
+            let mirrored = figure.mirror();
+            assert_eq!(mirrored.mirror(), figure);
+
+            let (width, _) = figure.bounding_box();
+            let mut flipped = figure.cells_iter(Point(0, 0))
+                .map(|Point(x, y)| Point(width - 1 - x, y))
+                .collect::<Vec<_>>();
+            let mut cells = mirrored.cells_iter(Point(0, 0)).collect::<Vec<_>>();
+            flipped.sort_by_key(|&Point(x, y)| (y, x));
+            cells.sort_by_key(|&Point(x, y)| (y, x));
+            assert_eq!(cells, flipped, "{:?} mirrors to {:?}", figure, mirrored);
+        }
+    }
 
-            if rotate_pressed {
-                self.handle_event(GameInputEvent::RotateClockwise);
-            }
 
-            if last_speed_up_was_at_figure + SPEED_UP_AFTER_FIGURE_COUNT <= self.figures_generated {
-                last_speed_up_was_at_figure = self.figures_generated;
-                auto_move_down_period = max(auto_move_down_period * 3 / 4, FAST_MOVE_DOWN_MS);
+    #[test]
+    fn four_rotations_bring_every_figure_back_in_place() {
+        // Four times the center of mass of the four cells, to keep it in integers.
+        let center = |figure: &Figure, PointOffset(dx, dy): PointOffset| {
+            figure.cells_iter(Point(0, 0))
+                .fold((0, 0), |(x, y), Point(cx, cy)| (x + cx as isize + dx, y + cy as isize + dy))
+        };
+        for kind in FIGURE_KINDS.iter() {
+            let start = Figure::new(*kind, 0);
+            let (mut figure, mut total) = (start.clone(), PointOffset(0, 0));
+            for _ in 0 .. 4 {
+                let (offset, rotated) = figure.clone().rotate_clockwise();
+                let (before, after) = (center(&figure, total), center(&rotated, total + offset));
+                assert!((after.0 - before.0).abs() <= 2 && (after.1 - before.1).abs() <= 2,
+                        "{:?} drifts by more than half a cell", figure);
+                figure = rotated;
+                total = total + offset;
             }
+            assert_eq!(figure, start);
+            assert_eq!(total, PointOffset(0, 0), "{:?} drifts after a full turn", kind);
+        }
+    }
 
-            let period = if move_down_pressed {
-                match figure_when_move_down_pressed {
-                    None => {
-                        figure_when_move_down_pressed = Some(self.figures_generated);
-                        FAST_MOVE_DOWN_MS
-                    },
-                    Some(x) if x == self.figures_generated => {
-                        FAST_MOVE_DOWN_MS
-                    },
-                    _ => auto_move_down_period,
-                }
+
+    #[test]
+    fn zigzag_flips_in_a_slot_too_tight_for_a_quarter_turn() {
+        let mut game = TetrisGame::new(seeded_rng(1), GameConfig::default());
+        game.cell_screen = TetrisCellScreen::from_bitboard(
+            &[0b00000, 0b00000, 0b00000, 0b10011, 0b11001, 0b11110],
+            Dimensions(5, 6), TetrisCellColor::Gray).unwrap();
+        let zigzag = Figure::new(FigureKind::LeftZigzag, 0);
+        game.cell_screen.set_figure(Point(1, 3), TetrisCellColor::Green, zigzag.clone());
+
+        game.lock_deadline_ms = Some(1);
+        game.rotate_clockwise();
+        game.rotate_clockwise();
+        assert_eq!(game.cell_screen.get_figure().unwrap().0, Point(1, 3));
+        assert_eq!(game.cell_screen.get_figure().unwrap().2, zigzag);
+        assert_eq!(game.lock_deadline_ms, Some(1), "a blocked rotation reset the lock delay");
+
+        game.rotate_180();
+        let (point, _, figure) = game.cell_screen.get_figure().unwrap();
+        assert_eq!((point, figure), (Point(1, 3), zigzag));
+        assert_eq!(game.lock_deadline_ms, None, "the flip was not applied");
+        assert_eq!(game.check_invariants(game.cell_screen.locked_cell_count()), Ok(()));
+    }
+
+
+    #[test]
+    fn apply_placement_rejects_positions_off_the_board() {
+        let mut game = TetrisGame::new(seeded_rng(1), GameConfig::default());
+        let columns = game.config.columns;
+        assert!(game.force_figure(FIGURE_KINDS[1].rotations()[1].clone(), 0));
+        let before = game.snapshot();
+
+        // A vertical line fits into the last column, a horizontal one does not.
+        assert!(! game.apply_placement(columns, 0));
+        assert!(! game.apply_placement(columns - 1, 1));
+        assert!(! game.apply_placement(columns - 3, 3));
+        assert!(! game.apply_placement(usize::MAX, 0));
+        assert_eq!(game.snapshot(), before);
+        assert_eq!(game.phase, GamePhase::Playing);
+
+        assert!(game.apply_placement(columns - 1, 4));
+        assert_eq!(game.figures_generated, before.figures_generated + 1);
+        let column = game.cell_screen.column_heights()[columns - 1];
+        assert_eq!(column, 4);
+    }
+
+    #[test]
+    fn apply_placement_locks_rotated_figures_on_the_floor() {
+        let mut game = TetrisGame::new(seeded_rng(1), GameConfig::default());
+        assert!(game.force_figure(FIGURE_KINDS[1].rotations()[0].clone(), 0));
+        assert!(game.apply_placement(2, 1));
+        let heights = game.cell_screen.column_heights();
+        assert_eq!(heights[.. 4], [0, 0, 4, 0]);
+        assert!(game.force_figure(FIGURE_KINDS[1].rotations()[0].clone(), 0));
+        assert!(game.apply_placement(3, 2));
+        assert_eq!(game.cell_screen.column_heights()[2 .. 8], [4, 1, 1, 1, 1, 0]);
+    }
+
+    #[test]
+    fn apply_placement_ends_the_game_on_a_full_board() {
+        let mut game = TetrisGame::new(seeded_rng(1), GameConfig::default());
+        let Dimensions(columns, lines) = game.cell_screen.dimensions();
+        let cells = (0 .. columns * lines)
+            .map(|i| if i < 2 * columns || i % columns == 0 {
+                None
             } else {
-                figure_when_move_down_pressed = None;
-                auto_move_down_period
-            };
+                Some(TetrisCellColor::Gray)
+            })
+            .collect();
+        game.import_board_state(&BoardState { cells, dim: Dimensions(columns, lines) }).unwrap();
+        assert!(game.force_figure(FIGURE_KINDS[1].rotations()[0].clone(), 3));
+        assert!(! game.apply_placement(3, 0));
+        assert_eq!(game.phase, GamePhase::GameOver);
+        assert!(game.game_over_error.is_some());
+    }
 
-            if last_auto_move_down_ms + period <= current_time_ms {
-                running = self.handle_event(GameInputEvent::Timer);
-                last_auto_move_down_ms = current_time_ms;
+
+    #[test]
+    fn bounding_boxes_match_the_bitmaps() {
+        let table: [(FigureKind, [(usize, usize); 4]); 7] = [
+            (FigureKind::Cube, [(2, 2); 4]),
+            (FigureKind::Line, [(4, 1), (1, 4), (4, 1), (1, 4)]),
+            (FigureKind::LeftL, [(2, 3), (3, 2), (2, 3), (3, 2)]),
+            (FigureKind::RightL, [(2, 3), (3, 2), (2, 3), (3, 2)]),
+            (FigureKind::LeftZigzag, [(3, 2), (2, 3), (3, 2), (2, 3)]),
+            (FigureKind::RightZigzag, [(3, 2), (2, 3), (3, 2), (2, 3)]),
+            (FigureKind::Pyramid, [(3, 2), (2, 3), (3, 2), (2, 3)]),
+        ];
+        for &(kind, ref boxes) in table.iter() {
+            for (figure, &expected) in kind.rotations().iter().zip(boxes.iter()) {
+                let (width, height) = figure.bounding_box();
+                assert_eq!((width, height), expected, "{:?}", figure);
+                assert_eq!(width * height, figure.bitmap().len(), "{:?}", figure);
+                #[allow(deprecated)]
+                let dimensions = figure.dimensions();
+                assert_eq!(dimensions, Dimensions(width, height), "{:?}", figure);
             }
         }
     }
 
-    fn window_size(&self) -> (u32, u32) {
-        let ws = self.cell_screen.window_size();
-        (ws.0 as u32, ws.1 as u32)
+
+    #[test]
+    fn all_figure_bitmap_dimensions_consistent() {
+        for figure in FIGURE_KINDS.iter().flat_map(|kind| kind.rotations()) {
+            let (width, height) = figure.bounding_box();
+            let bitmap = figure.bitmap();
+            assert_eq!(bitmap.len(), width * height, "{:?}", figure);
+            assert_eq!(bitmap.iter().filter(|&&filled| filled).count(), 4, "{:?}", figure);
+            // The box is tight: every edge row and column has a filled cell.
+            assert!(bitmap[.. width].iter().any(|&filled| filled), "{:?}", figure);
+            assert!(bitmap[width * (height - 1) ..].iter().any(|&filled| filled), "{:?}", figure);
+            assert!((0 .. height).any(|y| bitmap[y * width]), "{:?}", figure);
+            assert!((0 .. height).any(|y| bitmap[y * width + width - 1]), "{:?}", figure);
+        }
     }
-}
 
 
-impl <Random: rand::Rng> TetrisGame<Random> {
-    fn new(rng: Random, columns: usize, lines: usize, cell_size: usize) -> Self {
-        let mut game = TetrisGame {
-            cell_screen: TetrisCellScreen::new(columns, lines, cell_size),
-            rng: rng,
-            figures_generated: 0,
+    #[test]
+    fn big_mode_doubles_the_cells() {
+        let normal = TetrisGame::new(seeded_rng(1), GameConfig::default());
+        let big_config = GameConfig { big_mode: true, .. GameConfig::default() };
+        let big = TetrisGame::new(seeded_rng(1), big_config);
+        assert_eq!(big.cell_screen.cell_size(), Dimensions(80, 80));
+
+        // The board margin keeps its size, everything inside it doubles.
+        let board_area = |game: &TetrisGame<rand::XorShiftRng>| {
+            let Dimensions(width, height) = game.cell_screen.window_size();
+            let Dimensions(x_off, y_off) = game.cell_screen.global_offset();
+            (width - x_off * 2, height - y_off * 2)
         };
-        let can_create_first_figure = game.create_new_figure();
-        assert!(can_create_first_figure);
-        game
+        let (width, height) = board_area(&normal);
+        assert_eq!(board_area(&big), (width * 2, height * 2));
+        assert_eq!(big.cell_screen.global_offset(), normal.cell_screen.global_offset());
     }
 
-    fn create_new_figure(&mut self) -> bool {
-        self.cell_screen._figure = None;
-        let figure: Figure = self.rng.gen();
-        let offset = figure.offset_from_top_center();
-        assert!(offset.1 == 0);
-        let dim = self.cell_screen.dimensions();
-        let point = Point(
-            (dim.0 as isize / 2 + offset.0) as usize,
-            offset.1 as usize,
-            );
 
-        self.figures_generated += 1;
+    #[test]
+    fn from_cells_checks_the_cell_count() {
+        let cells = vec![Some(TetrisCellColor::Red), None, None, None, None, None];
+        let screen = TetrisCellScreen::from_cells(cells.clone(), Dimensions(3, 2)).unwrap();
+        assert_eq!(screen.dimensions(), Dimensions(3, 2));
+        assert_eq!(screen.get_figure(), None);
+        assert_eq!(screen.to_compact_string(), "R..\n...\n");
 
-        if self._figure_overlaps_cells(&point, &figure) {
-            false
-        } else {
-            self.cell_screen.set_figure(point, figure.color(), figure);
-            true
-        }
+        assert_eq!(TetrisCellScreen::from_cells(cells.clone(), Dimensions(2, 2)).err(),
+                   Some(GameError::BoardSizeMismatch { dim: Dimensions(2, 2), cells: 6 }));
+        assert_eq!(TetrisCellScreen::from_cells(cells, Dimensions(4, 2)).err(),
+                   Some(GameError::BoardSizeMismatch { dim: Dimensions(4, 2), cells: 6 }));
     }
 
-    fn handle_event(&mut self, event: GameInputEvent) -> bool {
-        let recreate_figure: bool = match event {
-            GameInputEvent::Timer => {
-                ! self.try_move_figure_down()
-            },
-            GameInputEvent::MoveLeft => {
-                if self.cell_screen.has_figure() { self.move_figure_left() }
-                false
-            },
-            GameInputEvent::MoveRight => {
-                if self.cell_screen.has_figure() { self.move_figure_right() }
-                false
-            },
-            GameInputEvent::RotateClockwise => {
-                if self.cell_screen.has_figure() {
-                    self.rotate_clockwise();
-                }
-                false
-            },
-        };
 
-        if recreate_figure {
-            self.remove_filled_lines();
+    fn stack_with_a_cube(dim: Dimensions, bits: &[u64]) -> TetrisCellScreen {
+        let mut screen = TetrisCellScreen::from_bitboard(bits, dim, TetrisCellColor::Gray).unwrap();
+        screen.set_figure(Point(2, 0), TetrisCellColor::Yellow, FIGURE_KINDS[0].rotations()[0].clone());
+        screen
+    }
 
-            if ! self.create_new_figure() {
-                return false;
-            }
+    #[test]
+    fn resize_cuts_only_empty_rows_and_columns() {
+        let mut screen = stack_with_a_cube(Dimensions(4, 4), &[0b0000, 0b0000, 0b0100, 0b0011]);
+        for &dim in &[Dimensions(2, 4), Dimensions(4, 1), Dimensions(0, 0)] {
+            assert_eq!(screen.resize(dim),
+                       Err(GameError::BoardTooSmall { dim, needed: Dimensions(3, 2) }));
+            assert_eq!(screen.cells_as_bitboard(), [0b0000, 0b0000, 0b0100, 0b0011]);
+            assert!(screen.has_figure());
         }
 
-        true
+        assert_eq!(screen.resize(Dimensions(4, 3)), Ok(()));
+        assert_eq!(screen.cells_as_bitboard(), [0b0000, 0b0100, 0b0011]);
+        assert!(! screen.has_figure(), "the figure is pushed out of the top");
+
+        let mut screen = stack_with_a_cube(Dimensions(4, 4), &[0b0000, 0b0000, 0b0100, 0b0011]);
+        assert_eq!(screen.resize(Dimensions(3, 2)), Ok(()));
+        assert_eq!(screen.to_compact_string(), "..X\nXX.\n");
+        assert!(! screen.has_figure(), "the figure is cut by the right edge");
     }
 
-    fn move_figure_left(&mut self) {
-        let (mut point, color, figure) = self.cell_screen.get_figure().unwrap();
-        if point.0 > 0 {
-            point.0 -= 1;
-            if ! self._figure_overlaps_cells(&point, &figure) {
-                self.cell_screen.set_figure(point, color, figure);
-            }
-        }
+    #[test]
+    fn resize_grows_above_the_stack() {
+        let mut screen = stack_with_a_cube(Dimensions(4, 4), &[0b0000, 0b0000, 0b0100, 0b0011]);
+        assert_eq!(screen.resize(Dimensions(6, 6)), Ok(()));
+        assert_eq!(screen.cells_as_bitboard(), [0, 0, 0, 0, 0b0100, 0b0011]);
+        let (point, _, _) = screen.get_figure().expect("the figure is dropped");
+        assert_eq!(point, Point(2, 2));
+
+        let dump = boarddump::parse(&boarddump::dump(&screen)).unwrap();
+        let mut game = TetrisGame::new(seeded_rng(1), GameConfig::default());
+        game.load_board_dump(&dump).unwrap();
+        let Dimensions(columns, lines) = game.cell_screen.dimensions();
+        assert_eq!(game.cell_screen.locked_cell_count(), 3);
+        assert!(game.cell_screen[Point(2, lines - 2)].is_some());
+        assert_eq!(game.cell_screen.get_figure().map(|(point, _, _)| point),
+                   Some(Point(2, lines - 4)));
+
+        let wide = TetrisCellScreen::from_cells(vec![Some(TetrisCellColor::Gray); columns + 1],
+                                                Dimensions(columns + 1, 1)).unwrap();
+        let dump = boarddump::parse(&boarddump::dump(&wide)).unwrap();
+        assert_eq!(game.load_board_dump(&dump), Err(GameError::BoardTooSmall {
+            dim: Dimensions(columns, lines),
+            needed: Dimensions(columns + 1, 1),
+        }));
     }
 
-    fn move_figure_right(&mut self) {
-        let (mut point, color, figure) = self.cell_screen.get_figure().unwrap();
-        if point.0 < self.cell_screen.dimensions().0 - figure.dimensions().0 {
-            point.0 += 1;
-            if ! self._figure_overlaps_cells(&point, &figure) {
-                self.cell_screen.set_figure(point, color, figure);
-            }
+
+    #[test]
+    #[cfg(feature = "sdl")]
+    fn hud_buttons_stay_left_of_the_fill_bar() {
+        for code in &["en", "es"] {
+            let config = GameConfig {
+                strings: strings::Strings::for_code(code).unwrap(),
+                .. GameConfig::default()
+            };
+            let game = TetrisGame::new(seeded_rng(1), config);
+            let buttons = game.hud_buttons();
+            assert_eq!(buttons.iter().map(|&(button, _, _, _)| button).collect::<Vec<_>>(),
+                       vec![HudButton::Pause, HudButton::Restart, HudButton::Quit]);
+
+            let Dimensions(x_off, _) = game.cell_screen.global_offset();
+            let Dimensions(board_window_width, _) = game.cell_screen.window_size();
+            let bar_x = board_window_width - x_off - (board_window_width - x_off * 2) / 4;
+            let (_, _, Point(x, y), Dimensions(width, height)) = buttons[2];
+            assert!(x + width < bar_x, "{}: the buttons run into the fill bar", code);
+            assert_eq!(game.hud_button_at((x + width / 2) as i32, (y + height / 2) as i32),
+                       Some(HudButton::Quit));
         }
     }
 
-    fn try_move_figure_down(&mut self) -> bool {
-        let (point, color, figure) = self.cell_screen.get_figure().unwrap();
-            let fig_dim = figure.dimensions();
-
-        let can_go_down =
-            (point.1 + figure.dimensions().1) < self.cell_screen.dimensions().1
-            && ! self._figure_overlaps_cells(&Point(point.0, point.1 + 1), &figure);
 
-        if can_go_down {
-            self.cell_screen.set_figure(Point(point.0, point.1 + 1), color, figure);
-            true
-        } else {
-            let mut new_cells = self.cell_screen._figure_layer.clone().into_iter();
-            for y in point.1 .. point.1 + fig_dim.1 {
-                for x in point.0 .. point.0 + fig_dim.0 {
-                    if let Some(color) = new_cells.next().unwrap().clone() {
-                        self.cell_screen.set_cell(Point(x, y), Some(color));
-                    }
-                }
+    #[test]
+    #[cfg(feature = "sdl")]
+    fn default_bindings_map_to_distinct_events() {
+        let bindings = KeyBindings::default();
+        let events = [
+            GameInputEvent::MoveLeft, GameInputEvent::MoveRight, GameInputEvent::RotateClockwise,
+            GameInputEvent::Rotate180, GameInputEvent::SoftDrop, GameInputEvent::Hold,
+        ];
+        let mut seen = Vec::new();
+        for &event in events.iter() {
+            let codes = bindings.scancodes(event);
+            assert!(! codes.is_empty(), "{:?} is not bound", event);
+            for &code in codes {
+                assert_eq!(GameInputEvent::from_scancode(code, &bindings), Some(event), "{:?}", code);
+                assert!(! seen.contains(&code), "{:?} is bound twice", code);
+                seen.push(code);
             }
-            false
         }
+        assert_eq!(GameInputEvent::from_scancode(Scancode::Q, &bindings), None);
+        assert!(bindings.scancodes(GameInputEvent::Timer).is_empty());
+        assert!(bindings.scancodes(GameInputEvent::Undo).is_empty());
     }
 
-    fn _figure_overlaps_cells(&self, new_point: &Point, figure: &Figure) -> bool {
-        let figure_bitmap = figure.bitmap();
-        let existing_cells = &self.cell_screen.cells;
-        let fig_dim = figure.dimensions();
-        let screen_dim = self.cell_screen.dimensions();
-
-        for y in 0 .. fig_dim.1 {
-            for x in 0 .. fig_dim.0 {
-                let screen_offset_in_new_point = (new_point.1 + y) * screen_dim.0 + new_point.0 + x;
-                let is_cell_in_figure = figure_bitmap[y * fig_dim.0 + x];
-                let has_cell_here = ! existing_cells[screen_offset_in_new_point].is_none();
-                if is_cell_in_figure && has_cell_here {
-                    return true;
-                }
+
+    #[test]
+    fn config_validation_names_the_offending_option() {
+        assert_eq!(GameConfig::default().validate(), Ok(()));
+        let cases: [(GameConfig, &str); 9] = [
+            (GameConfig { columns: 3, .. GameConfig::default() }, "--columns must be at least 4"),
+            (GameConfig { lines: 3, .. GameConfig::default() }, "--lines must be at least 4"),
+            (GameConfig { cell_size: 4, .. GameConfig::default() }, "--cell-size must be at least 5"),
+            (GameConfig { lines: 6, starting_garbage: 5, .. GameConfig::default() },
+             "--garbage must be between 0 and 4"),
+            (GameConfig { gravity_rows_per_second: 0.0, .. GameConfig::default() }, "--gravity"),
+            (GameConfig { gravity_rows_per_second: f64::NAN, .. GameConfig::default() }, "--gravity"),
+            (GameConfig { soft_drop_factor: 0.5, .. GameConfig::default() }, "--soft-drop-factor"),
+            (GameConfig { soft_drop_factor: f64::NAN, .. GameConfig::default() }, "--soft-drop-factor"),
+            (GameConfig { columns: 4, lines: 4, starting_garbage: 3, .. GameConfig::default() },
+             "--garbage must be between 0 and 2 on a board with 4 lines, got 3"),
+        ];
+        for &(ref config, expected) in cases.iter() {
+            match config.validate() {
+                Err(ref e) if e.starts_with(expected) => {},
+                result => panic!("expected {:?}, got {:?}", expected, result),
             }
         }
-        false
+
+        let edge = GameConfig {
+            columns: 4,
+            lines: 4,
+            cell_size: 5,
+            starting_garbage: 2,
+            gravity_rows_per_second: f64::INFINITY,
+            soft_drop_factor: 1.0,
+            .. GameConfig::default()
+        };
+        assert_eq!(edge.validate(), Ok(()));
     }
 
-    fn rotate_clockwise(&mut self) {
-        let (point, color, figure) = self.cell_screen.get_figure().unwrap();
-        let (offset, rotated_figure) = figure.rotate_clockwise();
 
-        let new_x = (max(0, point.0 as isize + offset.0)) as usize;
-        let new_y = (max(0, point.1 as isize + offset.1)) as usize;
-        let dim = self.cell_screen.dimensions();
-        let fig_dim = rotated_figure.dimensions();
+    #[test]
+    fn force_figure_replaces_the_falling_figure() {
+        let mut game = TetrisGame::new(seeded_rng(1), GameConfig::default());
+        let figures_generated = game.figures_generated;
+        let line = FIGURE_KINDS[1].rotations()[0].clone();
+        assert!(game.force_figure(line.clone(), 3));
+        let color = game.config.colors.figure_color(&line);
+        assert_eq!(game.cell_screen.get_figure(), Some((Point(3, 0), color, line.clone())));
+        assert_eq!(game.figures_generated, figures_generated);
+        assert_eq!(game.cell_screen.locked_cell_count(), 0);
+
+        game.cell_screen.set_cell(Point(5, 0), Some(TetrisCellColor::Gray));
+        assert!(! game.force_figure(line.clone(), 3));
+        assert!(! game.force_figure(line, game.config.columns - 3));
+    }
 
-        let new_point = Point(
-            min(new_x, dim.0 - fig_dim.0),
-            min(new_y, dim.1 - fig_dim.1));
-        
-        if ! self._figure_overlaps_cells(&new_point, &rotated_figure) {
-            self.cell_screen.set_figure(new_point, color, rotated_figure);
+
+    #[test]
+    fn undo_steps_back_one_user_action_at_a_time() {
+        let config = GameConfig { undo_enabled: true, .. GameConfig::default() };
+        let mut game = TetrisGame::new(seeded_rng(1), config);
+        let Dimensions(columns, lines) = game.cell_screen.dimensions();
+        let mut cells = vec![None; columns * lines];
+        for x in 4 .. columns {
+            cells[(lines - 1) * columns + x] = Some(TetrisCellColor::Gray);
+        }
+        game.import_board_state(&BoardState { cells, dim: Dimensions(columns, lines) }).unwrap();
+        assert!(game.force_figure(FIGURE_KINDS[1].rotations()[0].clone(), 3));
+        let start = game.snapshot();
+
+        for _ in 0 .. 3 {
+            game.handle_event(GameInputEvent::MoveLeft).unwrap();
+        }
+        game.drop_figure_to_floor(0);
+        game.handle_event(GameInputEvent::Timer).unwrap();
+        assert_eq!(game.lines_cleared, 1);
+        assert_eq!(game.figures_generated, start.figures_generated + 1);
+        game.handle_event(GameInputEvent::RotateClockwise).unwrap();
+        assert_eq!(game.history.len(), 5);
+
+        for _ in 0 .. 5 {
+            game.handle_event(GameInputEvent::Undo).unwrap();
         }
+        assert!(game.snapshot() == start, "undo did not get back to the start");
+        assert_eq!(game.rng.next_u32(), start.rng.clone().next_u32());
     }
 
-    fn remove_filled_lines(&mut self) {
-        let dim = self.cell_screen.dimensions();
 
-        let mut any_filled_line = false;
-        let mut filled_lines = Vec::with_capacity(dim.1);
-        let mut cell_position = 0;
-        for _ in 0 .. dim.1 {
-            let mut filled_line = true;
-            for _ in 0 .. dim.0 {
-                filled_line &= ! self.cell_screen.cells[cell_position].is_none();
-                cell_position += 1;
-            }
-            filled_lines.push(filled_line);
-            any_filled_line |= filled_line;
+    #[test]
+    fn game_over_height_spans_empty_to_full() {
+        let mut game = TetrisGame::new(seeded_rng(1), GameConfig::default());
+        let Dimensions(columns, lines) = game.cell_screen.dimensions();
+        assert_eq!(game.expected_game_over_height(), 0);
+
+        game.cell_screen.set_cell(Point(2, lines - 3), Some(TetrisCellColor::Gray));
+        assert_eq!(game.expected_game_over_height(), 3);
+
+        let full = vec![Some(TetrisCellColor::Gray); columns * lines];
+        game.import_board_state(&BoardState { cells: full, dim: Dimensions(columns, lines) }).unwrap();
+        assert_eq!(game.expected_game_over_height(), lines as u32);
+    }
+
+
+    #[test]
+    fn das_charge_survives_a_spawn_only_with_preserve_das() {
+        const SPAWN_MS: u64 = 10_000;
+        for &preserve_das in &[true, false] {
+            let mut timer = GameTimer::new(1.0, 1.0);
+            assert!(timer.should_move(SPAWN_MS - 1000, true));
+            assert!(timer.should_move(SPAWN_MS - 1, true));
+            timer.figure_spawned(SPAWN_MS, true, preserve_das);
+            assert_eq!(timer.should_move(SPAWN_MS, true), preserve_das,
+                       "move on the spawn frame, preserve_das {}", preserve_das);
+            let das_ms = if preserve_das { timer.move_arr_ms } else { timer.move_das_ms };
+            assert!(! timer.should_move(SPAWN_MS + das_ms - 1, true));
+            assert!(timer.should_move(SPAWN_MS + das_ms, true));
+
+            timer.figure_spawned(SPAWN_MS, false, preserve_das);
+            assert!(! timer.should_move(SPAWN_MS, false));
+            assert!(timer.should_move(SPAWN_MS + 1, true));
         }
+    }
 
-        assert!(cell_position == dim.0 * dim.1);
 
-        if ! any_filled_line {
-            return;
+    #[test]
+    fn transitions_count_walls_and_holes() {
+        let boards: [([u64; 3], u32, u32); 3] = [
+            ([0b0000, 0b0000, 0b0000], 6, 4),
+            ([0b0000, 0b0101, 0b1111], 6, 4),
+            ([0b0000, 0b0101, 0b1011], 8, 6),
+        ];
+        for &(ref bits, horizontal, vertical) in boards.iter() {
+            let screen = TetrisCellScreen::from_bitboard(bits, Dimensions(4, 3), TetrisCellColor::Gray)
+                .unwrap();
+            assert_eq!((screen.aggregate_transitions(), screen.vertical_transitions()),
+                       (horizontal, vertical), "transitions on {:?}", bits);
         }
+    }
 
-        let mut offset = 0;
-        for line in 0 .. dim.1 {
-            let line = dim.1 - line;
 
-            if line == filled_lines.len() {
-                while filled_lines.pop().unwrap() {
-                    offset += dim.0;
-                }
-            }
+    #[test]
+    fn column_heights_start_at_the_topmost_cell() {
+        // An empty column, one with holes under its top cell, a full one and a short one.
+        let bits = [0b0100, 0b0110, 0b0100, 0b1100];
+        let screen = TetrisCellScreen::from_bitboard(&bits, Dimensions(4, 4), TetrisCellColor::Gray)
+            .unwrap();
+        assert_eq!(screen.column_heights(), vec![0, 3, 4, 1]);
+        assert_eq!(screen.hole_count(), 2);
+    }
 
-            for _ in 0 .. dim.0 {
-                cell_position -= 1;
-                if offset != 0 {
-                    let new_cell = if cell_position >= offset {
-                        self.cell_screen.cells[cell_position - offset]
-                    } else {
-                        None
-                    };
-                    self.cell_screen.cells[cell_position] = new_cell;
-                }
-            }
+
+    #[test]
+    fn hole_count_counts_empty_cells_under_the_stack() {
+        let boards: [([u64; 4], u32); 4] = [
+            ([0b0000, 0b0000, 0b0110, 0b1111], 0),
+            ([0b0000, 0b0000, 0b0100, 0b1011], 1),
+            // A covered column counts every empty cell below its top, not just the first one.
+            ([0b0010, 0b0000, 0b0000, 0b1101], 3),
+            ([0b1111, 0b0001, 0b0010, 0b0100], 9),
+        ];
+        for &(ref bits, holes) in boards.iter() {
+            let screen = TetrisCellScreen::from_bitboard(bits, Dimensions(4, 4), TetrisCellColor::Gray)
+                .unwrap();
+            assert_eq!(screen.hole_count(), holes, "holes in {:?}", bits);
         }
     }
-}
 
 
-#[derive(Clone, PartialEq, Debug)]
-enum Figure {
-    Cube,
-    LineHorizontal,
-    LineVertical,
-
-    LeftL0,
-    LeftL90,
-    LeftL180,
-    LeftL270,
-    RightL0,
-    RightL90,
-    RightL180,
-    RightL270,
-
-    LeftZigzagHorizontal,
-    LeftZigzagVertical,
-    RightZigzagHorizontal,
-    RightZigzagVertical,
-
-    Pyramid0,
-    Pyramid90,
-    Pyramid180,
-    Pyramid270,
-}
-
-
-const CUBE_CELLS: &'static [bool] = &[
-    true, true,
-    true, true,
-    ];
+    #[test]
+    fn bitboards_must_match_the_board_size() {
+        let dim = Dimensions(70, 2);
+        let error = TetrisCellScreen::from_bitboard(&[0, 0, 0], dim, TetrisCellColor::Gray).err();
+        assert_eq!(error, Some(GameError::BitboardSizeMismatch { dim, words: 3 }));
+        assert_eq!(error.unwrap().to_string(), "a 70x2 board needs 4 bitboard words, got 3");
 
-const LINE_HORIZONTAL: &'static [bool] = &[
-    true, true, true, true
-    ];
+        let bits = [1 << 63, 1 << 5, 0, 0b11];
+        let screen = TetrisCellScreen::from_bitboard(&bits, dim, TetrisCellColor::Gray).unwrap();
+        assert_eq!(screen.locked_cell_count(), 4);
+        assert!(screen.bitboard_eq(&bits));
+        assert!(! screen.bitboard_eq(&[1 << 63, 1 << 5, 0, 0b01]));
+        assert!(! screen.bitboard_eq(&bits[.. 3]));
+    }
 
-const LINE_VERTICAL: &'static [bool] = LINE_HORIZONTAL;
 
-const LEFT_L_0: &'static [bool] = &[
-    true,  true,
-    false, true,
-    false, true,
-    ];
+    #[test]
+    fn zen_mode_clears_the_bottom_half_on_top_out() {
+        let mut screen = TetrisCellScreen::from_bitboard(&[0b0001, 0b0010, 0b0100, 0b1000, 0b0011],
+                                                         Dimensions(4, 5), TetrisCellColor::Gray)
+            .unwrap();
+        assert_eq!(screen.clear_bottom_half(), 3);
+        assert_eq!(screen.cells_as_bitboard(), [0b0000, 0b0000, 0b0000, 0b0001, 0b0010]);
+
+        let config = GameConfig { mode: GameMode::Zen, .. GameConfig::default() };
+        let Dimensions(columns, lines) = Dimensions(config.columns, config.lines);
+        let mut game = TetrisGame::new(seeded_rng(1), config);
+        let cells = (0 .. columns * lines)
+            .map(|i| if i % columns == 0 { None } else { Some(TetrisCellColor::Gray) })
+            .collect();
+        game.import_board_state(&BoardState { cells, dim: Dimensions(columns, lines) }).unwrap();
+        assert_eq!(game.create_new_figure(), Ok(()));
+        assert_eq!(game.cell_screen.locked_cell_count(), lines / 2 * (columns - 1));
+    }
 
-const LEFT_L_90: &'static [bool] = &[
-    false, false, true,
-    true,  true,  true,
-    ];
 
-const LEFT_L_180: &'static [bool] = &[
-    true,  false,
-    true,  false,
-    true,  true,
-    ];
+    #[test]
+    fn compaction_preview_drops_the_cleared_rows() {
+        let dim = Dimensions(4, 5);
+        let screen = TetrisCellScreen::from_bitboard(&[0b0000, 0b0100, 0b1111, 0b0110, 0b1111],
+                                                     dim, TetrisCellColor::Gray)
+            .unwrap();
+        let expected = TetrisCellScreen::from_bitboard(&[0b0000, 0b0000, 0b0100, 0b1111, 0b0110],
+                                                       dim, TetrisCellColor::Gray)
+            .unwrap();
+        let (mut preview_cells, mut expected_cells) = (Vec::new(), Vec::new());
+        screen.compaction_preview(&[4]).copy_cells_into(&mut preview_cells);
+        expected.copy_cells_into(&mut expected_cells);
+        assert_eq!(preview_cells, expected_cells);
+
+        let preview = screen.compaction_preview(&[2, 4]);
+        assert_eq!(preview.cells_as_bitboard(), [0b0000, 0b0000, 0b0000, 0b0100, 0b0110]);
+        assert_eq!(screen.cells_as_bitboard(), [0b0000, 0b0100, 0b1111, 0b0110, 0b1111]);
+    }
 
-const LEFT_L_270: &'static [bool] = &[
-    true,  true,  true,
-    true,  false, false,
-    ];
 
-const RIGHT_L_0: &'static [bool] = &[
-    true, true,
-    true, false,
-    true, false,
-    ];
+    #[test]
+    fn copy_cells_into_replaces_the_destination() {
+        let screen = stack_with_a_cube(Dimensions(4, 3), &[0b0000, 0b1000, 0b0111]);
+        let locked = screen.iter_cells().map(|(_, cell)| cell).collect::<Vec<_>>();
+        assert_eq!(locked.iter().filter(|cell| cell.is_some()).count(), 4);
 
-const RIGHT_L_90: &'static [bool] = &[
-    true,  true,  true,
-    false, false, true,
-    ];
+        // Stale cells are dropped whether the destination is longer or shorter than the board,
+        // and the falling cube is not copied.
+        for stale in &[vec![Some(TetrisCellColor::Red); 40], vec![], vec![None; 3]] {
+            let mut dest = stale.clone();
+            screen.copy_cells_into(&mut dest);
+            assert_eq!(dest, locked);
+        }
 
-const RIGHT_L_180: &'static [bool] = &[
-    false, true,
-    false, true,
-    true,  true,
-    ];
+        let mut dest = Vec::with_capacity(64);
+        let buffer = dest.as_ptr();
+        screen.copy_cells_into(&mut dest);
+        screen.copy_cells_into(&mut dest);
+        assert_eq!((dest.as_ptr(), dest.len()), (buffer, 12), "the destination was reallocated");
 
-const RIGHT_L_270: &'static [bool] = &[
-    true,  false, false,
-    true,  true,  true,
-    ];
+        TetrisCellScreen::new(0, 0, 10).copy_cells_into(&mut dest);
+        assert!(dest.is_empty());
+    }
 
-const LEFT_ZIGZAG_HORIZONTAL: &'static [bool] = &[
-    false, true, true,
-    true,  true, false,
-    ];
 
-const LEFT_ZIGZAG_VERTICAL: &'static [bool] = &[
-    true,  false,
-    true,  true,
-    false, true,
-    ];
+    /// The figure that `create_new_figure` draws next, before and after a clockwise turn.
+    fn next_spawn(game: &TetrisGame<rand::XorShiftRng>) -> (Figure, Figure) {
+        let figure = game.config.randomizer.next_figure(&mut game.rng.clone());
+        let rotated = figure.clone().rotate_clockwise().1;
+        (figure, rotated)
+    }
 
-const RIGHT_ZIGZAG_HORIZONTAL: &'static [bool] = &[
-    true,  true, false,
-    false, true, true,
-    ];
+    fn spawned_figure(game: &TetrisGame<rand::XorShiftRng>) -> Figure {
+        game.cell_screen.get_figure().expect("no figure spawned").2
+    }
 
-const RIGHT_ZIGZAG_VERTICAL: &'static [bool] = &[
-    false, true,
-    true,  true,
-    true,  false,
-    ];
+    fn cube_spawn_over_stacks(spawn_grace: bool, stacked_columns: &[usize], height: usize)
+                              -> Result<Point, GameError> {
+        let randomizer = FigureRandomizer::with_weights(&[1, 0, 0, 0, 0, 0, 0]).unwrap();
+        let config = GameConfig { randomizer, spawn_grace, .. GameConfig::default() };
+        let dim = Dimensions(config.columns, config.lines);
+        let mut game = TetrisGame::new(seeded_rng(1), config);
+        let cells = (0 .. dim.0 * dim.1)
+            .map(|i| if stacked_columns.contains(&(i % dim.0)) && i / dim.0 >= dim.1 - height {
+                Some(TetrisCellColor::Gray)
+            } else {
+                None
+            })
+            .collect();
+        game.import_board_state(&BoardState { cells, dim }).unwrap();
+        game.create_new_figure().map(|()| game.cell_screen.get_figure().unwrap().0)
+    }
 
-const PYRAMID_0: &'static [bool] = &[
-    false, true,  false,
-    true,  true,  true,
-    ];
+    #[test]
+    fn spawn_grace_moves_the_figure_past_a_stack_to_the_top() {
+        let lines = GameConfig::default().lines;
+        let overflow = Err(GameError::BoardOverflow { at_row: 0 });
+        for &height in &[lines - 1, lines] {
+            assert_eq!(cube_spawn_over_stacks(false, &[], height), Ok(Point(4, 0)));
+            assert_eq!(cube_spawn_over_stacks(false, &[4], height), overflow);
+            assert_eq!(cube_spawn_over_stacks(true, &[4], height), Ok(Point(5, 0)));
+            assert_eq!(cube_spawn_over_stacks(true, &[5], height), Ok(Point(3, 0)));
+            assert_eq!(cube_spawn_over_stacks(true, &[4, 5], height), overflow);
+            assert_eq!(cube_spawn_over_stacks(true, &[3, 6], height), Ok(Point(4, 0)));
+        }
+    }
 
-const PYRAMID_90: &'static [bool] = &[
-    true,  false,
-    true,  true,
-    true,  false,
-    ];
 
-const PYRAMID_180: &'static [bool] = &[
-    true,  true,  true,
-    false, true,  false,
-    ];
+    #[test]
+    fn held_rotation_spawns_the_figure_rotated() {
+        let mut game = TetrisGame::new(seeded_rng(1), GameConfig::default());
+        let (plain, _) = next_spawn(&game);
+        assert_eq!(game.create_new_figure(), Ok(()));
+        assert_eq!(spawned_figure(&game), plain);
 
-const PYRAMID_270: &'static [bool] = &[
-    false, true,
-    true,  true,
-    false, true,
-    ];
+        game.spawn_modifiers.rotate = true;
+        for _ in 0 .. 20 {
+            let (plain, rotated) = next_spawn(&game);
+            assert_eq!(game.create_new_figure(), Ok(()));
+            assert!(game.free_spawn_point(&plain).is_some());
+            assert_eq!(spawned_figure(&game), rotated);
+        }
+    }
 
 
-impl Figure {
-    fn offset_from_top_center(&self) -> PointOffset {
-        match self {
-            &Figure::Cube => PointOffset(-1, 0),
-            &Figure::LineHorizontal => PointOffset(-2, 0),
-            &Figure::LineVertical => PointOffset(0, 0),
+    #[test]
+    fn held_rotation_falls_back_to_the_plain_spawn() {
+        let randomizer = FigureRandomizer::with_weights(&[0, 1, 0, 0, 0, 0, 0]).unwrap();
+        let config = GameConfig { randomizer, .. GameConfig::default() };
+        let mut game = TetrisGame::new(seeded_rng(1), config);
+        let (plain, rotated) = next_spawn(&game);
+        let plain_cells = plain.cells_iter(game.spawn_point(&plain)).collect::<Vec<_>>();
+        let blocker = rotated.cells_iter(game.spawn_point(&rotated))
+            .find(|cell| ! plain_cells.contains(cell))
+            .unwrap();
+        let dim = game.cell_screen.dimensions();
+        let cells = (0 .. dim.0 * dim.1)
+            .map(|i| if i == blocker.1 * dim.0 + blocker.0 { Some(TetrisCellColor::Gray) } else { None })
+            .collect();
+        game.import_board_state(&BoardState { cells, dim }).unwrap();
+
+        game.spawn_modifiers.rotate = true;
+        assert_eq!(game.create_new_figure(), Ok(()));
+        assert_eq!(spawned_figure(&game), plain);
+    }
 
-            &Figure::LeftL0 => PointOffset(-1, 0),
-            &Figure::LeftL90 => PointOffset(-2, 0),
-            &Figure::LeftL180 => PointOffset(-1, 0),
-            &Figure::LeftL270 => PointOffset(-2, 0),
 
-            &Figure::RightL0 => PointOffset(-1, 0),
-            &Figure::RightL90 => PointOffset(-2, 0),
-            &Figure::RightL180 => PointOffset(-1, 0),
-            &Figure::RightL270 => PointOffset(-2, 0),
+    fn tetris_well() -> TetrisGame<rand::XorShiftRng> {
+        let config = GameConfig::default();
+        let Dimensions(columns, lines) = Dimensions(config.columns, config.lines);
+        let mut game = TetrisGame::new(seeded_rng(1), config);
+        let cells = (0 .. columns * lines)
+            .map(|i| if i / columns < lines - 4 || i % columns == 0 {
+                None
+            } else {
+                Some(TetrisCellColor::Gray)
+            })
+            .collect();
+        game.import_board_state(&BoardState { cells, dim: Dimensions(columns, lines) }).unwrap();
+        game
+    }
+
+    #[test]
+    fn lines_cleared_this_piece_counts_the_last_lock() {
+        let mut game = tetris_well();
+        assert!(game.force_figure(FIGURE_KINDS[1].rotations()[1].clone(), 0));
+        game.handle_event(GameInputEvent::Timer).unwrap();
+        assert_eq!(game.lines_cleared_this_piece, 0);
+        assert!(game.apply_placement(0, 0));
+        assert_eq!(game.lines_cleared_this_piece, 4);
+    }
 
-            &Figure::LeftZigzagHorizontal => PointOffset(-1, 0),
-            &Figure::LeftZigzagVertical => PointOffset(-1, 0),
-            &Figure::RightZigzagHorizontal => PointOffset(-1, 0),
-            &Figure::RightZigzagVertical => PointOffset(-1, 0),
 
-            &Figure::Pyramid0 => PointOffset(-1, 0),
-            &Figure::Pyramid90 => PointOffset(-1, 0),
-            &Figure::Pyramid180 => PointOffset(-1, 0),
-            &Figure::Pyramid270 => PointOffset(-1, 0),
+    #[test]
+    fn tetris_flashes_until_the_board_is_reset() {
+        let mut game = tetris_well();
+        let lines = game.config.lines;
+        assert!(game.force_figure(FIGURE_KINDS[1].rotations()[1].clone(), 0));
+        assert!(game.apply_placement(0, 0));
+        {
+            let flash = game.clear_flash.as_ref().expect("a tetris started no clear flash");
+            assert_eq!(flash.size, 4);
+            assert_eq!(flash.lines, [lines - 4, lines - 3, lines - 2, lines - 1]);
+            assert_eq!(flash.color(), (0, 255, 255));
         }
+
+        let state = game.export_board_state();
+        game.import_board_state(&state).unwrap();
+        game.update_clear_flash();
+        assert!(game.clear_flash.is_none());
+        assert!(game.cell_screen.row_color(lines - 1).is_none());
+    }
+
+
+    #[test]
+    fn rotation_during_the_clear_flash_reaches_the_next_figure() {
+        let mut game = tetris_well();
+        assert!(game.force_figure(FIGURE_KINDS[1].rotations()[1].clone(), 0));
+        game.spawn_modifiers.rotate = true;
+        let (_, rotated) = next_spawn(&game);
+        assert!(game.apply_placement(0, 0));
+        assert!(game.clear_flash.is_some());
+        assert_eq!(spawned_figure(&game), rotated);
+
+        game.dispatch_event(GameInputEvent::RotateClockwise, 0);
+        assert!(game.clear_flash.is_some());
+        assert_eq!(spawned_figure(&game), rotated.clone().rotate_clockwise().1);
     }
 
-    fn dimensions(&self) -> Dimensions {
-        match self {
-            &Figure::Cube => Dimensions(2, 2),
-            &Figure::LineHorizontal => Dimensions(4, 1),
-            &Figure::LineVertical => Dimensions(1, 4),
 
-            &Figure::LeftL0 => Dimensions(2, 3),
-            &Figure::LeftL90 => Dimensions(3, 2),
-            &Figure::LeftL180 => Dimensions(2, 3),
-            &Figure::LeftL270 => Dimensions(3, 2),
-            &Figure::RightL0 => Dimensions(2, 3),
-            &Figure::RightL90 => Dimensions(3, 2),
-            &Figure::RightL180 => Dimensions(2, 3),
-            &Figure::RightL270 => Dimensions(3, 2),
+    #[test]
+    fn render_to_buffer_keeps_to_the_stride() {
+        let mut screen = TetrisCellScreen::new(2, 1, 10);
+        screen.set_cell(Point(1, 0), Some(TetrisCellColor::Red));
+        let Dimensions(width, height) = screen.window_size();
+        let stride = width * 4 + 8;
+        let mut buf = vec![0xaa; stride * height];
+        let colors = ColorScheme::default();
+        screen.render_to_buffer(&mut buf, stride, &colors);
+
+        let pixel = |x: usize, y: usize| &buf[y * stride + x * 4 .. y * stride + x * 4 + 4];
+        let Dimensions(x_off, y_off) = screen.global_offset();
+        let Dimensions(spacing_x, spacing_y) = screen.cell_spacing();
+        let (r, g, b) = TetrisCellColor::Red.to_rgb(&colors);
+        assert_eq!(pixel(x_off + 10 + spacing_x, y_off + spacing_y), [r, g, b, 255]);
+        assert_eq!(pixel(x_off + spacing_x, y_off + spacing_y), [0, 0, 0, 255]);
+        assert_eq!(pixel(x_off, y_off), [127, 127, 127, 255]);
+        assert!(buf[width * 4 .. stride].iter().all(|&byte| byte == 0xaa));
+    }
 
-            &Figure::LeftZigzagHorizontal => Dimensions(3, 2),
-            &Figure::LeftZigzagVertical => Dimensions(2, 3),
-            &Figure::RightZigzagHorizontal => Dimensions(3, 2),
-            &Figure::RightZigzagVertical => Dimensions(2, 3),
 
-            &Figure::Pyramid0 => Dimensions(3, 2),
-            &Figure::Pyramid90 => Dimensions(2, 3),
-            &Figure::Pyramid180 => Dimensions(3, 2),
-            &Figure::Pyramid270 => Dimensions(2, 3),
+    #[test]
+    fn tiny_cells_keep_a_pixel_inside_the_spacing() {
+        let expected = [(0, None), (1, Some(0)), (2, Some(0)), (3, Some(1)), (4, Some(1)),
+                        (40, Some(2)), (100, Some(5))];
+        let colors = ColorScheme::default();
+        for &(cell_size, spacing) in &expected {
+            let mut screen = TetrisCellScreen::new(3, 2, cell_size);
+            screen.set_cell(Point(1, 1), Some(TetrisCellColor::Red));
+            assert_eq!(screen.drawn_cell_spacing(), spacing.map(|s| Dimensions(s, s)),
+                       "{} pixel cells", cell_size);
+
+            let Dimensions(width, height) = screen.window_size();
+            let mut buf = vec![0; width * height * 4];
+            screen.render_to_buffer(&mut buf, width * 4, &colors);
+            if cell_size > 0 {
+                let Dimensions(x_off, y_off) = screen.global_offset();
+                let (x, y) = (x_off + cell_size + cell_size / 2, y_off + cell_size + cell_size / 2);
+                let (r, g, b) = TetrisCellColor::Red.to_rgb(&colors);
+                let offset = (y * width + x) * 4;
+                assert_eq!(buf[offset .. offset + 4], [r, g, b, 255], "{} pixel cells", cell_size);
+            }
         }
     }
 
-    fn color(&self) -> TetrisCellColor {
-        match self {
-            &Figure::Cube => TetrisCellColor::Red,
-            &Figure::LineHorizontal => TetrisCellColor::Orange,
-            &Figure::LineVertical => TetrisCellColor::Orange,
 
-            &Figure::LeftL0 => TetrisCellColor::Yellow,
-            &Figure::LeftL90 => TetrisCellColor::Yellow,
-            &Figure::LeftL180 => TetrisCellColor::Yellow,
-            &Figure::LeftL270 => TetrisCellColor::Yellow,
-            &Figure::RightL0 => TetrisCellColor::Green,
-            &Figure::RightL90 => TetrisCellColor::Green,
-            &Figure::RightL180 => TetrisCellColor::Green,
-            &Figure::RightL270 => TetrisCellColor::Green,
+    #[test]
+    fn rasterize_paints_the_border_and_the_cells() {
+        let mut screen = TetrisCellScreen::new(2, 1, 10);
+        screen.set_cell(Point(1, 0), Some(TetrisCellColor::Red));
+        let frame = raster::rasterize(&screen, 4);
+        assert_eq!((frame.width, frame.height), (2 * 4 + 2, 4 + 2));
+
+        let rgba = frame.to_rgba8888(&raster::palette(&screen));
+        let pixel = |x: usize, y: usize| rgba[y * frame.width + x];
+        let (r, g, b) = screen.rgb(TetrisCellColor::Red);
+        let red = (r as u32) << 24 | (g as u32) << 16 | (b as u32) << 8 | 0xff;
+        assert_eq!(pixel(0, 0), 0x7f7f7fff);
+        assert_eq!(pixel(frame.width - 1, frame.height - 1), 0x7f7f7fff);
+        assert_eq!(pixel(2, 2), 0, "the empty cell is painted");
+        assert_eq!(pixel(1 + 4, 1), 0, "the spacing of the red cell is painted");
+        assert_eq!(pixel(1 + 4 + 1, 1 + 1), red);
+        assert_eq!(pixel(1 + 4 + 2, 1 + 2), red);
+        assert_eq!(pixel(1 + 4 + 3, 1 + 3), 0);
+    }
 
-            &Figure::LeftZigzagHorizontal => TetrisCellColor::Blue,
-            &Figure::LeftZigzagVertical => TetrisCellColor::Blue,
-            &Figure::RightZigzagHorizontal => TetrisCellColor::DeepBlue,
-            &Figure::RightZigzagVertical => TetrisCellColor::DeepBlue,
 
-            &Figure::Pyramid0 => TetrisCellColor::Purple,
-            &Figure::Pyramid90 => TetrisCellColor::Purple,
-            &Figure::Pyramid180 => TetrisCellColor::Purple,
-            &Figure::Pyramid270 => TetrisCellColor::Purple,
+    #[test]
+    fn color_indices_round_trip() {
+        let colors = [
+            TetrisCellColor::Red, TetrisCellColor::Orange, TetrisCellColor::Yellow,
+            TetrisCellColor::Green, TetrisCellColor::Blue, TetrisCellColor::DeepBlue,
+            TetrisCellColor::Purple, TetrisCellColor::Gray,
+        ];
+        for (index, &color) in colors.iter().enumerate() {
+            assert_eq!(color.to_index() as usize, index);
+            assert_eq!(TetrisCellColor::from_index(color.to_index()), Some(color));
         }
+        assert_eq!(TetrisCellColor::from_index(colors.len() as u8), None);
+        assert_eq!(TetrisCellColor::from_index(u8::MAX), None);
     }
 
-    fn bitmap(&self) -> &'static [bool] {
-        match self {
-            &Figure::Cube => CUBE_CELLS,
-            &Figure::LineHorizontal => LINE_HORIZONTAL,
-            &Figure::LineVertical => LINE_VERTICAL,
 
-            &Figure::LeftL0 => LEFT_L_0,
-            &Figure::LeftL90 => LEFT_L_90,
-            &Figure::LeftL180 => LEFT_L_180,
-            &Figure::LeftL270 => LEFT_L_270,
-            &Figure::RightL0 => RIGHT_L_0,
-            &Figure::RightL90 => RIGHT_L_90,
-            &Figure::RightL180 => RIGHT_L_180,
-            &Figure::RightL270 => RIGHT_L_270,
+    #[test]
+    fn ghost_is_drawn_darker_than_its_figure() {
+        let colors = ColorScheme::default();
+        for color in (0 ..).map_while(TetrisCellColor::from_index) {
+            let ((r, g, b), dim) = (color.to_rgb(&colors), color.to_dim_rgb(GHOST_ALPHA, &colors));
+            assert!(dim.0 <= r && dim.1 <= g && dim.2 <= b && dim != (r, g, b),
+                    "dim {:?} is {:?}, not darker than {:?}", color, dim, (r, g, b));
+        }
+
+        let mut game = TetrisGame::new(seeded_rng(1), GameConfig::default());
+        let drop_distance = game.drop_distance();
+        game.cell_screen.set_drop_distance(drop_distance);
+        let screen = &game.cell_screen;
+        let Dimensions(columns, _) = screen.dimensions();
+        let (i, color) = screen.ghost_cells().iter().enumerate()
+            .find_map(|(i, cell)| cell.map(|color| (i, color)))
+            .expect("a new game has no ghost");
+        let Dimensions(width, height) = screen.window_size();
+        let mut buf = vec![0; width * height * 4];
+        screen.render_to_buffer(&mut buf, width * 4, &colors);
+        let Dimensions(x_off, y_off) = screen.global_offset();
+        let Dimensions(cell_width, cell_height) = screen.cell_size();
+        let Dimensions(spacing_x, spacing_y) = screen.cell_spacing();
+        let offset = (y_off + i / columns * cell_height + spacing_y) * width * 4
+            + (x_off + i % columns * cell_width + spacing_x) * 4;
+        let (r, g, b) = color.to_dim_rgb(GHOST_ALPHA, &colors);
+        assert_eq!(buf[offset .. offset + 4], [r, g, b, 255]);
+    }
 
-            &Figure::LeftZigzagHorizontal => LEFT_ZIGZAG_HORIZONTAL,
-            &Figure::LeftZigzagVertical => LEFT_ZIGZAG_VERTICAL,
-            &Figure::RightZigzagHorizontal => RIGHT_ZIGZAG_HORIZONTAL,
-            &Figure::RightZigzagVertical => RIGHT_ZIGZAG_VERTICAL,
 
-            &Figure::Pyramid0 => PYRAMID_0,
-            &Figure::Pyramid90 => PYRAMID_90,
-            &Figure::Pyramid180 => PYRAMID_180,
-            &Figure::Pyramid270 => PYRAMID_270,
+    #[test]
+    fn garbage_styles_place_the_holes() {
+        let (rows, columns) = (6, 10);
+        let mut rng = seeded_rng(7);
+        let independent: Vec<_> = (0 .. rows).map(|_| rng.gen_range(0, columns)).collect();
+        for &style in GARBAGE_STYLES.iter() {
+            let expected = match style {
+                GarbageStyle::Messy => independent.clone(),
+                GarbageStyle::Clean => vec![independent[0]; rows],
+                GarbageStyle::Shifting =>
+                    (0 .. rows).map(|row| (independent[0] + row) % columns).collect(),
+            };
+            assert_eq!(garbage_gaps(style, rows, columns, &mut seeded_rng(7)), expected,
+                       "{} garbage holes", style.name());
+
+            let game = TetrisGame::new(seeded_rng(7), GameConfig {
+                starting_garbage: rows,
+                garbage_style: style,
+                .. GameConfig::default()
+            });
+            let holes: Vec<Vec<_>> = game.cell_screen.iter_rows()
+                .skip(game.config.lines - rows)
+                .map(|row| (0 .. columns).filter(|&x| row[x].is_none()).collect())
+                .collect();
+            assert!(holes.iter().all(|row_holes| row_holes.len() == 1), "holes {:?}", holes);
+            let consistent = match style {
+                GarbageStyle::Messy => true,
+                GarbageStyle::Clean => holes.windows(2).all(|pair| pair[0] == pair[1]),
+                GarbageStyle::Shifting =>
+                    holes.windows(2).all(|pair| pair[1][0] == (pair[0][0] + 1) % columns),
+            };
+            assert!(consistent, "{} garbage left holes {:?} on a new board", style.name(), holes);
+            assert_eq!(game.garbage_remaining, rows);
         }
     }
 
-    fn rotate_clockwise(self) -> (PointOffset, Self) {
-        match self {
-            Figure::Cube => (PointOffset(0, 0), Figure::Cube),
-            Figure::LineHorizontal => (PointOffset(2, -2), Figure::LineVertical),
-            Figure::LineVertical => (PointOffset(-2, 2), Figure::LineHorizontal),
 
-            Figure::LeftL0 => (PointOffset(0, 0), Figure::LeftL90),
-            Figure::LeftL90 => (PointOffset(0, 0), Figure::LeftL180),
-            Figure::LeftL180 => (PointOffset(0, 0), Figure::LeftL270),
-            Figure::LeftL270 => (PointOffset(0, 0), Figure::LeftL0),
+    #[test]
+    fn starting_garbage_leaves_holes_under_the_stack() {
+        for seed in 1 .. 6 {
+            for &style in &[GarbageStyle::Messy, GarbageStyle::Shifting, GarbageStyle::Clean] {
+                let game = TetrisGame::new(seeded_rng(seed), GameConfig {
+                    starting_garbage: 5,
+                    garbage_style: style,
+                    .. GameConfig::default()
+                });
+                let holes = game.cell_screen.hole_count();
+                if style == GarbageStyle::Clean {
+                    assert_eq!(holes, 0, "clean garbage covered its own well");
+                } else {
+                    assert!(holes > 0, "{} garbage, seed {}: no holes", style.name(), seed);
+                }
+            }
+        }
+    }
 
-            Figure::RightL0 => (PointOffset(0, 0), Figure::RightL90),
-            Figure::RightL90 => (PointOffset(0, 0), Figure::RightL180),
-            Figure::RightL180 => (PointOffset(0, 0), Figure::RightL270),
-            Figure::RightL270 => (PointOffset(0, 0), Figure::RightL0),
 
-            Figure::LeftZigzagHorizontal => (PointOffset(0, 0), Figure::LeftZigzagVertical),
-            Figure::LeftZigzagVertical => (PointOffset(0, 0), Figure::LeftZigzagHorizontal),
-            Figure::RightZigzagHorizontal => (PointOffset(0, 0), Figure::RightZigzagVertical),
-            Figure::RightZigzagVertical => (PointOffset(0, 0), Figure::RightZigzagHorizontal),
+    #[test]
+    fn set_level_speeds_up_gravity_and_back() {
+        let config = GameConfig::default();
+        let mut game = TetrisGame::new(seeded_rng(1), config.clone());
+        game.set_level(10);
+        let gravity = (0 .. 10).fold(config.gravity_rows_per_second, |rate, _| faster_gravity(rate));
+        assert_eq!(game.level(), 10);
+        assert_eq!(game.lines_to_next_level(), 10);
+        assert_eq!(game.gravity_rows_per_second, gravity);
 
-            Figure::Pyramid0 => (PointOffset(0, 0), Figure::Pyramid90),
-            Figure::Pyramid90 => (PointOffset(0, 0), Figure::Pyramid180),
-            Figure::Pyramid180 => (PointOffset(0, 0), Figure::Pyramid270),
-            Figure::Pyramid270 => (PointOffset(0, 0), Figure::Pyramid0),
-        }
+        game.set_level(0);
+        assert_eq!(game.lines_cleared, 0);
+        assert_eq!(game.gravity_rows_per_second, config.gravity_rows_per_second);
     }
-}
 
 
-impl rand::Rand for Figure {
-    fn rand<R: rand::Rng>(rng: &mut R) -> Self {
-        match rng.next_u32() % 28 {
-            0...3 => Figure::Cube,
-            4...5 => Figure::LineHorizontal,
-            6...7 => Figure::LineVertical,
+    #[test]
+    fn tenth_line_moves_to_the_next_level() {
+        let config = GameConfig { columns: 4, lines: 8, .. GameConfig::default() };
+        let mut game = TetrisGame::new(seeded_rng(1), config);
+        let cells = (0 .. 32)
+            .map(|i| if i >= 28 && i % 4 != 0 { Some(TetrisCellColor::Gray) } else { None })
+            .collect();
+        game.import_board_state(&BoardState { cells, dim: Dimensions(4, 8) }).unwrap();
+        game.lines_cleared = 9;
+        assert_eq!((game.level(), game.lines_to_next_level()), (0, 1));
+
+        drop_line_into_the_left_column(&mut game).unwrap();
+        assert_eq!(game.lines_cleared, 10);
+        assert_eq!((game.level(), game.lines_to_next_level()), (1, 10));
+    }
 
-            8 => Figure::LeftL0,
-            9 => Figure::LeftL90,
-            10 => Figure::LeftL180,
-            11 => Figure::LeftL270,
-            12 => Figure::RightL0,
-            13 => Figure::RightL90,
-            14 => Figure::RightL180,
-            15 => Figure::RightL270,
 
-            16...17 => Figure::LeftZigzagHorizontal,
-            18...19 => Figure::LeftZigzagVertical,
-            20...21 => Figure::RightZigzagHorizontal,
-            22...23 => Figure::RightZigzagVertical,
+    fn half_filled_game() -> TetrisGame<rand::XorShiftRng> {
+        let config = GameConfig { columns: 4, lines: 8, .. GameConfig::default() };
+        let mut game = TetrisGame::new(seeded_rng(1), config);
+        // Three cells in each of the bottom four rows and two in each of the two rows above.
+        let cells = (0 .. 32)
+            .map(|i| match (i / 4, i % 4) {
+                (4 ..= 7, 0 ..= 2) | (2 ..= 3, 0 ..= 1) => Some(TetrisCellColor::Gray),
+                _ => None,
+            })
+            .collect();
+        game.import_board_state(&BoardState { cells, dim: Dimensions(4, 8) }).unwrap();
+        assert!(game.force_figure(FIGURE_KINDS[0].rotations()[0].clone(), 2));
+        game
+    }
 
-            24 => Figure::Pyramid0,
-            25 => Figure::Pyramid90,
-            26 => Figure::Pyramid180,
-            27 => Figure::Pyramid270,
 
-            _ => panic!("lolwut"),
-        }
+    #[test]
+    fn half_filled_board_has_a_fill_ratio_of_one_half() {
+        let mut game = half_filled_game();
+        assert_eq!(game.board_fill_ratio(), 0.5);
+
+        game.cell_screen.clear_figure();
+        assert_eq!(game.board_fill_ratio(), 0.5);
     }
-}
 
 
-fn main() {
-    let mut columns = 10;
-    let mut lines = 16;
-    let mut cell_size = 40;
-    {
-        let mut parser = argparse::ArgumentParser::new();
-        parser.refer(&mut cell_size)
-            .add_option(&["--cell-size"], argparse::Store,
-                        "One cell size in pixels");
-        parser.refer(&mut columns)
-            .add_option(&["--columns"], argparse::Store, "");
-        parser.refer(&mut lines)
-            .add_option(&["--lines"], argparse::Store, "");
-        parser.parse_args_or_exit();
+    #[test]
+    fn invisible_board_keeps_its_fill_ratio() {
+        let mut game = half_filled_game();
+        game.cell_screen.set_invisible(true);
+        let shown = game.cell_screen.composed_cells().iter().filter(|cell| cell.is_some()).count();
+        assert_eq!(shown, 4, "only the falling cube is shown");
+        assert_eq!(game.board_fill_ratio(), 0.5);
+
+        game.cell_screen.set_invisible(false);
+        assert_eq!(game.board_fill_ratio(), 0.5);
     }
 
-    assert!(columns >= 5);
-    assert!(lines >= 4);
-    assert!(cell_size >= 5);
 
-    let sdl_context = sdl2::init().unwrap();
+    #[test]
+    fn mirrored_board_is_composed_right_to_left() {
+        let mut game = half_filled_game();
+        let logical = game.cell_screen.composed_cells();
+        game.cell_screen._mirrored = true;
+        game.cell_screen._dirty.set(true);
+        let mirrored = game.cell_screen.composed_cells();
+
+        let Dimensions(columns, _) = game.cell_screen.dimensions();
+        let flipped = logical.chunks(columns)
+            .flat_map(|row| row.iter().rev().cloned())
+            .collect::<Vec<_>>();
+        assert!(mirrored != logical, "the test board is symmetric");
+        assert_eq!(mirrored, flipped);
+        game.cell_screen.with_composed_cells(&mut |cells| assert_eq!(cells, &flipped[..]));
+    }
 
-    let mut game = TetrisGame::new(rand::thread_rng(), columns, lines, cell_size);
-    let window_size = game.window_size();
 
-    let window = sdl_context.video().unwrap().window("Tetris", window_size.0, window_size.1).build().unwrap();
+    #[test]
+    fn versus_panels_fit_the_window_side_by_side() {
+        let boards = [TetrisCellScreen::new(10, 20, 30), TetrisCellScreen::new(10, 20, 30)];
+        for &window_size in &[(640, 480), (1000, 700), (320, 240), (2000, 400)] {
+            let layout = versus_layout(&boards, window_size);
+            assert_eq!(layout.panels.len(), 2);
+            let rects = layout.panels.iter()
+                .map(|&(Point(x, y), ref panel)| {
+                    let Dimensions(width, height) = panel.window_size();
+                    assert!(panel.cell_size().0 > 0, "{:?} leaves no room for cells", window_size);
+                    (x, y, x + width, y + height)
+                })
+                .collect::<Vec<_>>();
+            for &(_, _, right, bottom) in &rects {
+                assert!(right <= window_size.0 as usize && bottom <= window_size.1 as usize,
+                        "a panel is out of the {:?} window: {:?}", window_size, rects);
+            }
+            let ((_, top, left_right, left_bottom), (right_left, _, _, _)) = (rects[0], rects[1]);
+            assert!(left_right <= right_left, "panels overlap in {:?}: {:?}", window_size, rects);
+            assert_eq!((top, left_bottom), (rects[1].1, rects[1].3));
+        }
+    }
 
-    let mut event_pump = sdl_context.event_pump().unwrap();
-    let mut renderer = window.renderer().build().unwrap();
 
-    game.run(&mut event_pump, &mut renderer);
+    #[test]
+    fn endless_mode_tops_out_with_a_board_overflow() {
+        let config = GameConfig { mode: GameMode::Endless, .. GameConfig::default() };
+        let Dimensions(columns, lines) = Dimensions(config.columns, config.lines);
+        let mut game = TetrisGame::new(seeded_rng(1), config);
+        let cells = (0 .. columns * lines)
+            .map(|i| if i % columns == 0 { None } else { Some(TetrisCellColor::Gray) })
+            .collect();
+        game.import_board_state(&BoardState { cells, dim: Dimensions(columns, lines) }).unwrap();
+        assert_eq!(game.create_new_figure(), Err(GameError::BoardOverflow { at_row: 0 }));
+    }
 }
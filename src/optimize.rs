@@ -0,0 +1,127 @@
+use std::cmp::Reverse;
+use std::fs::File;
+use std::io::{self, Write};
+use std::thread;
+
+use rand::XorShiftRng;
+use rand::distributions::{IndependentSample, Normal};
+use time;
+
+use ai::{self, EvalWeights};
+use super::{GameConfig, TetrisGame, seeded_rng};
+
+
+const RESULT_PATH: &str = "optimizer_result.json";
+const MAX_FIGURES_PER_GAME: usize = 1_000;
+const GAMES_PER_GENOME: u32 = 2;
+const MUTATION_SIGMA: f64 = 0.1;
+
+
+fn play(weights: EvalWeights, seed: u32, config: &GameConfig) -> usize {
+    let mut lines = 0;
+    for game_no in 0 .. GAMES_PER_GENOME {
+        let mut game = TetrisGame::new(seeded_rng(seed.wrapping_add(game_no)), config.clone());
+        let mut player = ai::Player::with_weights(&game.cell_screen, weights);
+        for _ in 0 .. MAX_FIGURES_PER_GAME {
+            let running = match player.best_placement(&game.cell_screen) {
+                Some((col, rotation)) => game.apply_placement(col, rotation),
+                None => false,
+            };
+            if ! running {
+                break;
+            }
+        }
+        lines += game.lines_cleared;
+    }
+    lines
+}
+
+
+pub struct GeneticOptimizer {
+    population: Vec<EvalWeights>,
+    generation: u32,
+    seed: u32,
+    config: GameConfig,
+    rng: XorShiftRng,
+    noise: Normal,
+}
+
+
+impl GeneticOptimizer {
+    pub fn new(population_size: usize, seed: u32, config: &GameConfig) -> Self {
+        let mut rng = seeded_rng(seed);
+        let noise = Normal::new(0.0, MUTATION_SIGMA);
+        let population = (0 .. population_size).map(|i| {
+            if i == 0 {
+                EvalWeights::default()
+            } else {
+                EvalWeights::default().map(|weight| weight + noise.ind_sample(&mut rng))
+            }
+        }).collect();
+        GeneticOptimizer {
+            population,
+            generation: 0,
+            seed,
+            config: config.clone(),
+            rng,
+            noise,
+        }
+    }
+
+    fn score_population(&self) -> Vec<(usize, EvalWeights)> {
+        let seed = self.seed.wrapping_add(self.generation * GAMES_PER_GENOME);
+        let players: Vec<_> = self.population.iter().map(|&weights| {
+            let config = self.config.clone();
+            thread::spawn(move || (play(weights, seed, &config), weights))
+        }).collect();
+        let mut scored: Vec<_> = players.into_iter()
+            .map(|player| player.join().expect("optimizer game panicked"))
+            .collect();
+        scored.sort_by_key(|&(lines, _)| Reverse(lines));
+        scored
+    }
+
+    pub fn step(&mut self) -> (usize, EvalWeights) {
+        let scored = self.score_population();
+        let survivors: Vec<_> = scored.iter()
+            .take(self.population.len() - self.population.len() / 2)
+            .map(|&(_, weights)| weights)
+            .collect();
+        let (noise, rng) = (&self.noise, &mut self.rng);
+        let children: Vec<_> = survivors.iter().cycle()
+            .take(self.population.len() - survivors.len())
+            .map(|weights| weights.map(|weight| weight + noise.ind_sample(rng)))
+            .collect();
+        self.population = survivors.into_iter().chain(children).collect();
+        self.generation += 1;
+        scored[0]
+    }
+}
+
+
+fn save(generation: u32, lines: usize, weights: &EvalWeights) -> io::Result<()> {
+    let mut file = File::create(RESULT_PATH)?;
+    writeln!(file, "{{\"generation\": {}, \"lines_cleared\": {}, \"weights\": {}}}",
+             generation, lines, weights.to_json())
+}
+
+
+pub fn run(seed: u32, generations: u32, population_size: usize, config: &GameConfig) {
+    let mut optimizer = GeneticOptimizer::new(population_size, seed, config);
+    let mut best = None;
+    for generation in 1 .. generations + 1 {
+        let started_ns = time::precise_time_ns();
+        let (lines, weights) = optimizer.step();
+        println!("generation {}: best {} lines in {:.2} s, weights {}", generation, lines,
+                 (time::precise_time_ns() - started_ns) as f64 / 1e9, weights.to_json());
+        match best {
+            Some(best_lines) if best_lines >= lines => {},
+            _ => {
+                best = Some(lines);
+                if let Err(e) = save(generation, lines, &weights) {
+                    println!("Can not save {}: {}", RESULT_PATH, e);
+                }
+            },
+        }
+    }
+}
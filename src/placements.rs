@@ -0,0 +1,115 @@
+use std::fs::{File, OpenOptions};
+use std::io::{self, BufWriter, Write};
+use std::path::{Path, PathBuf};
+
+use time;
+
+use super::{FIGURE_KINDS, Figure, Point};
+
+
+const HEADER: &str =
+    "time,shape,rotation,column,row,lines_cleared,aggregate_height,holes";
+
+
+pub struct Placement<'a> {
+    pub figure: &'a Figure,
+    pub point: Point,
+    pub lines_cleared: usize,
+    pub aggregate_height: u32,
+    pub holes: u32,
+}
+
+
+fn csv_row(time: &str, placement: &Placement) -> String {
+    let (kind, rotation) = FIGURE_KINDS.iter()
+        .filter_map(|kind| {
+            kind.rotations().iter().position(|f| f == placement.figure).map(|r| (kind, r))
+        })
+        .next().unwrap();
+    format!("{},{:?},{},{},{},{},{},{}",
+            time, kind, rotation, placement.point.0, placement.point.1,
+            placement.lines_cleared, placement.aggregate_height, placement.holes)
+}
+
+
+pub struct PlacementLog {
+    path: PathBuf,
+    out: BufWriter<File>,
+}
+
+
+impl PlacementLog {
+    pub fn open(path: PathBuf) -> io::Result<Self> {
+        let file = OpenOptions::new().append(true).create(true).open(&path)?;
+        let is_new = file.metadata()?.len() == 0;
+        let mut out = BufWriter::new(file);
+        if is_new {
+            writeln!(out, "{}", HEADER)?;
+        }
+        Ok(PlacementLog {
+            path,
+            out,
+        })
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    pub fn record(&mut self, placement: &Placement) -> io::Result<()> {
+        let now = time::now();
+        writeln!(self.out, "{}", csv_row(&now.rfc3339().to_string(), placement))
+    }
+
+    pub fn flush(&mut self) -> io::Result<()> {
+        self.out.flush()
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use std::env;
+    use std::fs;
+
+    use super::*;
+
+    #[test]
+    fn placement_rows_match_the_header() {
+        let figure = FIGURE_KINDS[2].rotations()[3].clone();
+        let placement = Placement {
+            figure: &figure,
+            point: Point(4, 17),
+            lines_cleared: 2,
+            aggregate_height: 31,
+            holes: 5,
+        };
+        let row = csv_row("2026-01-02T03:04:05Z", &placement);
+        assert_eq!(row, "2026-01-02T03:04:05Z,LeftL,3,4,17,2,31,5");
+        assert_eq!(row.split(',').count(), HEADER.split(',').count());
+    }
+
+    #[test]
+    fn reopened_log_keeps_a_single_header() {
+        let path = env::temp_dir().join(format!("tetris-placements-{}.csv", std::process::id()));
+        let figure = FIGURE_KINDS[0].rotations()[0].clone();
+        let placement = Placement {
+            figure: &figure,
+            point: Point(0, 18),
+            lines_cleared: 0,
+            aggregate_height: 2,
+            holes: 0,
+        };
+        for _ in 0 .. 2 {
+            let mut log = PlacementLog::open(path.clone()).unwrap();
+            log.record(&placement).unwrap();
+            log.flush().unwrap();
+        }
+        let written = fs::read_to_string(&path).unwrap();
+        let _ = fs::remove_file(&path);
+        let lines = written.lines().collect::<Vec<_>>();
+        assert_eq!(lines.len(), 3);
+        assert_eq!(lines[0], HEADER);
+        assert!(lines[1 ..].iter().all(|line| line.ends_with(",Cube,0,0,18,0,2,0")), "{}", written);
+    }
+}
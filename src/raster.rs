@@ -0,0 +1,105 @@
+use super::{CellScreen, Dimensions, Figure, TetrisCellColor};
+
+
+const BACKGROUND_INDEX: u8 = 0;
+const BORDER_INDEX: u8 = 1;
+const FIRST_CELL_INDEX: u8 = 2;
+
+pub const PALETTE_SIZE: usize = 16;
+
+
+pub struct Frame {
+    pub width: usize,
+    pub height: usize,
+    pub pixels: Vec<u8>,
+}
+
+
+impl Frame {
+    pub fn to_rgba8888(&self, palette: &[(u8, u8, u8); PALETTE_SIZE]) -> Vec<u32> {
+        self.pixels.iter().map(|&index| {
+            if index == BACKGROUND_INDEX {
+                return 0;
+            }
+            let (r, g, b) = palette[index as usize];
+            (r as u32) << 24 | (g as u32) << 16 | (b as u32) << 8 | 0xff
+        }).collect()
+    }
+}
+
+
+fn palette_index(color: TetrisCellColor) -> u8 {
+    FIRST_CELL_INDEX + color.to_index()
+}
+
+
+pub fn palette<C: CellScreen>(screen: &C) -> [(u8, u8, u8); PALETTE_SIZE] {
+    let mut palette = [(0, 0, 0); PALETTE_SIZE];
+    palette[BORDER_INDEX as usize] = (127, 127, 127);
+    let mut index = 0;
+    while let Some(color) = TetrisCellColor::from_index(index) {
+        palette[palette_index(color) as usize] = screen.rgb(color);
+        index += 1;
+    }
+    palette
+}
+
+
+fn paint_cells<I>(frame: &mut Frame, origin: (usize, usize), columns: usize, cell_pixels: usize,
+                  cells: I)
+    where I: IntoIterator<Item = Option<TetrisCellColor>>
+{
+    let spacing = if cell_pixels > 2 { 1 } else { 0 };
+    for (i, cell) in cells.into_iter().enumerate() {
+        if let Some(color) = cell {
+            let index = palette_index(color);
+            let x0 = origin.0 + (i % columns) * cell_pixels;
+            let y0 = origin.1 + (i / columns) * cell_pixels;
+            for y in y0 + spacing .. y0 + cell_pixels - spacing {
+                for x in x0 + spacing .. x0 + cell_pixels - spacing {
+                    frame.pixels[y * frame.width + x] = index;
+                }
+            }
+        }
+    }
+}
+
+
+pub fn rasterize<C: CellScreen>(screen: &C, cell_pixels: usize) -> Frame {
+    let Dimensions(columns, lines) = screen.dimensions();
+    let width = columns * cell_pixels + 2;
+    let height = lines * cell_pixels + 2;
+
+    let mut pixels = vec![BACKGROUND_INDEX; width * height];
+    for x in 0 .. width {
+        pixels[x] = BORDER_INDEX;
+        pixels[(height - 1) * width + x] = BORDER_INDEX;
+    }
+    for y in 0 .. height {
+        pixels[y * width] = BORDER_INDEX;
+        pixels[y * width + width - 1] = BORDER_INDEX;
+    }
+
+    let mut frame = Frame {
+        width,
+        height,
+        pixels,
+    };
+    paint_cells(&mut frame, (1, 1), columns, cell_pixels, screen.composed_cells());
+    frame
+}
+
+
+pub fn rasterize_figure(figure: &Figure, color: TetrisCellColor, size: usize) -> Frame {
+    let (columns, lines) = figure.bounding_box();
+    let cell_pixels = size / columns.max(lines);
+    let origin = ((size - columns * cell_pixels) / 2, (size - lines * cell_pixels) / 2);
+    let mut frame = Frame {
+        width: size,
+        height: size,
+        pixels: vec![BACKGROUND_INDEX; size * size],
+    };
+    let cells = figure.bitmap().iter().map(|&filled| if filled { Some(color) } else { None });
+    paint_cells(&mut frame, origin, columns, cell_pixels, cells);
+    frame
+}
@@ -0,0 +1,374 @@
+use std::fs::File;
+use std::io::{self, BufRead, BufReader, BufWriter, Write};
+use std::path::{Path, PathBuf};
+
+use rand::XorShiftRng;
+#[cfg(feature = "sdl")]
+use sdl2::pixels::Color;
+#[cfg(feature = "sdl")]
+use sdl2::render::Renderer;
+
+#[cfg(feature = "sdl")]
+use draw::TryDraw;
+use super::{CellScreen, Dimensions, FigureRandomizer, GARBAGE_STYLES, GameConfig, GameInputEvent,
+            GarbageStyle, SpawnModifiers, TetrisCellScreen, TetrisGame, seeded_rng};
+
+
+const REPLAY_HEADER: &str = "tetris-replay 10";
+
+
+#[derive(Clone)]
+pub struct Replay {
+    pub seed: u32,
+    pub randomizer: FigureRandomizer,
+    pub columns: usize,
+    pub lines: usize,
+    pub starting_garbage: usize,
+    pub garbage_style: GarbageStyle,
+    pub starting_level: usize,
+    pub spawn_grace: bool,
+    pub undo_history: usize,
+    pub allow_hold: bool,
+    pub hold_resets_on_lock: bool,
+    pub events: Vec<(u64, GameInputEvent, SpawnModifiers)>,
+}
+
+
+pub fn event_name(event: GameInputEvent) -> &'static str {
+    match event {
+        GameInputEvent::RotateClockwise => "rotate",
+        GameInputEvent::Rotate180 => "rotate180",
+        GameInputEvent::MoveLeft => "left",
+        GameInputEvent::MoveRight => "right",
+        GameInputEvent::SoftDrop => "softdrop",
+        GameInputEvent::Timer => "timer",
+        GameInputEvent::Undo => "undo",
+        GameInputEvent::Hold => "hold",
+    }
+}
+
+
+pub fn event_from_name(name: &str) -> Option<GameInputEvent> {
+    match name {
+        "rotate" => Some(GameInputEvent::RotateClockwise),
+        "rotate180" => Some(GameInputEvent::Rotate180),
+        "left" => Some(GameInputEvent::MoveLeft),
+        "right" => Some(GameInputEvent::MoveRight),
+        "softdrop" => Some(GameInputEvent::SoftDrop),
+        "timer" => Some(GameInputEvent::Timer),
+        "undo" => Some(GameInputEvent::Undo),
+        "hold" => Some(GameInputEvent::Hold),
+        _ => None,
+    }
+}
+
+
+fn invalid_data(message: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
+
+
+impl Replay {
+    pub fn new(seed: u32, config: &GameConfig) -> Self {
+        Replay {
+            seed,
+            randomizer: config.randomizer,
+            columns: config.columns,
+            lines: config.lines,
+            starting_garbage: config.starting_garbage,
+            garbage_style: config.garbage_style,
+            starting_level: config.starting_level,
+            spawn_grace: config.spawn_grace,
+            undo_history: if config.undo_enabled { config.undo_history } else { 0 },
+            allow_hold: config.allow_hold,
+            hold_resets_on_lock: config.hold_resets_on_lock,
+            events: Vec::new(),
+        }
+    }
+
+    pub fn record(&mut self, time_ms: u64, event: GameInputEvent, modifiers: SpawnModifiers) {
+        self.events.push((time_ms, event, modifiers));
+    }
+
+    pub fn load(path: &Path) -> io::Result<Self> {
+        let mut lines = BufReader::new(File::open(path)?).lines();
+
+        match lines.next() {
+            Some(Ok(ref header)) if header == REPLAY_HEADER => {},
+            Some(Ok(ref header)) if header.starts_with("tetris-replay ") => {
+                return Err(invalid_data(format!(
+                    "{} was recorded by another version of the game ({}, expected {})",
+                    path.display(), header, REPLAY_HEADER)));
+            },
+            Some(Err(e)) => return Err(e),
+            _ => return Err(invalid_data(format!("{} is not a replay file", path.display()))),
+        }
+
+        let params = match lines.next() {
+            Some(line) => line?,
+            None => return Err(invalid_data("replay has no parameters line".to_string())),
+        };
+        let params = params.split_whitespace()
+            .map(|s| s.parse::<usize>())
+            .collect::<Result<Vec<_>, _>>();
+        let mut replay = match params {
+            Ok(ref p) if p.len() == 4 + 7 + 6
+                && p[11] <= 1 && p[13] <= 1 && p[14] <= 1 && p[15] < GARBAGE_STYLES.len() => {
+                let weights = p[4 .. 11].iter()
+                    .map(|&w| if w <= u32::MAX as usize { Some(w as u32) } else { None })
+                    .collect::<Option<Vec<_>>>();
+                let randomizer = match weights.and_then(|w| FigureRandomizer::with_weights(&w)) {
+                    Some(randomizer) => randomizer,
+                    None => return Err(invalid_data("malformed replay figure weights".to_string())),
+                };
+                Replay::new(p[0] as u32, &GameConfig {
+                    columns: p[1],
+                    lines: p[2],
+                    starting_garbage: p[3],
+                    randomizer,
+                    spawn_grace: p[11] == 1,
+                    undo_enabled: p[12] > 0,
+                    undo_history: p[12],
+                    allow_hold: p[13] == 1,
+                    hold_resets_on_lock: p[14] == 1,
+                    garbage_style: GARBAGE_STYLES[p[15]],
+                    starting_level: p[16],
+                    .. GameConfig::default()
+                })
+            },
+            _ => return Err(invalid_data("malformed replay parameters".to_string())),
+        };
+
+        for (line_no, line) in lines.enumerate() {
+            let line = line?;
+            let mut parts = line.split_whitespace();
+            let time_ms = parts.next().and_then(|s| s.parse::<u64>().ok());
+            let event = parts.next().and_then(event_from_name);
+            let modifiers = match parts.next() {
+                None => Some(SpawnModifiers::default()),
+                Some("rotate") => Some(SpawnModifiers { rotate: true }),
+                Some(_) => None,
+            };
+            match (time_ms, event, modifiers) {
+                (Some(time_ms), Some(event), Some(modifiers)) =>
+                    replay.record(time_ms, event, modifiers),
+                _ => return Err(invalid_data(format!(
+                    "malformed replay event at line {}: {:?}", line_no + 3, line))),
+            }
+        }
+
+        Ok(replay)
+    }
+
+    pub fn save(&self, path: &Path) -> io::Result<()> {
+        let mut out = BufWriter::new(File::create(path)?);
+        writeln!(out, "{}", REPLAY_HEADER)?;
+        write!(out, "{} {} {} {}", self.seed, self.columns, self.lines, self.starting_garbage)?;
+        for weight in self.randomizer.weights.iter() {
+            write!(out, " {}", weight)?;
+        }
+        writeln!(out, " {} {} {} {} {} {}", self.spawn_grace as u8, self.undo_history,
+                 self.allow_hold as u8, self.hold_resets_on_lock as u8, self.garbage_style.to_index(),
+                 self.starting_level)?;
+        for &(time_ms, event, modifiers) in self.events.iter() {
+            write!(out, "{} {}", time_ms, event_name(event))?;
+            if modifiers.rotate {
+                write!(out, " rotate")?;
+            }
+            writeln!(out)?;
+        }
+        out.flush()
+    }
+
+    pub fn resimulate(self) -> u64 {
+        let mut ghost = Ghost::new(self);
+        ghost.advance(u64::MAX);
+        ghost.game.state_hash
+    }
+
+    pub fn final_board(self) -> String {
+        let mut ghost = Ghost::new(self);
+        ghost.advance(u64::MAX);
+        ghost.game.cell_screen.to_compact_string()
+    }
+}
+
+
+pub struct Ghost {
+    game: Box<TetrisGame<XorShiftRng>>,
+    events: Vec<(u64, GameInputEvent, SpawnModifiers)>,
+    next_event: usize,
+    running: bool,
+}
+
+
+impl Ghost {
+    fn new(replay: Replay) -> Self {
+        Ghost {
+            game: Box::new(TetrisGame::new(seeded_rng(replay.seed), GameConfig {
+                columns: replay.columns,
+                lines: replay.lines,
+                starting_garbage: replay.starting_garbage,
+                garbage_style: replay.garbage_style,
+                starting_level: replay.starting_level,
+                cell_size: 1,
+                randomizer: replay.randomizer,
+                spawn_grace: replay.spawn_grace,
+                undo_enabled: replay.undo_history > 0,
+                undo_history: replay.undo_history,
+                allow_hold: replay.allow_hold,
+                hold_resets_on_lock: replay.hold_resets_on_lock,
+                .. GameConfig::default()
+            })),
+            events: replay.events,
+            next_event: 0,
+            running: true,
+        }
+    }
+
+    fn advance(&mut self, time_ms: u64) {
+        while self.running && self.next_event < self.events.len() {
+            let (event_time_ms, event, modifiers) = self.events[self.next_event];
+            if event_time_ms > time_ms {
+                break;
+            }
+            self.game.spawn_modifiers = modifiers;
+            self.running = self.game.handle_event(event).is_ok();
+            self.next_event += 1;
+        }
+    }
+
+    fn stack_height(&self) -> usize {
+        self.game.cell_screen.column_heights().into_iter().max().unwrap_or(0) as usize
+    }
+}
+
+
+pub struct GhostRace {
+    path: PathBuf,
+    recording: Replay,
+    ghost: Option<Ghost>,
+}
+
+
+impl GhostRace {
+    pub fn new(path: PathBuf, recording: Replay, best: Option<Replay>) -> Self {
+        GhostRace {
+            path,
+            recording,
+            ghost: best.map(Ghost::new),
+        }
+    }
+
+    pub fn record(&mut self, time_ms: u64, event: GameInputEvent, modifiers: SpawnModifiers) {
+        self.recording.record(time_ms, event, modifiers);
+    }
+
+    pub fn advance(&mut self, time_ms: u64) {
+        if let Some(ref mut ghost) = self.ghost {
+            ghost.advance(time_ms);
+        }
+    }
+
+    pub fn finish(&mut self, lines_cleared: usize, state_hash: u64) {
+        let resimulated_hash = self.recording.clone().resimulate();
+        if resimulated_hash != state_hash {
+            println!("DESYNC: the game ended with state hash {:016x}, but replaying its {} \
+                      recorded events gives {:016x}",
+                     state_hash, self.recording.events.len(), resimulated_hash);
+        }
+
+        let beaten = match self.ghost {
+            Some(ref mut ghost) => {
+                ghost.advance(u64::MAX);
+                lines_cleared > ghost.game.lines_cleared
+            },
+            None => true,
+        };
+
+        if beaten {
+            if let Err(e) = self.recording.save(&self.path) {
+                println!("Can not save replay to {}: {}", self.path.display(), e);
+            }
+        }
+    }
+
+    #[cfg(feature = "sdl")]
+    pub fn render(&self, renderer: &mut Renderer, screen: &TetrisCellScreen, lines_cleared: usize)
+                  -> Result<(), String> {
+        let ghost = match self.ghost {
+            Some(ref ghost) => ghost,
+            None => return Ok(()),
+        };
+
+        let Dimensions(_, y_off) = screen.global_offset();
+        let Dimensions(spacing, _) = screen.cell_spacing();
+        let Dimensions(cell_width, cell_height) = screen.cell_size();
+        let Dimensions(_, lines) = screen.dimensions();
+        let x0 = screen.window_size().0;
+        let stack_height = ghost.stack_height();
+
+        renderer.try_set_draw_color(Color::RGB(127, 127, 127))?;
+        renderer.try_draw_rect(::sdl2::rect::Rect::new_unwrap(
+            x0 as i32,
+            y_off as i32,
+            cell_width as u32,
+            (cell_height * lines) as u32,
+            ))?;
+
+        if stack_height > 0 {
+            renderer.try_set_draw_color(if lines_cleared >= ghost.game.lines_cleared {
+                Color::RGB(0, 120, 0)
+            } else {
+                Color::RGB(120, 0, 0)
+            })?;
+            renderer.try_fill_rect(::sdl2::rect::Rect::new_unwrap(
+                (x0 + spacing) as i32,
+                (y_off + cell_height * (lines - stack_height)) as i32,
+                (cell_width - spacing * 2) as u32,
+                (cell_height * stack_height) as u32,
+                ))?;
+        }
+        Ok(())
+    }
+
+    pub fn extra_width(&self, screen: &TetrisCellScreen) -> usize {
+        screen.cell_size().0 + screen.global_offset().0
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use std::env;
+    use std::fs;
+
+    use super::*;
+
+    fn load_text(name: &str, text: &str) -> io::Result<Replay> {
+        let path = env::temp_dir().join(format!("tetris-replay-test-{}-{}", name, std::process::id()));
+        fs::write(&path, text)?;
+        let loaded = Replay::load(&path);
+        let _ = fs::remove_file(&path);
+        loaded
+    }
+
+    #[test]
+    fn older_replay_versions_are_rejected() {
+        // Version 1 files predate the figure weights and have three parameters.
+        let err = load_text("v1", "tetris-replay 1\n42 10 20\n").err().expect("old replay loaded");
+        assert!(err.to_string().contains("another version"), "{}", err);
+    }
+
+    #[test]
+    fn overflowing_weights_are_rejected() {
+        let params = "42 10 20 0 4294967295 1 1 1 1 1 1 0 0 0 0 0 0";
+        let err = load_text("weights", &format!("{}\n{}\n", REPLAY_HEADER, params))
+            .err().expect("overflowing weights loaded");
+        assert!(err.to_string().contains("figure weights"), "{}", err);
+
+        let params = "42 10 20 0 2 1 1 1 1 1 1 0 0 0 0 0 0";
+        let replay = load_text("ok", &format!("{}\n{}\n", REPLAY_HEADER, params)).unwrap();
+        assert_eq!(replay.randomizer.weights, [2, 1, 1, 1, 1, 1, 1]);
+    }
+}
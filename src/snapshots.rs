@@ -0,0 +1,163 @@
+//! Scripted games compared against the boards stored in `tests/snapshots`.
+//!
+//! Run the tests with `TETRIS_UPDATE_SNAPSHOTS=1` to rewrite the stored boards after an
+//! intentional change, then review the diff.
+
+use std::env;
+use std::fmt::Write;
+use std::fs;
+use std::path::PathBuf;
+
+use super::{CellScreen, Figure, FIGURE_KINDS, GameConfig, GameInputEvent, Point, TetrisCellColor,
+            TetrisGame, seeded_rng};
+
+
+const UPDATE_VAR: &str = "TETRIS_UPDATE_SNAPSHOTS";
+
+
+struct Scenario {
+    name: &'static str,
+    seed: u32,
+    /// Locked rows at the bottom of the board, `X` for a garbage cell and `.` for a hole.
+    board: &'static [&'static str],
+    /// Space-separated steps: `left`, `right`, `rotate`, `rotate180`, `down`, `timer`, `hold`,
+    /// `undo`, `drop` for a hard drop with lock, and `kind:rotation@column` to replace the falling
+    /// figure.
+    script: &'static str,
+}
+
+
+fn force(game: &mut TetrisGame<::rand::XorShiftRng>, step: &str) {
+    let (figure, column) = step.split_at(step.find('@').expect("no column"));
+    let (kind, rotation) = figure.split_at(figure.find(':').expect("no rotation"));
+    let kind = *FIGURE_KINDS.iter().find(|k| k.name() == kind).expect("unknown figure kind");
+    let figure = Figure::new(kind, rotation[1 ..].parse().unwrap());
+    assert!(game.force_figure(figure, column[1 ..].parse().unwrap()), "{} does not fit", step);
+}
+
+
+fn play(scenario: &Scenario) -> String {
+    let config = GameConfig { columns: 10, lines: 10, .. GameConfig::default() };
+    let mut game = TetrisGame::new(seeded_rng(scenario.seed), config);
+    let offset = 10 - scenario.board.len();
+    for (y, row) in scenario.board.iter().enumerate() {
+        for (x, cell) in row.chars().enumerate() {
+            if cell == 'X' {
+                game.cell_screen.set_cell(Point(x, offset + y), Some(TetrisCellColor::Gray));
+            }
+        }
+    }
+
+    let mut result = Ok(());
+    for step in scenario.script.split_whitespace() {
+        result = match step {
+            "left" => game.handle_event(GameInputEvent::MoveLeft),
+            "right" => game.handle_event(GameInputEvent::MoveRight),
+            "rotate" => game.handle_event(GameInputEvent::RotateClockwise),
+            "rotate180" => game.handle_event(GameInputEvent::Rotate180),
+            "down" => game.handle_event(GameInputEvent::SoftDrop),
+            "timer" => game.handle_event(GameInputEvent::Timer),
+            "hold" => game.handle_event(GameInputEvent::Hold),
+            "undo" => game.handle_event(GameInputEvent::Undo),
+            "drop" => {
+                while game.figure_can_move_down() {
+                    game.handle_event(GameInputEvent::SoftDrop).unwrap();
+                }
+                game.handle_event(GameInputEvent::SoftDrop)
+            },
+            _ => {
+                force(&mut game, step);
+                Ok(())
+            },
+        };
+        if result.is_err() {
+            break;
+        }
+    }
+
+    let mut dump = String::new();
+    writeln!(dump, "lines cleared: {}", game.lines_cleared).unwrap();
+    match result {
+        Ok(()) => writeln!(dump, "result: ok").unwrap(),
+        Err(e) => writeln!(dump, "result: {:?}", e).unwrap(),
+    }
+    match game.cell_screen.get_figure() {
+        Some((point, _, figure)) => writeln!(dump, "figure: {}:{} at {},{}",
+                                             figure.kind.name(), figure.rotation, point.0, point.1),
+        None => writeln!(dump, "figure: none"),
+    }.unwrap();
+    dump.push_str(&game.cell_screen.to_compact_string());
+    dump
+}
+
+
+fn check(scenario: &Scenario) {
+    let path = PathBuf::from(env!("CARGO_MANIFEST_DIR"))
+        .join("tests").join("snapshots").join(format!("{}.txt", scenario.name));
+    let actual = play(scenario);
+    if env::var_os(UPDATE_VAR).is_some() {
+        fs::create_dir_all(path.parent().unwrap()).unwrap();
+        fs::write(&path, &actual).unwrap();
+        return;
+    }
+    let expected = fs::read_to_string(&path).unwrap_or_else(|e| {
+        panic!("Can not read {}: {}; run with {}=1 to create it", path.display(), e, UPDATE_VAR)
+    });
+    assert!(actual == expected, "{} does not match {}; run with {}=1 to update it\n{}",
+            scenario.name, path.display(), UPDATE_VAR, actual);
+}
+
+
+#[test]
+fn single_line_clear() {
+    check(&Scenario {
+        name: "single_line_clear",
+        seed: 1,
+        board: &["XXXXXX...."],
+        script: "line:0@6 drop",
+    });
+}
+
+
+#[test]
+fn tetris() {
+    check(&Scenario {
+        name: "tetris",
+        seed: 2,
+        board: &["XXX...XXXX", "XXXXX.XXXX", "XXXXX.XXXX", "XXXXX.XXXX", "XXXXX.XXXX"],
+        script: "line:1@5 drop",
+    });
+}
+
+
+#[test]
+fn stacking_to_game_over() {
+    check(&Scenario {
+        name: "stacking_to_game_over",
+        seed: 3,
+        board: &[],
+        script: "drop drop drop drop drop drop drop drop drop drop drop drop drop drop drop drop",
+    });
+}
+
+
+#[test]
+fn rotation_against_the_wall() {
+    check(&Scenario {
+        name: "rotation_against_the_wall",
+        seed: 4,
+        board: &[],
+        script: "line:1@9 rotate down down drop",
+    });
+}
+
+
+#[test]
+fn hard_drop_into_a_partial_well() {
+    check(&Scenario {
+        name: "hard_drop_into_a_partial_well",
+        seed: 5,
+        board: &["XX.XXXXXXX", "XX.XXXXX.X", "XX.XX..X.."],
+        script: "right_l:0@3 left drop",
+    });
+}
@@ -0,0 +1,156 @@
+//! Random events fed to headless games, checking the board invariants after every one.
+
+use rand::{Rng, XorShiftRng};
+
+use super::{FIGURE_KINDS, GameConfig, GameInputEvent, GamePhase, TetrisGame, seeded_rng};
+
+
+const SOAK_EVENTS: [GameInputEvent; 10] = [
+    GameInputEvent::RotateClockwise,
+    GameInputEvent::Rotate180,
+    GameInputEvent::MoveLeft,
+    GameInputEvent::MoveLeft,
+    GameInputEvent::MoveRight,
+    GameInputEvent::MoveRight,
+    GameInputEvent::SoftDrop,
+    GameInputEvent::Hold,
+    GameInputEvent::Timer,
+    GameInputEvent::Timer,
+    ];
+
+
+/// Applies a random event, forced figure or placement to the game. Returns what was done and
+/// whether the game goes on, or an error for a broken rule that the board does not show.
+fn random_action(rng: &mut XorShiftRng, game: &mut TetrisGame<XorShiftRng>)
+                 -> Result<(String, bool), String> {
+    let choice = rng.gen_range(0, SOAK_EVENTS.len() + 2);
+    if choice == SOAK_EVENTS.len() + 1 {
+        let kind = rng.gen_range(0, FIGURE_KINDS.len());
+        let figure = FIGURE_KINDS[kind].rotations()[0].clone();
+        let col = rng.gen_range(0, game.config.columns);
+        let figures_generated = game.figures_generated;
+        let action = format!("forced {} at column {}", FIGURE_KINDS[kind].name(), col);
+        let running = game.force_figure(figure, col);
+        if running && game.figures_generated != figures_generated {
+            return Err(format!("{}: figure counter changed", action));
+        }
+        Ok((action, running))
+    } else if choice == SOAK_EVENTS.len() {
+        let col = rng.gen_range(0, game.config.columns + 1);
+        let rotation = rng.gen_range(0, 4);
+        let running = game.apply_placement(col, rotation) || game.phase != GamePhase::GameOver;
+        let action = format!("placement at column {} rotated {} times", col, rotation);
+        if ! running && game.game_over_error.is_none() {
+            return Err(format!("{}: game over without an error", action));
+        }
+        Ok((action, running))
+    } else {
+        let event = SOAK_EVENTS[choice];
+        Ok((format!("{:?}", event), game.handle_event(event).is_ok()))
+    }
+}
+
+
+fn run(seed: u32, events: usize, config: &GameConfig) {
+    let mut rng = seeded_rng(seed);
+    let mut games = 1;
+    let mut game = TetrisGame::new(seeded_rng(seed), config.clone());
+
+    for step in 0 .. events {
+        let locked_cells_before = game.cell_screen.locked_cell_count();
+        let (action, running) = random_action(&mut rng, &mut game).unwrap_or_else(|e| {
+            panic!("Soak seed {}, game {}, event #{} {}", seed, games, step, e)
+        });
+
+        if let Err(e) = game.check_invariants(locked_cells_before) {
+            panic!("Soak seed {}, game {}, event #{} {}: {}\n{}",
+                   seed, games, step, action, e, game.cell_screen.to_compact_string());
+        }
+
+        if ! running {
+            game = TetrisGame::new(seeded_rng(seed.wrapping_add(games)), config.clone());
+            games += 1;
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use boarddump;
+
+    use super::*;
+    use super::super::{TetrisCellColor, TetrisCellScreen};
+
+    #[test]
+    fn soak_keeps_the_invariants() {
+        run(1, 20_000, &GameConfig::default());
+    }
+
+    #[test]
+    fn random_boards_survive_every_round_trip() {
+        let mut rng = seeded_rng(2);
+        let mut game = TetrisGame::new(seeded_rng(2), GameConfig::default());
+        for step in 0 .. 5_000 {
+            let (action, running) = random_action(&mut rng, &mut game).unwrap();
+            let (point, _, figure) = match game.cell_screen.get_figure() {
+                Some(current) if running => current,
+                _ => {
+                    game = TetrisGame::new(seeded_rng(step), GameConfig::default());
+                    continue;
+                },
+            };
+
+            let board = &game.cell_screen;
+            let bitboard = board.cells_as_bitboard();
+            let restored = TetrisCellScreen::from_bitboard(&bitboard, board.dim, TetrisCellColor::Gray)
+                .unwrap();
+            assert!(restored.bitboard_eq(&bitboard)
+                    && restored.locked_cell_count() == board.locked_cell_count(),
+                    "event #{} {}: bitboard round trip changed the board to\n{}",
+                    step, action, restored.to_compact_string());
+
+            let state = game.export_board_state();
+            let dumped = boarddump::dump(board);
+            let parsed = boarddump::parse(&dumped).ok();
+            assert!(parsed.is_some_and(|dump| {
+                        dump.board == state && dump.figure == Some((figure.clone(), point))
+                    }),
+                    "event #{} {}: board dump does not parse back\n{}", step, action, dumped);
+
+            game.import_board_state(&state).unwrap();
+            assert!(game.export_board_state() == state,
+                    "event #{} {}: board changed after a round trip\n{}",
+                    step, action, game.cell_screen.to_compact_string());
+            assert!(game.force_figure(figure, point.0));
+        }
+    }
+
+    #[test]
+    fn undo_restores_the_game_before_every_event() {
+        let config = GameConfig { undo_enabled: true, .. GameConfig::default() };
+        let mut rng = seeded_rng(3);
+        let mut game = TetrisGame::new(seeded_rng(3), config.clone());
+        for step in 0 .. 5_000 {
+            let event = SOAK_EVENTS[rng.gen_range(0, SOAK_EVENTS.len())];
+            if event != GameInputEvent::Timer {
+                // A soft drop row after the first one undoes together with the rows before it.
+                let state = if event == GameInputEvent::SoftDrop
+                    && game.soft_drop_figure == Some(game.figures_generated) {
+                    game.history.back().cloned()
+                } else {
+                    Some(game.snapshot())
+                };
+                let _ = game.handle_event(event);
+                assert!(game.undo(), "event #{} {:?}: nothing to undo", step, event);
+                assert!(Some(game.snapshot()) == state,
+                        "event #{} {:?}: undo did not restore the game\n{}",
+                        step, event, game.cell_screen.to_compact_string());
+            }
+
+            if ! random_action(&mut rng, &mut game).unwrap().1 {
+                game = TetrisGame::new(seeded_rng(step), config.clone());
+            }
+        }
+    }
+}
@@ -0,0 +1,182 @@
+use std::io::{self, Write};
+use std::net::{TcpListener, TcpStream};
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError, SyncSender};
+use std::thread;
+use std::time::Duration;
+
+use super::{CellScreen, Dimensions, Point, TetrisCellScreen};
+
+
+/// The decoder of the example client, to check frames against it.
+#[cfg(test)]
+#[allow(dead_code)]
+#[path = "../examples/spectator.rs"]
+mod spectator;
+
+
+const NO_FIGURE: u8 = 0xff;
+const ACCEPT_INTERVAL_MS: u64 = 100;
+
+
+/// Encodes the board as one frame of the spectator protocol.
+///
+/// A frame is a big-endian `u32` payload length followed by the payload: `u16` columns, `u16`
+/// lines, `u32` lines cleared, then the falling figure as a `u8` kind index and `u16` column and
+/// row, or `0xff` and four zero bytes without a figure. The game keeps no score, so spectators get
+/// the lines cleared in its place. The rest of the payload is the board in row-major order as
+/// `(count, cell)` byte pairs, where a cell is 0 when empty and a color index plus one otherwise.
+pub fn snapshot(screen: &TetrisCellScreen, lines_cleared: usize) -> Vec<u8> {
+    let Dimensions(columns, lines) = screen.dimensions();
+    let mut payload = Vec::new();
+    payload.extend_from_slice(&(columns as u16).to_be_bytes());
+    payload.extend_from_slice(&(lines as u16).to_be_bytes());
+    payload.extend_from_slice(&(lines_cleared as u32).to_be_bytes());
+    match screen.get_figure() {
+        Some((Point(x, y), _, figure)) => {
+            payload.push(figure.kind_index() as u8);
+            payload.extend_from_slice(&(x as u16).to_be_bytes());
+            payload.extend_from_slice(&(y as u16).to_be_bytes());
+        },
+        None => payload.extend_from_slice(&[NO_FIGURE, 0, 0, 0, 0]),
+    }
+
+    let cells = screen.composed_cells();
+    let mut cell_iter = cells.iter()
+        .map(|cell| cell.map_or(0, |color| color.to_index() + 1))
+        .peekable();
+    while let Some(value) = cell_iter.next() {
+        let mut run = 1u8;
+        while run < u8::MAX && cell_iter.peek() == Some(&value) {
+            cell_iter.next();
+            run += 1;
+        }
+        payload.push(run);
+        payload.push(value);
+    }
+
+    let mut frame = (payload.len() as u32).to_be_bytes().to_vec();
+    frame.extend_from_slice(&payload);
+    frame
+}
+
+
+struct Client {
+    stream: TcpStream,
+    pending: Vec<u8>,
+    /// Number of the last frame queued for this client.
+    sent_frame: u64,
+}
+
+
+impl Client {
+    fn flush(&mut self) -> io::Result<()> {
+        while ! self.pending.is_empty() {
+            match self.stream.write(&self.pending) {
+                Ok(0) => return Err(io::Error::new(io::ErrorKind::WriteZero, "client went away")),
+                Ok(written) => { self.pending.drain(.. written); },
+                Err(ref e) if e.kind() == io::ErrorKind::WouldBlock => return Ok(()),
+                Err(e) => return Err(e),
+            }
+        }
+        Ok(())
+    }
+}
+
+
+fn broadcast(listener: TcpListener, frames: Receiver<Vec<u8>>) {
+    let mut clients: Vec<Client> = Vec::new();
+    let mut last_frame = Vec::new();
+    let mut frame_number = 0;
+    loop {
+        match frames.recv_timeout(Duration::from_millis(ACCEPT_INTERVAL_MS)) {
+            Ok(frame) => {
+                if frame != last_frame {
+                    last_frame = frame;
+                    frame_number += 1;
+                }
+            },
+            Err(RecvTimeoutError::Timeout) => {},
+            Err(RecvTimeoutError::Disconnected) => return,
+        }
+
+        while let Ok((stream, _)) = listener.accept() {
+            if stream.set_nonblocking(true).is_ok() {
+                clients.push(Client { stream, pending: last_frame.clone(), sent_frame: frame_number });
+            }
+        }
+
+        let mut index = 0;
+        while index < clients.len() {
+            let client = &mut clients[index];
+            // A laggard skips the frames sent while it was busy but always gets the latest one.
+            if client.pending.is_empty() && client.sent_frame != frame_number {
+                client.pending.extend_from_slice(&last_frame);
+                client.sent_frame = frame_number;
+            }
+            if client.flush().is_ok() {
+                index += 1;
+            } else {
+                clients.swap_remove(index);
+            }
+        }
+    }
+}
+
+
+#[derive(Clone)]
+pub struct Spectators {
+    frames: SyncSender<Vec<u8>>,
+}
+
+
+impl Spectators {
+    /// Listens on the loopback interface only, unless `public` is set.
+    pub fn listen(port: u16, public: bool) -> io::Result<Self> {
+        let host = if public { "0.0.0.0" } else { "127.0.0.1" };
+        let listener = TcpListener::bind((host, port))?;
+        listener.set_nonblocking(true)?;
+        let (sender, receiver) = mpsc::sync_channel(1);
+        thread::spawn(move || broadcast(listener, receiver));
+        Ok(Spectators { frames: sender })
+    }
+
+    /// Hands the current frame to the broadcast thread, which passes it on if the board changed.
+    /// The frame is dropped if the thread is still busy with the previous one.
+    pub fn send(&self, frame: Vec<u8>) {
+        let _ = self.frames.try_send(frame);
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use super::super::{FIGURE_KINDS, TetrisCellColor};
+    use super::spectator;
+
+    #[test]
+    fn snapshot_decodes_in_the_example_client() {
+        let mut screen = TetrisCellScreen::new(10, 40, 10);
+        for x in 0 .. 9 {
+            screen.set_cell(Point(x, 39), Some(TetrisCellColor::Gray));
+        }
+        screen.set_cell(Point(4, 38), Some(TetrisCellColor::Red));
+        let figure = FIGURE_KINDS[2].rotations()[1].clone();
+        screen.set_figure(Point(3, 5), TetrisCellColor::Blue, figure.clone());
+
+        let frame = snapshot(&screen, 70_000);
+        let length = u32::from_be_bytes([frame[0], frame[1], frame[2], frame[3]]) as usize;
+        assert_eq!(length, frame.len() - 4);
+        let decoded = spectator::decode(&frame[4 ..]).expect("the client rejected the frame");
+        assert_eq!((decoded.columns, decoded.lines, decoded.lines_cleared), (10, 40, 70_000));
+        assert_eq!(decoded.figure, Some((figure.kind_index() as u8, 3, 5)));
+        let cells: Vec<_> = screen.composed_cells().iter()
+            .map(|cell| cell.map_or(0, |color| color.to_index() + 1))
+            .collect();
+        assert_eq!(decoded.cells, cells);
+
+        screen.clear_figure();
+        let decoded = spectator::decode(&snapshot(&screen, 0)[4 ..]).unwrap();
+        assert_eq!(decoded.figure, None);
+    }
+}
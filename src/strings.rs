@@ -0,0 +1,185 @@
+use std::env;
+use std::fmt::Display;
+
+
+#[derive(Debug)]
+struct Language {
+    code: &'static str,
+    table: &'static [(&'static str, &'static str)],
+    /// Keys that are meant to show the English text; only the table test reads this.
+    #[cfg_attr(not(test), allow(dead_code))]
+    fallback: &'static [&'static str],
+}
+
+
+const ENGLISH: [(&str, &str); 40] = [
+    ("quit_confirm", "QUIT? Y/N"),
+    ("go", "GO!"),
+    ("game_over", "GAME OVER"),
+    ("lines", "{} LINES"),
+    ("pieces_time", "{} PIECES {}"),
+    ("soft_drop_instant", "SOFT DROP: INSTANT"),
+    ("soft_drop_factor", "SOFT DROP: {}X"),
+    ("level", "LEVEL {}"),
+    ("i_drought", "I-DROUGHT: {}"),
+    ("drought", "{} DROUGHT: {}"),
+    ("garbage", "GARBAGE: {}"),
+    ("next", "NEXT: {}"),
+    ("lines_count", "LINES: {}"),
+    ("pause", "PAUSE"),
+    ("restart", "RESTART"),
+    ("quit", "QUIT"),
+    ("press_enter", "PRESS ENTER"),
+    ("player", "PLAYER {}"),
+    ("player_lines", "P{}: {} LINES"),
+    ("player_wins", "PLAYER {} WINS"),
+    ("draw", "DRAW"),
+    ("mode_endless", "ENDLESS"),
+    ("mode_sprint", "SPRINT"),
+    ("mode_ultra", "ULTRA"),
+    ("mode_invisible", "INVISIBLE"),
+    ("mode_cheese", "CHEESE"),
+    ("mode_hot_seat", "HOT SEAT"),
+    ("mode_zen", "ZEN"),
+    ("figure_cube", "CUBE"),
+    ("figure_line", "LINE"),
+    ("figure_left_l", "LEFT L"),
+    ("figure_right_l", "RIGHT L"),
+    ("figure_left_zigzag", "LEFT ZIGZAG"),
+    ("figure_right_zigzag", "RIGHT ZIGZAG"),
+    ("figure_pyramid", "PYRAMID"),
+    ("achievement_first_tetris", "FIRST TETRIS"),
+    ("achievement_hundred_lines", "100 LINES"),
+    ("achievement_level_ten", "LEVEL 10"),
+    ("achievement_perfect_clear", "PERFECT CLEAR"),
+    ("achievement_endless_survivor", "5 MINUTES"),
+];
+
+
+const SPANISH: [(&str, &str); 35] = [
+    ("quit_confirm", "SALIR? Y/N"),
+    ("go", "YA!"),
+    ("game_over", "FIN DEL JUEGO"),
+    ("lines", "{} LINEAS"),
+    ("pieces_time", "{} PIEZAS {}"),
+    ("soft_drop_instant", "BAJADA: INSTANTANEA"),
+    ("soft_drop_factor", "BAJADA: {}X"),
+    ("level", "NIVEL {}"),
+    ("i_drought", "SEQUIA DE I: {}"),
+    ("drought", "SEQUIA DE {}: {}"),
+    ("garbage", "BASURA: {}"),
+    ("next", "SIGUIENTE: {}"),
+    ("lines_count", "LINEAS: {}"),
+    ("pause", "PAUSA"),
+    ("restart", "NUEVA"),
+    ("quit", "SALIR"),
+    ("press_enter", "PULSA ENTER"),
+    ("player", "JUGADOR {}"),
+    ("player_lines", "J{}: {} LINEAS"),
+    ("player_wins", "GANA EL JUGADOR {}"),
+    ("draw", "EMPATE"),
+    ("mode_endless", "SIN FIN"),
+    ("mode_hot_seat", "POR TURNOS"),
+    ("figure_cube", "CUBO"),
+    ("figure_line", "LINEA"),
+    ("figure_left_l", "L IZQUIERDA"),
+    ("figure_right_l", "L DERECHA"),
+    ("figure_left_zigzag", "ZIGZAG IZQUIERDO"),
+    ("figure_right_zigzag", "ZIGZAG DERECHO"),
+    ("figure_pyramid", "PIRAMIDE"),
+    ("achievement_first_tetris", "PRIMER TETRIS"),
+    ("achievement_hundred_lines", "100 LINEAS"),
+    ("achievement_level_ten", "NIVEL 10"),
+    ("achievement_perfect_clear", "TABLERO LIMPIO"),
+    ("achievement_endless_survivor", "5 MINUTOS"),
+];
+
+
+const SPANISH_FALLBACK: [&str; 5] = [
+    "mode_sprint",
+    "mode_ultra",
+    "mode_invisible",
+    "mode_cheese",
+    "mode_zen",
+];
+
+
+static LANGUAGES: [Language; 2] = [
+    Language { code: "en", table: &ENGLISH, fallback: &[] },
+    Language { code: "es", table: &SPANISH, fallback: &SPANISH_FALLBACK },
+];
+
+
+#[derive(Clone, Copy, Debug)]
+pub struct Strings {
+    language: &'static Language,
+}
+
+
+impl Strings {
+    pub fn for_code(code: &str) -> Option<Self> {
+        let code = code.split(['_', '.', '-']).next().unwrap_or("");
+        LANGUAGES.iter()
+            .find(|language| language.code.eq_ignore_ascii_case(code))
+            .map(|language| Strings { language })
+    }
+
+    pub fn from_env() -> Self {
+        env::var("LANG").ok()
+            .and_then(|lang| Strings::for_code(&lang))
+            .unwrap_or_default()
+    }
+
+    pub fn get(&self, key: &str) -> &'static str {
+        let find = |table: &'static [(&'static str, &'static str)]| {
+            table.iter().find(|&&(k, _)| k == key).map(|&(_, text)| text)
+        };
+        find(self.language.table)
+            .or_else(|| find(&ENGLISH))
+            .unwrap_or_else(|| panic!("No string for {}", key))
+    }
+
+    pub fn fill(&self, key: &str, args: &[&dyn Display]) -> String {
+        let mut result = String::new();
+        for (i, piece) in self.get(key).split("{}").enumerate() {
+            if i > 0 {
+                result.push_str(&args[i - 1].to_string());
+            }
+            result.push_str(piece);
+        }
+        result
+    }
+}
+
+
+impl Default for Strings {
+    fn default() -> Self {
+        Strings { language: &LANGUAGES[0] }
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn translations_match_the_english_table() {
+        for language in LANGUAGES.iter().skip(1) {
+            for &(key, _) in ENGLISH.iter() {
+                let translated = language.table.iter().any(|&(k, _)| k == key);
+                assert!(translated || language.fallback.contains(&key),
+                        "{} has no string for {} and does not list it as fallback", language.code, key);
+            }
+            for &(key, text) in language.table.iter() {
+                let english = ENGLISH.iter().find(|&&(k, _)| k == key).map(|&(_, english)| english);
+                let english = english.unwrap_or_else(|| {
+                    panic!("{} has a string for {}, which English does not have", language.code, key)
+                });
+                assert_eq!(english.matches("{}").count(), text.matches("{}").count(),
+                           "{} string for {} does not take the same arguments as English",
+                           language.code, key);
+            }
+        }
+    }
+}
@@ -0,0 +1,89 @@
+const REPORT_PERIOD_NS: u64 = 1_000_000_000;
+
+
+pub struct LoopTimings {
+    period_started_ns: u64,
+    iterations: u64,
+    render_ns: u64,
+    wait_ns: u64,
+    logic_ns: u64,
+}
+
+
+fn average_ms(total_ns: u64, iterations: u64) -> f64 {
+    total_ns as f64 / iterations as f64 / 1_000_000.0
+}
+
+
+impl LoopTimings {
+    pub fn new(now_ns: u64) -> Self {
+        LoopTimings {
+            period_started_ns: now_ns,
+            iterations: 0,
+            render_ns: 0,
+            wait_ns: 0,
+            logic_ns: 0,
+        }
+    }
+
+    pub fn record(&mut self, render_ns: u64, wait_ns: u64, logic_ns: u64) {
+        self.iterations += 1;
+        self.render_ns += render_ns;
+        self.wait_ns += wait_ns;
+        self.logic_ns += logic_ns;
+    }
+
+    pub fn summary(&self, now_ns: u64) -> Option<String> {
+        let elapsed_ns = now_ns - self.period_started_ns;
+        if elapsed_ns < REPORT_PERIOD_NS || self.iterations == 0 {
+            return None;
+        }
+        Some(format!(
+            "{:.1} loops/s, render {:.2} ms, logic {:.2} ms, wait {:.2} ms",
+            self.iterations as f64 * 1e9 / elapsed_ns as f64,
+            average_ms(self.render_ns, self.iterations),
+            average_ms(self.logic_ns, self.iterations),
+            average_ms(self.wait_ns, self.iterations)))
+    }
+
+    pub fn report_if_due(&mut self, now_ns: u64) {
+        if let Some(summary) = self.summary(now_ns) {
+            println!("{}", summary);
+            *self = LoopTimings::new(now_ns);
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn a_frame_longer_than_several_periods_is_reported_once() {
+        let mut timings = LoopTimings::new(0);
+        timings.record(3_000_000_000, 400_000_000, 100_000_000);
+        let now_ns = 3 * REPORT_PERIOD_NS + 500_000_000;
+        assert_eq!(timings.summary(now_ns).unwrap(),
+                   "0.3 loops/s, render 3000.00 ms, logic 100.00 ms, wait 400.00 ms");
+
+        timings.report_if_due(now_ns);
+        assert_eq!(timings.summary(now_ns + REPORT_PERIOD_NS), None);
+    }
+
+    #[test]
+    fn the_next_period_starts_at_the_report() {
+        let mut timings = LoopTimings::new(0);
+        timings.record(2_000_000, 10_000_000, 1_000_000);
+        assert_eq!(timings.summary(REPORT_PERIOD_NS - 1), None);
+        let reported_ns = REPORT_PERIOD_NS + 250_000_000;
+        timings.report_if_due(reported_ns);
+
+        // A report closes its period when it is printed, and only later frames count in the next.
+        timings.record(4_000_000, 0, 2_000_000);
+        timings.record(6_000_000, 0, 2_000_000);
+        assert_eq!(timings.summary(reported_ns + REPORT_PERIOD_NS - 1), None);
+        assert_eq!(timings.summary(reported_ns + REPORT_PERIOD_NS).unwrap(),
+                   "2.0 loops/s, render 5.00 ms, logic 2.00 ms, wait 0.00 ms");
+    }
+}
@@ -0,0 +1,145 @@
+use std::cmp::{min, max};
+
+use sdl2::EventPump;
+use sdl2::event::Event;
+use sdl2::keyboard::Keycode;
+use sdl2::pixels::Color;
+use sdl2::render::Renderer;
+
+use draw::TryDraw;
+use font;
+use strings::Strings;
+use super::{CellScreenRenderer, ColorScheme, GAME_MODES, GameMode, TetrisCellColor};
+
+
+const LOGO: &str = "TETRIS";
+
+
+fn render(renderer: &mut Renderer, window_size: (u32, u32), strings: &Strings, selected: GameMode)
+          -> Result<(), String> {
+    let (width, height) = (window_size.0 as usize, window_size.1 as usize);
+
+    renderer.try_set_draw_color(Color::RGB(0, 0, 0))?;
+    renderer.try_clear()?;
+
+    let cell_size = max(width / (font::text_width(LOGO, 1) + 2), 1);
+    let logo_x = (width - font::text_width(LOGO, cell_size)) / 2;
+    let logo_y = height / 8;
+    let colors = ColorScheme::default();
+    for (i, letter) in LOGO.chars().enumerate() {
+        let color = TetrisCellColor::from_index(i as u8).unwrap();
+        font::draw_text(renderer, &letter.to_string(),
+                        logo_x + i * font::char_advance(cell_size),
+                        logo_y, cell_size, color.get_sdl_color(&colors))?;
+    }
+
+    let pixel_size = max(width / 60, 1);
+    let line_height = font::text_height(pixel_size) * 2;
+    let menu_y = logo_y + font::text_height(cell_size) + line_height * 2;
+    let menu_width = GAME_MODES.iter()
+        .map(|mode| font::text_width(&format!("> {}", mode.name(strings)), pixel_size))
+        .max().unwrap();
+    let menu_x = (width - menu_width) / 2;
+    for (i, &mode) in GAME_MODES.iter().enumerate() {
+        let label = if mode == selected {
+            format!("> {}", mode.name(strings))
+        } else {
+            format!("  {}", mode.name(strings))
+        };
+        font::draw_text(renderer, &label, menu_x,
+                        menu_y + i * line_height, pixel_size,
+                        if mode == selected {
+                            Color::RGB(255, 255, 255)
+                        } else {
+                            Color::RGB(127, 127, 127)
+                        })?;
+    }
+
+    render_hint(renderer, window_size, strings, pixel_size)?;
+    renderer.present();
+    Ok(())
+}
+
+
+fn render_hint(renderer: &mut Renderer, window_size: (u32, u32), strings: &Strings,
+               pixel_size: usize) -> Result<(), String> {
+    let (width, height) = (window_size.0 as usize, window_size.1 as usize);
+    let hint = strings.get("press_enter");
+    font::draw_text(renderer, hint,
+                    (width - font::text_width(hint, pixel_size)) / 2,
+                    height - font::text_height(pixel_size) * 4, pixel_size,
+                    Color::RGB(160, 160, 160))
+}
+
+
+fn render_message(renderer: &mut Renderer, window_size: (u32, u32), strings: &Strings,
+                  lines: &[String], background: Option<&dyn CellScreenRenderer>)
+                  -> Result<(), String> {
+    let (width, height) = (window_size.0 as usize, window_size.1 as usize);
+
+    match background {
+        Some(background) => background.render_cell_screen(renderer)?,
+        None => {
+            renderer.try_set_draw_color(Color::RGB(0, 0, 0))?;
+            renderer.try_clear()?;
+        },
+    }
+
+    let longest = lines.iter().map(|line| font::text_width(line, 1)).max().unwrap_or(1);
+    let pixel_size = max(min(width / (longest + 2), width / 30), 1);
+    let line_height = font::text_height(pixel_size) * 2;
+    let top = if background.is_some() { height / 12 } else { height / 3 };
+    for (i, line) in lines.iter().enumerate() {
+        font::draw_text(renderer, line,
+                        (width - font::text_width(line, pixel_size)) / 2,
+                        top + i * line_height, pixel_size, Color::RGB(255, 255, 255))?;
+    }
+
+    render_hint(renderer, window_size, strings, max(width / 60, 1))?;
+    renderer.present();
+    Ok(())
+}
+
+
+pub fn prompt(event_pump: &mut EventPump, renderer: &mut Renderer, window_size: (u32, u32),
+              strings: &Strings, lines: &[String], background: Option<&dyn CellScreenRenderer>)
+              -> bool {
+    loop {
+        if let Err(e) = render_message(renderer, window_size, strings, lines, background) {
+            println!("Can not draw the message: {}", e);
+        }
+        match event_pump.wait_event() {
+            Event::Quit {..} => return false,
+            Event::KeyDown {keycode: Some(kc), ..} => match kc {
+                Keycode::Escape => return false,
+                Keycode::Return | Keycode::Space => return true,
+                _ => {},
+            },
+            _ => {},
+        }
+    }
+}
+
+
+pub fn run(event_pump: &mut EventPump, renderer: &mut Renderer, window_size: (u32, u32),
+           strings: &Strings, selected: GameMode) -> Option<GameMode> {
+    let mut selected_index = GAME_MODES.iter().position(|&mode| mode == selected).unwrap();
+    loop {
+        if let Err(e) = render(renderer, window_size, strings, GAME_MODES[selected_index]) {
+            println!("Can not draw the title screen: {}", e);
+        }
+        match event_pump.wait_event() {
+            Event::Quit {..} => return None,
+            Event::KeyDown {keycode: Some(kc), ..} => match kc {
+                Keycode::Escape | Keycode::Q => return None,
+                Keycode::Return | Keycode::Space => return Some(GAME_MODES[selected_index]),
+                Keycode::Up => {
+                    selected_index = (selected_index + GAME_MODES.len() - 1) % GAME_MODES.len();
+                },
+                Keycode::Down => selected_index = (selected_index + 1) % GAME_MODES.len(),
+                _ => {},
+            },
+            _ => {},
+        }
+    }
+}
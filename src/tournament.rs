@@ -0,0 +1,106 @@
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use time;
+
+use ai;
+use super::{GameConfig, TetrisGame, seeded_rng};
+
+
+const MAX_FIGURES_PER_GAME: usize = 10_000;
+
+
+pub struct GameResult {
+    pub policy: &'static str,
+    pub seed: u32,
+    pub figures: usize,
+    pub lines: usize,
+    pub duration_ms: u64,
+    pub state_hash: u64,
+}
+
+
+pub fn parse_policies(spec: &str) -> Result<Vec<&'static str>, String> {
+    spec.split(',').map(|name| {
+        ai::POLICY_NAMES.iter().find(|&&known| known == name.trim()).cloned()
+            .ok_or_else(|| format!("unknown policy {}, expected one of {}",
+                                   name, ai::POLICY_NAMES.join(", ")))
+    }).collect()
+}
+
+
+fn play(policy: &'static str, seed: u32, config: &GameConfig) -> GameResult {
+    let started_ns = time::precise_time_ns();
+    let mut game = TetrisGame::new(seeded_rng(seed), config.clone());
+    let mut player = ai::policy(policy, &game.cell_screen, seeded_rng(seed))
+        .expect("policy names are checked by parse_policies");
+    let mut figures = 0;
+    for _ in 0 .. MAX_FIGURES_PER_GAME {
+        let running = match player.best_placement(&game.cell_screen) {
+            Some((col, rotation)) => game.apply_placement(col, rotation),
+            None => false,
+        };
+        figures += 1;
+        if ! running {
+            break;
+        }
+    }
+    GameResult {
+        policy,
+        seed,
+        figures,
+        lines: game.lines_cleared,
+        duration_ms: (time::precise_time_ns() - started_ns) / 1_000_000,
+        state_hash: game.state_hash,
+    }
+}
+
+
+fn export_csv(path: &str, results: &[GameResult]) -> io::Result<()> {
+    let mut out = BufWriter::new(File::create(path)?);
+    writeln!(out, "policy,seed,figures,lines,duration_ms,state_hash")?;
+    for result in results {
+        writeln!(out, "{},{},{},{},{},{:016x}",
+                 result.policy, result.seed, result.figures, result.lines, result.duration_ms,
+                 result.state_hash)?;
+    }
+    out.flush()
+}
+
+
+fn print_table(policies: &[&'static str], results: &[GameResult]) {
+    println!("{:<10} {:>6} {:>10} {:>6} {:>6} {:>12} {:>8}  outcome hash",
+             "policy", "games", "avg lines", "min", "max", "avg figures", "avg ms");
+    for &policy in policies {
+        let games: Vec<_> = results.iter().filter(|result| result.policy == policy).collect();
+        let count = games.len().max(1) as f64;
+        let lines = games.iter().map(|result| result.lines);
+        let outcome_hash = games.iter().fold(0u64, |hash, result| {
+            hash.rotate_left(5) ^ result.state_hash
+        });
+        println!("{:<10} {:>6} {:>10.1} {:>6} {:>6} {:>12.1} {:>8.1}  {:016x}",
+                 policy, games.len(),
+                 games.iter().map(|result| result.lines).sum::<usize>() as f64 / count,
+                 lines.clone().min().unwrap_or(0), lines.max().unwrap_or(0),
+                 games.iter().map(|result| result.figures).sum::<usize>() as f64 / count,
+                 games.iter().map(|result| result.duration_ms).sum::<u64>() as f64 / count,
+                 outcome_hash);
+    }
+}
+
+
+pub fn run(seed: u32, games: u32, policies: &[&'static str], csv_path: &str, config: &GameConfig) {
+    let mut results = Vec::new();
+    for &policy in policies {
+        for game_no in 0 .. games {
+            results.push(play(policy, seed.wrapping_add(game_no), config));
+        }
+    }
+
+    print_table(policies, &results);
+
+    if ! csv_path.is_empty() {
+        if let Err(e) = export_csv(csv_path, &results) {
+            println!("Can not save {}: {}", csv_path, e);
+        }
+    }
+}
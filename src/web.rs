@@ -0,0 +1,246 @@
+//! The browser build. `cargo build --release --no-default-features --features web --target
+//! wasm32-unknown-unknown` makes a `tetris.wasm` without SDL that `examples/web` loads: the page
+//! maps keys to the input bits of `tick`, calls it every animation frame and paints `cells` onto a
+//! canvas.
+
+use std::cell::{Cell, RefCell};
+
+use rand::XorShiftRng;
+
+use super::{CellScreen, GameConfig, GameInputEvent, GamePhase, GameTimer,
+            TetrisCellColor, TetrisGame, seeded_rng};
+
+
+/// The events of the bits of `input_bits`, lowest first. A bit is set while its key is held.
+const INPUT_EVENTS: [GameInputEvent; 6] = [
+    GameInputEvent::MoveLeft,
+    GameInputEvent::MoveRight,
+    GameInputEvent::RotateClockwise,
+    GameInputEvent::Rotate180,
+    GameInputEvent::SoftDrop,
+    GameInputEvent::Hold,
+];
+
+
+thread_local! {
+    static CLOCK_MS: Cell<u64> = const { Cell::new(0) };
+    static GAME: RefCell<Option<WebGame>> = const { RefCell::new(None) };
+}
+
+
+pub fn clock_ms() -> u64 {
+    CLOCK_MS.with(|clock| clock.get())
+}
+
+
+fn held(input_bits: u32, event: GameInputEvent) -> bool {
+    INPUT_EVENTS.iter().position(|&bit_event| bit_event == event)
+        .is_some_and(|bit| input_bits >> bit & 1 == 1)
+}
+
+
+fn move_direction(input_bits: u32) -> Option<GameInputEvent> {
+    match (held(input_bits, GameInputEvent::MoveLeft), held(input_bits, GameInputEvent::MoveRight)) {
+        (true, false) => Some(GameInputEvent::MoveLeft),
+        (false, true) => Some(GameInputEvent::MoveRight),
+        _ => None,
+    }
+}
+
+
+/// A game driven by the page instead of the SDL event loop, with the same timer for gravity,
+/// lock delay and auto-repeat.
+pub struct WebGame {
+    game: TetrisGame<XorShiftRng>,
+    timer: GameTimer,
+    time_ms: u64,
+    input_bits: u32,
+    figure_when_move_charged: usize,
+    figure_when_soft_drop_pressed: Option<usize>,
+    cells: Vec<u8>,
+    palette: Vec<u8>,
+}
+
+
+impl WebGame {
+    pub fn new(seed: u32) -> Self {
+        let game = TetrisGame::new(seeded_rng(seed), GameConfig::default());
+        let mut timer = GameTimer::new(game.gravity_rows_per_second, game.config.soft_drop_factor);
+        timer.reset_drop(0);
+        let palette = (0u8 ..).map_while(TetrisCellColor::from_index)
+            .flat_map(|color| {
+                let (r, g, b) = color.to_rgb(&game.config.colors);
+                vec![r, g, b]
+            })
+            .collect();
+        WebGame {
+            figure_when_move_charged: game.figures_generated,
+            game,
+            timer,
+            time_ms: 0,
+            input_bits: 0,
+            figure_when_soft_drop_pressed: None,
+            cells: Vec::new(),
+            palette,
+        }
+    }
+
+    /// Advances the game by `dt_ms` with the keys of `input_bits` held, and returns whether the
+    /// game goes on. Rotations and hold happen once per press, moves repeat while held.
+    pub fn tick(&mut self, input_bits: u32, dt_ms: u32) -> bool {
+        if self.game.phase == GamePhase::GameOver {
+            return false;
+        }
+        self.time_ms += dt_ms as u64;
+        let now_ms = self.time_ms;
+        CLOCK_MS.with(|clock| clock.set(now_ms));
+
+        let pressed = input_bits & ! self.input_bits;
+        let direction = move_direction(input_bits);
+        if direction != move_direction(self.input_bits) {
+            self.timer.restart_move();
+        }
+        self.input_bits = input_bits;
+
+        if self.figure_when_move_charged != self.game.figures_generated {
+            self.figure_when_move_charged = self.game.figures_generated;
+            self.timer.figure_spawned(now_ms, direction.is_some(), self.game.config.preserve_das);
+        }
+        if self.timer.should_move(now_ms, direction.is_some()) {
+            if let Some(event) = direction {
+                self.game.dispatch_event(event, now_ms);
+            }
+        }
+        for &event in &[GameInputEvent::RotateClockwise, GameInputEvent::Rotate180,
+                        GameInputEvent::Hold] {
+            if held(pressed, event) {
+                self.game.dispatch_event(event, now_ms);
+            }
+        }
+        self.game.spawn_modifiers.rotate = held(input_bits, GameInputEvent::RotateClockwise);
+
+        let soft_dropping = if held(input_bits, GameInputEvent::SoftDrop) {
+            let figure = *self.figure_when_soft_drop_pressed.get_or_insert(self.game.figures_generated);
+            figure == self.game.figures_generated
+        } else {
+            self.figure_when_soft_drop_pressed = None;
+            false
+        };
+        self.game.gravity_tick(&mut self.timer, soft_dropping, now_ms, now_ms);
+        self.game.phase != GamePhase::GameOver
+    }
+
+    /// The board with the falling figure in row-major order, one byte per cell: 0 when empty and
+    /// a color index plus one otherwise.
+    pub fn cells(&mut self) -> &[u8] {
+        let cells = &mut self.cells;
+        self.game.cell_screen.with_composed_cells(&mut |composed| {
+            cells.clear();
+            cells.extend(composed.iter().map(|cell| cell.map_or(0, |color| color.to_index() + 1)));
+        });
+        &self.cells
+    }
+}
+
+
+fn with_game<T, F: FnOnce(&mut WebGame) -> T>(f: F) -> T {
+    GAME.with(|game| f(game.borrow_mut().as_mut().expect("start() was not called")))
+}
+
+
+/// Starts a new game, dropping the current one.
+#[no_mangle]
+pub extern "C" fn start(seed: u32) {
+    GAME.with(|game| *game.borrow_mut() = Some(WebGame::new(seed)));
+}
+
+
+/// Returns 1 while the game goes on and 0 after it is over.
+#[no_mangle]
+pub extern "C" fn tick(input_bits: u32, dt_ms: u32) -> u32 {
+    with_game(|game| game.tick(input_bits, dt_ms) as u32)
+}
+
+
+/// Points to `columns() * lines()` cells, valid until the next call into the module.
+#[no_mangle]
+pub extern "C" fn cells() -> *const u8 {
+    with_game(|game| game.cells().as_ptr())
+}
+
+
+/// Points to the red, green and blue bytes of every color index.
+#[no_mangle]
+pub extern "C" fn palette() -> *const u8 {
+    with_game(|game| game.palette.as_ptr())
+}
+
+
+#[no_mangle]
+pub extern "C" fn columns() -> u32 {
+    with_game(|game| game.game.cell_screen.dimensions().0 as u32)
+}
+
+
+#[no_mangle]
+pub extern "C" fn lines() -> u32 {
+    with_game(|game| game.game.cell_screen.dimensions().1 as u32)
+}
+
+
+#[no_mangle]
+pub extern "C" fn lines_cleared() -> u32 {
+    with_game(|game| game.game.lines_cleared as u32)
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use super::super::{Dimensions, Point};
+
+
+    fn figure_point(game: &WebGame) -> Point {
+        game.game.cell_screen.get_figure().expect("no falling figure").0
+    }
+
+    #[test]
+    fn gravity_follows_the_ticks() {
+        let mut game = WebGame::new(1);
+        let Point(_, y) = figure_point(&game);
+        assert!(game.tick(0, 1));
+        assert_eq!(figure_point(&game).1, y);
+        assert!(game.tick(0, 1000));
+        assert!(figure_point(&game).1 > y, "no gravity after a second");
+        assert_eq!(clock_ms(), 1001);
+    }
+
+    #[test]
+    fn input_bits_move_and_rotate_the_figure() {
+        let mut game = WebGame::new(1);
+        let Point(x, y) = figure_point(&game);
+        assert!(game.tick(0b1, 1));
+        assert_eq!(figure_point(&game), Point(x - 1, y));
+
+        let rotation = |game: &WebGame| game.game.cell_screen.get_figure().unwrap().2.rotation;
+        let before = rotation(&game);
+        game.tick(0b100, 1);
+        let after = rotation(&game);
+        game.tick(0b100, 1);
+        let kind = game.game.cell_screen.get_figure().unwrap().2.kind;
+        assert!(before != after || kind.rotation_count() == 1, "a pressed rotation did nothing");
+        assert_eq!(rotation(&game), after, "a held rotation repeated");
+    }
+
+    #[test]
+    fn cells_show_the_falling_figure() {
+        let mut game = WebGame::new(1);
+        let Dimensions(columns, lines) = game.game.cell_screen.dimensions();
+        let color = game.game.cell_screen.get_figure().unwrap().1;
+        let cells = game.cells();
+        assert_eq!(cells.len(), columns * lines);
+        assert_eq!(cells.iter().filter(|&&cell| cell == color.to_index() + 1).count(), 4);
+        assert!(cells.iter().all(|&cell| cell == 0 || cell == color.to_index() + 1));
+        assert_eq!(game.palette.len(), 3 * 8);
+    }
+}